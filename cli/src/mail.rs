@@ -10,7 +10,13 @@ use tokio_util::sync::CancellationToken;
 
 use micron::{
     auth::{hash_password, validate_password},
-    email::list::Subscriber,
+    config::ListVisibility,
+    email::{
+        campaign::{self, Campaign, ContentFormat},
+        list::Subscriber,
+        queue::{self, QueueStatus, QueuedEmail},
+        suppression, tracking,
+    },
     Config, Database, User,
 };
 use uuid::Uuid;
@@ -25,23 +31,48 @@ pub fn cmd(config: &Config) -> clap::Command {
             clap::Command::new("send")
                 .arg_required_else_help(true)
                 .about("Send email to subscribers")
-                .arg(Arg::new("file").long("file").value_name("PATH"))
+                .arg(
+                    Arg::new("file")
+                        .long("file")
+                        .value_name("PATH")
+                        .help("Markdown or html file with the message content"),
+                )
+                .arg(
+                    Arg::new("subject")
+                        .long("subject")
+                        .short('s')
+                        .value_name("SUBJECT"),
+                )
                 .arg(
                     Arg::new("lists")
                         .long("lists")
-                        .help("Mailing lists to send to")
+                        .help("Mailing lists to send to, all lists if not provided")
                         .required(false)
                         .value_name("LISTS")
+                        .num_args(1..)
                         .value_parser(
                             config
                                 .mailing
                                 .lists
                                 .iter()
-                                .map(|list| PossibleValue::new(list))
+                                .map(|list| PossibleValue::new(&list.name))
                                 .collect::<Vec<PossibleValue>>(),
                         ),
+                )
+                .arg(
+                    Arg::new("resume")
+                        .long("resume")
+                        .value_name("ID")
+                        .conflicts_with_all(["file", "subject", "lists"])
+                        .help("Resume an interrupted campaign"),
                 ),
         )
+        .subcommand(clap::Command::new("campaigns").about("List sent campaigns"))
+        .subcommand(
+            clap::Command::new("stats")
+                .about("Show campaign open and click stats")
+                .arg(arg!([id] "Campaign id, all campaigns if not provided")),
+        )
         .subcommand(
             clap::Command::new("list")
                 .subcommand_required(false)
@@ -56,7 +87,7 @@ pub fn cmd(config: &Config) -> clap::Command {
                     Arg::new("email")
                         .long("email")
                         .short('e')
-                        .help("User email"),
+                        .help("Add the address to the selected list"),
                 )
                 .arg(
                     Arg::new("is_admin")
@@ -66,6 +97,48 @@ pub fn cmd(config: &Config) -> clap::Command {
                         .action(ArgAction::Set),
                 ),
         )
+        .subcommand(
+            clap::Command::new("queue")
+                .subcommand_required(false)
+                .about("Show outbound email queue status")
+                .arg(
+                    Arg::new("all")
+                        .long("all")
+                        .num_args(0)
+                        .help("Include already sent messages"),
+                )
+                .subcommand(
+                    clap::Command::new("retry")
+                        .about("Puts a dead message back into the queue")
+                        .arg(arg!(<id> "Queued message id")),
+                )
+                .subcommand(clap::Command::new("purge").about("Removes all dead messages")),
+        )
+        .subcommand(
+            clap::Command::new("dkim").about("Print the DNS record with the DKIM public key"),
+        )
+        .subcommand(
+            clap::Command::new("suppression")
+                .subcommand_required(false)
+                .about("Manage addresses that no emails are sent to")
+                .subcommand(
+                    clap::Command::new("add")
+                        .about("Suppresses the address")
+                        .arg(arg!(<email> "Email address")),
+                )
+                .subcommand(
+                    clap::Command::new("rm")
+                        .about("Takes the address off the suppression list")
+                        .arg(arg!(<email> "Email address")),
+                )
+                .subcommand(
+                    clap::Command::new("mailbox")
+                        .about("Processes delivery status notifications from a maildir")
+                        .arg(
+                            arg!([path] "Maildir path, defaults to the configured bounce mailbox"),
+                        ),
+                ),
+        )
 }
 
 pub async fn run(
@@ -77,28 +150,228 @@ pub async fn run(
     let db = Database::new()?;
 
     match matches.subcommand() {
-        Some(("list", m)) => {
-            match m.get_one::<String>("name") {
-                Some(name) => {
+        Some(("send", m)) => {
+            let mut campaign = match m.get_one::<String>("resume") {
+                Some(id) => db.get::<Campaign>(Uuid::from_str(id)?)?,
+                None => {
+                    let Some(path) = m.get_one::<String>("file") else {
+                        anyhow::bail!("either --file or --resume is required");
+                    };
+                    let Some(subject) = m.get_one::<String>("subject").cloned() else {
+                        anyhow::bail!("missing campaign --subject");
+                    };
+                    let lists = match m.get_many::<String>("lists") {
+                        Some(lists) => lists.cloned().collect(),
+                        None => config.mailing.list_names(),
+                    };
+
+                    let content = std::fs::read_to_string(path)?;
+                    let format = ContentFormat::from_path(path);
+                    let campaign = Campaign::new(subject, content, format, lists);
+                    db.set(&campaign)?;
+                    campaign
+                }
+            };
+
+            campaign::send(&mut campaign, config, &db)?;
+            println!(
+                "Campaign {} queued for {} of {} recipients, {} failed, {} skipped",
+                campaign.id,
+                campaign.queued.len(),
+                campaign.recipients.len(),
+                campaign.failed.len(),
+                campaign.skipped.len(),
+            );
+            println!(
+                "Messages are delivered by the application email queue, retry failed ones with `micron mail send --resume {}`",
+                campaign.id
+            );
+        }
+        Some(("campaigns", _)) => {
+            let mut campaigns = db.get_collection::<Campaign>()?;
+            campaigns.sort_by_key(|c| c.created_at);
+            for mut c in campaigns {
+                campaign::update_progress(&mut c, &db)?;
+                println!(
+                    "{} | {} | {} | lists: {} | queued: {} | sent: {}/{} | failed: {} | created: {}",
+                    c.id,
+                    c.status,
+                    c.subject,
+                    c.lists.iter().cloned().collect::<Vec<_>>().join(", "),
+                    c.queued.len(),
+                    c.sent.len(),
+                    c.recipients.len(),
+                    c.failed.len(),
+                    c.created_at,
+                );
+            }
+        }
+        Some(("stats", m)) => {
+            let campaigns = match m.get_one::<String>("id") {
+                Some(id) => vec![db.get::<Campaign>(Uuid::from_str(id)?)?],
+                None => {
+                    let mut campaigns = db.get_collection::<Campaign>()?;
+                    campaigns.sort_by_key(|c| c.created_at);
+                    campaigns
+                }
+            };
+            for mut c in campaigns {
+                campaign::update_progress(&mut c, &db)?;
+                let stats = tracking::stats(&c, &db)?;
+                println!(
+                    "{} | {} | sent: {} | opens: {} ({} unique) | clicks: {} ({} unique)",
+                    stats.campaign,
+                    stats.subject,
+                    stats.sent,
+                    stats.opens,
+                    stats.unique_opens,
+                    stats.clicks,
+                    stats.unique_clicks,
+                );
+                for (url, clicks) in stats.links {
+                    println!("    {clicks} | {url}");
+                }
+            }
+        }
+        Some(("list", m)) => match m.get_one::<String>("name") {
+            Some(name) => {
+                if config.mailing.list(name).is_none() {
+                    anyhow::bail!("unknown mailing list: {name}");
+                }
+
+                // `mail list <name> --email <email>` adds the address to the
+                // list, which is the only way to populate internal lists
+                if let Some(email) = m.get_one::<String>("email") {
+                    let user = micron::util::find_user_by_email(&db, email).ok();
+                    let mut subscriber = db
+                        .get_collection::<Subscriber>()?
+                        .into_iter()
+                        .find(|s| micron::util::same_email(&s.address, email))
+                        .unwrap_or_else(|| Subscriber::new(email.to_string()));
+                    subscriber.user = subscriber.user.or(user.map(|u| u.id));
+                    subscriber.confirmed = true;
+                    subscriber.marketing_consent = true;
+                    subscriber.lists.insert(name.to_string());
+                    db.set(&subscriber)?;
+                    println!("Added {email} to mailing list `{name}`");
+                } else {
                     let mut subs = db.get_collection::<Subscriber>()?;
                     subs.retain(|s| s.lists.contains(name));
                     println!("Mailing list `{}` subscribers: {:?}", name, subs);
                 }
+            }
+            None => {
+                // `mail list` gets a list of mailing lists
+                println!("Found mailing lists:");
+                for list in &config.mailing.lists {
+                    let visibility = match list.visibility {
+                        ListVisibility::Public => "public",
+                        ListVisibility::Internal => "internal",
+                    };
+                    println!("{} ({visibility}) {}", list.name, list.description);
+                }
+            }
+        },
+        Some(("queue", m)) => match m.subcommand() {
+            Some(("retry", m)) => {
+                let id = Uuid::from_str(m.get_one::<String>("id").unwrap())?;
+                queue::retry(id, &db)?;
+                println!("Message {id} queued for retry");
+            }
+            Some(("purge", _)) => {
+                let count = queue::purge(&db)?;
+                println!("Removed {count} dead messages");
+            }
+            _ => {
+                let mut emails = db.get_collection::<QueuedEmail>()?;
+                emails.sort_by_key(|e| e.created_at);
 
-                None => {
-                    // `mail list` gets a list of mailing lists
+                let count = |status| emails.iter().filter(|e| e.status == status).count();
+                println!(
+                    "pending: {}, sent: {}, dead: {}",
+                    count(QueueStatus::Pending),
+                    count(QueueStatus::Sent),
+                    count(QueueStatus::Dead),
+                );
 
-                    print!("Found mailing lists: ");
-                    for (n, list) in config.mailing.lists.iter().enumerate() {
-                        print!("{list}");
-                        if n < config.mailing.lists.len() - 1 {
-                            print!(", ");
-                        }
+                for email in &emails {
+                    if email.status == QueueStatus::Sent && !m.get_flag("all") {
+                        continue;
+                    }
+                    let next = match email.status {
+                        QueueStatus::Pending => format!("next attempt: {}", email.next_attempt),
+                        QueueStatus::Sent => format!(
+                            "sent: {}",
+                            email.sent_at.map(|t| t.to_string()).unwrap_or_default()
+                        ),
+                        QueueStatus::Dead => "no more attempts".to_string(),
+                    };
+                    println!(
+                        "{} | {} | to: {} | subject: {} | attempts: {} | {}",
+                        email.id,
+                        email.status,
+                        email.to.join(", "),
+                        email.subject,
+                        email.attempts,
+                        next,
+                    );
+                    if let Some(error) = &email.last_error {
+                        println!("    last error: {error}");
                     }
-                    print!("\n");
                 }
             }
+        },
+        Some(("dkim", _)) => {
+            let (name, value) = micron::email::dkim::dns_record(config)?;
+            println!("Publish the following TXT record:\n");
+            println!("{name}\n");
+            println!("{value}\n");
+            // zone files and some dns providers expect values longer than
+            // 255 characters to be split into multiple strings
+            let chunks = value
+                .as_bytes()
+                .chunks(255)
+                .map(|c| format!("\"{}\"", String::from_utf8_lossy(c)))
+                .collect::<Vec<_>>();
+            println!("Zone file format:\n");
+            println!("{name}. IN TXT ( {} )", chunks.join(" "));
         }
+        Some(("suppression", m)) => match m.subcommand() {
+            Some(("add", m)) => {
+                let email = m.get_one::<String>("email").unwrap();
+                suppression::suppress(email, suppression::Reason::Manual, "cli", None, &db)?;
+                println!("Suppressed {email}");
+            }
+            Some(("rm", m)) => {
+                let email = m.get_one::<String>("email").unwrap();
+                suppression::remove(email, &db)?;
+                println!("Removed {email} from the suppression list");
+            }
+            Some(("mailbox", m)) => {
+                let Some(path) =
+                    m.get_one::<String>("path")
+                        .or(config.email.bounces.mailbox.as_ref())
+                else {
+                    anyhow::bail!("no maildir path provided or configured");
+                };
+                let count = suppression::process_mailbox(path, &db)?;
+                println!("Suppressed {count} addresses");
+            }
+            _ => {
+                let mut entries = db.get_collection::<suppression::Suppression>()?;
+                entries.sort_by_key(|e| e.created_at);
+                for e in entries {
+                    println!(
+                        "{} | {} | source: {} | {} | {}",
+                        e.address,
+                        e.reason,
+                        e.source,
+                        e.created_at,
+                        e.detail.unwrap_or_default(),
+                    );
+                }
+            }
+        },
         _ => unimplemented!(),
     }
 
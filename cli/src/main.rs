@@ -39,7 +39,7 @@ async fn main() -> anyhow::Result<()> {
     }
 
     match matches.subcommand() {
-        Some(("user", m)) => user::run(m, false, cancel.clone()).await?,
+        Some(("user", m)) => user::run(m, false, &config, cancel.clone()).await?,
         Some(("mail", m)) => mail::run(m, false, &config, cancel.clone()).await?,
         Some(("login", m)) => login::run(m, cancel.clone()).await?,
         Some(("export", m)) => export::run(m, cancel.clone()).await?,
@@ -74,7 +74,7 @@ pub fn cmd(config: &Config) -> Command {
             "Build web apps fast. Repeat.\n\
             Learn more at https://saasba.se/micron",
         )
-        .subcommand(user::cmd())
+        .subcommand(user::cmd(&config))
         .subcommand(mail::cmd(config))
         .subcommand(export::cmd())
         .subcommand(login::cmd())
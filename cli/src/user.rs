@@ -5,13 +5,18 @@ use clap::{arg, Arg, ArgAction, ArgMatches};
 use tokio_util::sync::CancellationToken;
 
 use micron::{
-    auth::{hash_password, validate_password},
+    auth::{hash_password_with, validate_password},
     db::Collectable,
-    Database, User,
+    user::data::ImportedUser,
+    Config, Database, User,
 };
 use uuid::Uuid;
 
-pub fn cmd() -> clap::Command {
+pub fn cmd(config: &Config) -> clap::Command {
+    // Provide available roles as possible values if they're known
+    let mut roles = vec![micron::user::role::ADMIN.to_string()];
+    roles.extend(config.roles.keys().cloned());
+
     clap::Command::new("user")
         .subcommand_required(true)
         // .arg_required_else_help(true)
@@ -72,12 +77,102 @@ pub fn cmd() -> clap::Command {
                 .arg(arg!(-e --email [email] "User email"))
                 .arg(Arg::new("id"))
                 .arg(Arg::new("subscription-plan").long("subscription-plan"))
+                .arg(
+                    Arg::new("new-email")
+                        .long("new-email")
+                        .help("Changes user email, the new address will need to be confirmed"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .requires("new-email")
+                        .action(ArgAction::SetTrue)
+                        .help("Changes user email right away, without sending a confirmation link"),
+                )
                 // .arg(arg!(-a --admin [is_admin] "User is administrator"))
                 // .arg(arg!(-d --disabled [is_disabled] "User is disabled"))
                 // .arg(arg!(--first_name [first_name] "User first name"))
                 // .arg(arg!(--last_name [last_name] "User last name"))
                 .arg(Arg::new("passwd_hash").long("passwd").short('p')),
         )
+        .subcommand(
+            clap::Command::new("grant")
+                .about("Grants role to the user")
+                .arg(arg!(<email> "User email"))
+                .arg(
+                    Arg::new("role")
+                        .required(true)
+                        .value_parser(clap::builder::PossibleValuesParser::new(roles.clone()))
+                        .help("Role to grant"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("revoke")
+                .about("Revokes role from the user")
+                .arg(arg!(<email> "User email"))
+                .arg(Arg::new("role").required(true).help("Role to revoke")),
+        )
+        .subcommand(
+            clap::Command::new("export")
+                .about("Exports all data tied to the user into a zip archive")
+                .arg(arg!(<email> "User email"))
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .value_name("PATH")
+                        .help("Output file path, defaults to `{user_id}.zip`"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("import")
+                .about("Imports users from another system")
+                .long_about(
+                    "Imports users from a CSV or JSON file. Records need an `email` \
+                    field and can carry `password_hash`, `name`, `handle` and \
+                    `email_confirmed`. Password hashes can be argon2, bcrypt, scrypt \
+                    or PBKDF2 (PHC or Django format), they're upgraded to argon2 on \
+                    the first successful login.",
+                )
+                .arg(arg!(<path> "Path to the CSV or JSON file"))
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .short('f')
+                        .value_parser(["csv", "json"])
+                        .help("File format, detected from file extension by default"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("invite")
+                .subcommand_required(true)
+                .about("Manage invite codes for invite-only registration")
+                .subcommand(
+                    clap::Command::new("add")
+                        .about("Generates a new invite code")
+                        .arg(arg!(-e --email [email] "Only allow signing up with this email"))
+                        .arg(
+                            Arg::new("uses")
+                                .long("uses")
+                                .short('u')
+                                .value_parser(clap::value_parser!(u32))
+                                .help("Maximum number of signups"),
+                        )
+                        .arg(
+                            Arg::new("expires")
+                                .long("expires")
+                                .value_parser(clap::value_parser!(u32))
+                                .value_name("DAYS")
+                                .help("Number of days the invite stays valid"),
+                        ),
+                )
+                .subcommand(clap::Command::new("ls").about("Lists existing invite codes"))
+                .subcommand(
+                    clap::Command::new("rm")
+                        .about("Revokes an invite code")
+                        .arg(arg!(<code> "Invite code")),
+                ),
+        )
         .subcommand(
             clap::Command::new("rm")
                 .about("Removes selected user(s)")
@@ -98,7 +193,12 @@ pub fn cmd() -> clap::Command {
         )
 }
 
-pub async fn run(sub_matches: &ArgMatches, remote: bool, cancel: CancellationToken) -> Result<()> {
+pub async fn run(
+    sub_matches: &ArgMatches,
+    remote: bool,
+    config: &Config,
+    cancel: CancellationToken,
+) -> Result<()> {
     let db = Some(Database::new()?);
     let user_command = sub_matches.subcommand().unwrap_or(("get", sub_matches));
     match user_command {
@@ -119,7 +219,7 @@ pub async fn run(sub_matches: &ArgMatches, remote: bool, cancel: CancellationTok
                 is_disabled: is_disabled.unwrap_or(false),
                 email,
                 email_confirmed: false,
-                password_hash: Some(hash_password(&passwd)?),
+                password_hash: Some(hash_password_with(&passwd, &config.auth.argon2)?),
                 name: name.unwrap_or("".to_string()),
                 handle: handle.unwrap_or("".to_string()),
                 plan: micron::user::Plan::free(),
@@ -133,7 +233,7 @@ pub async fn run(sub_matches: &ArgMatches, remote: bool, cancel: CancellationTok
             } else if let Some(db) = db {
                 db.set(&user)?;
             } else {
-                panic!("no access to application data")
+                anyhow::bail!("no access to application data")
             }
             println!("Added new user {:?} ", user);
         }
@@ -230,12 +330,192 @@ pub async fn run(sub_matches: &ArgMatches, remote: bool, cancel: CancellationTok
 
                 //     println!("response: {:?}", response);
                 // }
+            } else if let Some(db) = db {
+                let mut user = db
+                    .get_collection::<User>()?
+                    .into_iter()
+                    .find(|u| {
+                        Some(u.id) == id
+                            || email
+                                .as_ref()
+                                .is_some_and(|e| micron::util::same_email(&u.email, e))
+                    })
+                    .ok_or(anyhow::Error::msg(
+                        "no user matching provided email or id exists",
+                    ))?;
+
+                if let Some(new_email) = sub_matches.get_one::<String>("new-email") {
+                    if sub_matches.get_flag("force") {
+                        if db.get_collection::<User>()?.iter().any(|u| {
+                            u.id != user.id && micron::util::same_email(&u.email, new_email)
+                        }) {
+                            return Err(anyhow::Error::msg("user with that email already exists"));
+                        }
+                        user.email = new_email.to_owned();
+                        user.email_confirmed = false;
+                        micron::email::list::update_address(user.id, new_email, &db)?;
+                    } else {
+                        micron::auth::email_change::request(
+                            &user,
+                            new_email.to_owned(),
+                            config,
+                            &db,
+                        )?;
+                        println!("Sent confirmation link to {new_email}");
+                    }
+                }
+                if let Some(plan) = sub_matches.get_one::<String>("subscription-plan") {
+                    user.plan = config
+                        .plans
+                        .iter()
+                        .find(|p| &p.name == plan)
+                        .cloned()
+                        .ok_or(anyhow::Error::msg(format!(
+                            "unknown subscription plan: {plan}"
+                        )))?;
+                }
+                if let Some(passwd) = sub_matches.get_one::<String>("passwd_hash") {
+                    user.password_hash = Some(hash_password_with(passwd, &config.auth.argon2)?);
+                }
+
+                db.set(&user)?;
             } else {
-                unimplemented!()
+                anyhow::bail!("no access to application data")
             }
 
             println!("Changed user");
         }
+        ("grant", sub_matches) | ("revoke", sub_matches) => {
+            let grant = user_command.0 == "grant";
+            let email = sub_matches.get_one::<String>("email").cloned().unwrap();
+            let role = sub_matches.get_one::<String>("role").cloned().unwrap();
+
+            let Some(db) = db else {
+                anyhow::bail!("no access to application data")
+            };
+            let mut user = db
+                .get_collection::<User>()?
+                .into_iter()
+                .find(|u| micron::util::same_email(&u.email, &email))
+                .ok_or(anyhow::Error::msg("no users with that email exist"))?;
+
+            if grant {
+                user.grant_role(&role, config)?;
+            } else {
+                user.revoke_role(&role)?;
+            }
+            db.set(&user)?;
+
+            if grant {
+                println!("Granted role {role} to {email}");
+            } else {
+                println!("Revoked role {role} from {email}");
+            }
+        }
+        ("export", sub_matches) => {
+            let email = sub_matches.get_one::<String>("email").cloned().unwrap();
+
+            let Some(db) = db else {
+                anyhow::bail!("no access to application data")
+            };
+            let user = db
+                .get_collection::<User>()?
+                .into_iter()
+                .find(|u| micron::util::same_email(&u.email, &email))
+                .ok_or(anyhow::Error::msg("no users with that email exist"))?;
+
+            let path = sub_matches
+                .get_one::<String>("output")
+                .cloned()
+                .unwrap_or(format!("{}.zip", user.id));
+            std::fs::write(&path, micron::user::data::export(user.id, &db)?)?;
+
+            println!("Exported user data to {path}");
+        }
+        ("import", sub_matches) => {
+            let path = sub_matches.get_one::<String>("path").unwrap();
+            let format = sub_matches
+                .get_one::<String>("format")
+                .cloned()
+                .or(std::path::Path::new(path)
+                    .extension()
+                    .map(|e| e.to_string_lossy().to_lowercase()))
+                .ok_or(anyhow::Error::msg(
+                    "can't detect file format, provide --format",
+                ))?;
+
+            let records: Vec<ImportedUser> = match format.as_str() {
+                "csv" => csv::Reader::from_path(path)?
+                    .deserialize()
+                    .collect::<Result<_, _>>()?,
+                "json" => serde_json::from_slice(&std::fs::read(path)?)?,
+                _ => return Err(anyhow::Error::msg(format!("unsupported format: {format}"))),
+            };
+
+            let Some(db) = db else {
+                anyhow::bail!("no access to application data")
+            };
+            let (mut imported, mut skipped) = (0, 0);
+            for record in records {
+                let email = record.email.clone();
+                match micron::user::data::import(record, &db) {
+                    Ok(_) => imported += 1,
+                    Err(e) => {
+                        println!("Skipping {email}: {e}");
+                        skipped += 1;
+                    }
+                }
+            }
+
+            println!("Imported {imported} user(s), skipped {skipped}");
+        }
+        ("invite", sub_matches) => {
+            let Some(db) = db else {
+                anyhow::bail!("no access to application data")
+            };
+            match sub_matches.subcommand() {
+                Some(("add", m)) => {
+                    let invite = micron::auth::invite::create(
+                        m.get_one::<String>("email").cloned(),
+                        m.get_one::<u32>("uses").cloned(),
+                        m.get_one::<u32>("expires").cloned(),
+                        None,
+                        &db,
+                    )?;
+                    println!("{}", invite.code);
+                }
+                Some(("ls", _)) => {
+                    for invite in db.get_collection::<micron::auth::invite::Invite>()? {
+                        println!(
+                            "{} | email: {} | uses: {}/{} | expires: {}",
+                            invite.code,
+                            invite.email.as_deref().unwrap_or("any"),
+                            invite.uses,
+                            invite
+                                .max_uses
+                                .map(|m| m.to_string())
+                                .unwrap_or("unlimited".to_string()),
+                            invite
+                                .expires_at
+                                .map(|e| e.to_string())
+                                .unwrap_or("never".to_string()),
+                        );
+                    }
+                }
+                Some(("rm", m)) => {
+                    let code = m.get_one::<String>("code").unwrap();
+                    micron::auth::invite::revoke(code, &db)?;
+                    println!("Revoked invite {code}");
+                }
+                _ => {
+                    return Err(clap::Error::raw(
+                        clap::error::ErrorKind::InvalidSubcommand,
+                        "unknown invite subcommand\n",
+                    )
+                    .into())
+                }
+            }
+        }
         ("rm", sub_matches) => {
             let email = sub_matches.get_one::<String>("email").cloned();
             let handle = sub_matches.get_one::<String>("handle").cloned();
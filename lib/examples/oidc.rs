@@ -0,0 +1,73 @@
+//! Example showing how to use a generic OIDC provider, e.g. a self-hosted
+//! Keycloak or Authentik instance, without writing any provider-specific
+//! code.
+//!
+//! # Setup
+//!
+//! The example is configured to work with a local mock OIDC server:
+//!
+//! ```sh
+//! docker run -p 8080:8080 ghcr.io/navikt/mock-oauth2-server:2.1.0
+//! ```
+//!
+//! The mock server accepts any client credentials and lets you fill in the
+//! claims of the user logging in. Make sure to provide an `email` claim
+//! along with `"email_verified": true`.
+//!
+//! To use a real provider instead, replace `discovery_url` with the issuer
+//! url of your realm, e.g. `https://sso.example.com/realms/main`, set proper
+//! client credentials and remove `redirect_url`.
+
+use axum::{
+    response::{Html, IntoResponse, Response},
+    routing::get,
+};
+
+use micron::{config, Config};
+
+#[tokio::main]
+async fn main() {
+    let config = Config {
+        domain: "127.0.0.1:8000".to_string(),
+        registration: config::Registration {
+            enabled: true,
+            oauth: true,
+            ..Default::default()
+        },
+        oauth: config::Oauth {
+            enabled: true,
+            providers: vec![config::OauthProvider {
+                name: "mock".to_string(),
+                client_id: "micron".to_string(),
+                client_secret: "secret".to_string(),
+                discovery_url: Some("http://localhost:8080/default".to_string()),
+                // the default redirect url assumes https
+                redirect_url: Some("http://127.0.0.1:8000/auth/mock".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    // main application router
+    let mut router = micron::axum::Router::new().route("/", get(home));
+
+    // attach micron routes
+    router = micron::axum::router(router, &config);
+
+    // start the application
+    micron::axum::start(router, config).await.expect("failed")
+}
+
+async fn home(user: Option<micron::axum::extract::User>) -> Response {
+    if let Some(user) = user {
+        Html(format!(
+            "welcome {}! | <a href=\"/logout\">log out</a>",
+            user.email
+        ))
+        .into_response()
+    } else {
+        Html("landing page | <a href=\"/login/mock\">log in with mock provider</a>").into_response()
+    }
+}
@@ -59,10 +59,12 @@ async fn home(user: Option<micron::axum::extract::User>) -> Response {
 async fn login(
     cookies: PrivateCookieJar,
     Extension(db): micron::axum::DbExt,
+    Extension(config): micron::axum::ConfigExt,
 ) -> (PrivateCookieJar, Response) {
     (
         cookies.add(
-            micron::auth::login::log_in_user_id(&Uuid::nil(), &db).expect("failed logging user in"),
+            micron::auth::login::log_in_user_id(&Uuid::nil(), false, &config, &db)
+                .expect("failed logging user in"),
         ),
         Redirect::to("/").into_response(),
     )
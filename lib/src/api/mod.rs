@@ -1,8 +1,9 @@
+use std::collections::HashSet;
 use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
 
-// TODO make into set of enum variants for more granular control
 /// Defines the scope of access for resulting access token.
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub enum AuthScope {
@@ -10,10 +11,86 @@ pub enum AuthScope {
     Public,
     #[default]
     Complete,
+    /// Explicitly selected set of scopes
+    Custom(HashSet<Scope>),
+}
+
+impl AuthScope {
+    /// Expands the scope definition into a set of granular scopes.
+    pub fn scopes(&self) -> HashSet<Scope> {
+        match self {
+            Self::Public => HashSet::from([Scope::UserRead]),
+            Self::Complete => Scope::iter().filter(|s| *s != Scope::Admin).collect(),
+            Self::Custom(scopes) => scopes.clone(),
+        }
+    }
+}
+
+/// Granular permission that can be granted to an access token.
+///
+/// Session cookies are not restricted by scopes. Tokens used as bearer
+/// tokens are only allowed to perform actions covered by their scope set.
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    strum::Display,
+    strum::EnumString,
+    strum::EnumIter,
+)]
+pub enum Scope {
+    #[serde(rename = "user:read")]
+    #[strum(serialize = "user:read")]
+    UserRead,
+    #[serde(rename = "user:write")]
+    #[strum(serialize = "user:write")]
+    UserWrite,
+    #[serde(rename = "credits:read")]
+    #[strum(serialize = "credits:read")]
+    CreditsRead,
+    #[serde(rename = "credits:spend")]
+    #[strum(serialize = "credits:spend")]
+    CreditsSpend,
+    #[serde(rename = "orders:read")]
+    #[strum(serialize = "orders:read")]
+    OrdersRead,
+    #[serde(rename = "orders:write")]
+    #[strum(serialize = "orders:write")]
+    OrdersWrite,
+    #[serde(rename = "comments:read")]
+    #[strum(serialize = "comments:read")]
+    CommentsRead,
+    #[serde(rename = "comments:write")]
+    #[strum(serialize = "comments:write")]
+    CommentsWrite,
+    /// Administrative actions. Only issued to admins and never included in
+    /// the `Complete` scope, it needs to be requested explicitly.
+    #[serde(rename = "admin")]
+    #[strum(serialize = "admin")]
+    Admin,
+}
+
+impl Scope {
+    /// Returns true if the scope allows changing state.
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Self::UserWrite
+                | Self::CreditsSpend
+                | Self::OrdersWrite
+                | Self::CommentsWrite
+                | Self::Admin
+        )
+    }
 }
 
 /// Defines the length-of-life of resulting access token.
-#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum AuthDuration {
     /// 1 hour
     Short,
@@ -58,9 +135,20 @@ pub struct AuthRequest {
 ///
 /// Contains a newly generated access token that can be used with subsequent
 /// API requests (as bearer token).
+///
+/// The refresh token can be exchanged for a new token pair at
+/// `api/auth/refresh` before the access token expires from inactivity.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AuthResponse {
     pub token: String,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
+
+/// Refresh request to be sent to `api/auth/refresh` endpoint.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -0,0 +1,70 @@
+//! Changing the account email address.
+//!
+//! The new address needs to be confirmed before it replaces the current
+//! one. The current address is notified about the requested change so that
+//! the account owner can react if they didn't request it.
+
+use validator::ValidateEmail;
+
+use crate::auth::{ConfirmationKey, ConfirmationPurpose};
+use crate::error::{ErrorKind, Result};
+use crate::{util, Config, Database, User, UserId};
+
+/// Makes sure no other user is using the email address.
+fn ensure_available(email: &str, user: UserId, db: &Database) -> Result<()> {
+    if db
+        .get_collection::<User>()?
+        .iter()
+        .any(|u| u.id != user && util::same_email(&u.email, email))
+    {
+        return Err(ErrorKind::UserWithEmailAlreadyExists(email.to_string()).into());
+    }
+    Ok(())
+}
+
+/// Starts the email change, sending the confirmation link to the new
+/// address. Any previously requested changes are discarded.
+pub fn request(user: &User, new_email: String, config: &Config, db: &Database) -> Result<()> {
+    if !new_email.validate_email() {
+        return Err(ErrorKind::BadInput("invalid email".to_string()).into());
+    }
+    if new_email == user.email {
+        return Err(ErrorKind::BadInput("email is the same as the current one".to_string()).into());
+    }
+    ensure_available(&new_email, user.id, db)?;
+
+    for key in db.get_collection::<ConfirmationKey>()? {
+        if key.user == user.id && key.purpose == ConfirmationPurpose::EmailChange {
+            db.remove(&key)?;
+        }
+    }
+
+    let key = ConfirmationKey::email_change(user.id, new_email.clone());
+    db.set(&key)?;
+
+    crate::email::email_change(new_email.clone(), key.key.to_string(), config, db)?;
+    crate::email::email_change_notice(user.email.clone(), new_email, config, db)?;
+
+    Ok(())
+}
+
+/// Applies the email change stored with the confirmation key.
+pub fn confirm(key: &ConfirmationKey, db: &Database) -> Result<User> {
+    let new_email = key.email.clone().ok_or(ErrorKind::Other(
+        "email change key missing email".to_string(),
+    ))?;
+
+    // the address could have been taken in the meantime
+    ensure_available(&new_email, key.user, db)?;
+
+    let mut user: User = db.get(key.user)?;
+    user.email = new_email;
+    // confirmation status now refers to the new address, which was just
+    // confirmed by following the link
+    user.email_confirmed = true;
+    db.set(&user)?;
+
+    crate::email::list::update_address(user.id, &user.email, db)?;
+
+    Ok(user)
+}
@@ -0,0 +1,66 @@
+//! Admin impersonation, letting admins see the application exactly the way
+//! a given user does.
+//!
+//! Impersonation sessions use regular tokens issued for the target user,
+//! marked with the id of the impersonating admin. Starting and stopping
+//! impersonation is recorded in the target user's activities.
+
+use chrono::Utc;
+
+use crate::api::AuthDuration;
+use crate::error::{ErrorKind, Result};
+use crate::user::{UserActivity, UserActivityCategory};
+use crate::{Database, User, UserId};
+
+use super::TokenMeta;
+
+/// Issues an impersonation token for the target user.
+pub fn start(admin: &User, target: UserId, db: &Database) -> Result<TokenMeta> {
+    if !admin.is_admin {
+        return Err(ErrorKind::Forbidden.into());
+    }
+    if admin.id == target {
+        return Err(ErrorKind::BadInput("can't impersonate yourself".to_string()).into());
+    }
+
+    let mut user = db.get::<User>(target)?;
+    // don't allow escalating to other admins' accounts
+    if user.is_admin {
+        return Err(ErrorKind::Forbidden.into());
+    }
+
+    let mut token = TokenMeta::new(user.id);
+    token.duration = AuthDuration::Short;
+    token.context = "impersonation".to_string();
+    token.impersonator = Some(admin.id);
+    db.set(&token)?;
+
+    user.activities.list.push(UserActivity {
+        time: Utc::now(),
+        category: UserActivityCategory::Impersonation,
+        message: format!("Impersonation started by {} ({})", admin.email, admin.id),
+    });
+    db.set(&user)?;
+
+    Ok(token)
+}
+
+/// Ends the impersonation session, revoking the token.
+pub fn stop(token: &TokenMeta, db: &Database) -> Result<()> {
+    let Some(admin) = token.impersonator else {
+        return Err(ErrorKind::BadInput("not an impersonation session".to_string()).into());
+    };
+
+    db.remove(token)?;
+
+    if let Ok(mut user) = db.get::<User>(token.user_id) {
+        user.activities.list.push(UserActivity {
+            time: Utc::now(),
+            category: UserActivityCategory::Impersonation,
+            message: format!("Impersonation ended by {}", admin),
+        });
+        db.set(&user)?;
+    }
+
+    Ok(())
+}
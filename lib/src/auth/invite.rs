@@ -0,0 +1,144 @@
+//! Invite codes for invite-only registration.
+//!
+//! Invites are created by admins, either through the web endpoints or the
+//! CLI. An invite can be bound to a single email address, limited in the
+//! number of uses and given an expiry date.
+
+use chrono::{DateTime, Duration, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use uuid::Uuid;
+
+use crate::db::{Collectable, Identifiable};
+use crate::error::{ErrorKind, Result};
+use crate::{Database, UserId};
+
+/// Length of generated invite codes.
+const CODE_LENGTH: usize = 12;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Invite {
+    pub code: String,
+    /// Only this address can sign up using the invite, if set.
+    pub email: Option<String>,
+    /// Maximum number of signups, unlimited if not set.
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+    pub expires_at: Option<DateTime<Utc>>,
+
+    pub created_by: Option<UserId>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Collectable for Invite {
+    fn get_collection_name() -> &'static str {
+        "invites"
+    }
+}
+
+impl Identifiable for Invite {
+    fn get_id(&self) -> Uuid {
+        code_id(&self.code)
+    }
+}
+
+impl Invite {
+    pub fn new(email: Option<String>, max_uses: Option<u32>) -> Self {
+        let code = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(CODE_LENGTH)
+            .map(char::from)
+            .collect::<String>()
+            .to_uppercase();
+        Self {
+            code,
+            email,
+            max_uses,
+            uses: 0,
+            expires_at: None,
+            created_by: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Returns true if the invite can't be used anymore.
+    pub fn is_exhausted(&self) -> bool {
+        self.max_uses.is_some_and(|max| self.uses >= max)
+            || self.expires_at.is_some_and(|at| at <= Utc::now())
+    }
+}
+
+/// Invites are stored under ids derived from their codes so that they can
+/// be looked up directly.
+fn code_id(code: &str) -> Uuid {
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, code.trim().to_uppercase().as_bytes())
+}
+
+/// Creates and stores a new invite, optionally valid only for the given
+/// number of days.
+pub fn create(
+    email: Option<String>,
+    max_uses: Option<u32>,
+    valid_days: Option<u32>,
+    created_by: Option<UserId>,
+    db: &Database,
+) -> Result<Invite> {
+    let mut invite = Invite::new(email, max_uses);
+    invite.expires_at = valid_days.map(|days| Utc::now() + Duration::days(days as i64));
+    invite.created_by = created_by;
+    db.set(&invite)?;
+    Ok(invite)
+}
+
+/// Checks that the invite can be used to sign up with the email.
+pub fn check(code: &str, email: &str, db: &Database) -> Result<Invite> {
+    let invalid = || ErrorKind::BadInput("invalid invite code".to_string());
+
+    let invite = db.get::<Invite>(code_id(code)).map_err(|_| invalid())?;
+    if invite.is_exhausted() {
+        return Err(invalid().into());
+    }
+    if invite
+        .email
+        .as_ref()
+        .is_some_and(|e| !e.eq_ignore_ascii_case(email))
+    {
+        return Err(invalid().into());
+    }
+
+    Ok(invite)
+}
+
+/// Records a signup made with the invite.
+///
+/// The use count is updated atomically, so that concurrent signups can't
+/// use the invite more times than allowed.
+pub fn redeem(code: &str, email: &str, db: &Database) -> Result<()> {
+    check(code, email, db)?;
+    db.update::<Invite, _>(code_id(code), |mut invite| {
+        if invite.is_exhausted() {
+            return Err(ErrorKind::BadInput("invalid invite code".to_string()).into());
+        }
+        invite.uses += 1;
+        Ok(invite)
+    })?;
+    Ok(())
+}
+
+/// Gives back a use of the invite, in case the signup it was redeemed for
+/// failed.
+pub fn release(code: &str, db: &Database) -> Result<()> {
+    db.update::<Invite, _>(code_id(code), |mut invite| {
+        invite.uses = invite.uses.saturating_sub(1);
+        Ok(invite)
+    })?;
+    Ok(())
+}
+
+pub fn revoke(code: &str, db: &Database) -> Result<()> {
+    let invite = db
+        .get::<Invite>(code_id(code))
+        .map_err(|_| ErrorKind::BadInput("invalid invite code".to_string()))?;
+    db.remove(&invite)?;
+    Ok(())
+}
@@ -0,0 +1,183 @@
+//! Verification of password hashes imported from other systems.
+//!
+//! Supported formats:
+//!
+//! - bcrypt in modular crypt format, e.g. `$2b$12$...` (`$2a$`, `$2x$` and
+//!   `$2y$` variants included)
+//! - scrypt in PHC format, e.g. `$scrypt$ln=17,r=8,p=1$...`
+//! - PBKDF2 in PHC format, e.g. `$pbkdf2-sha256$i=600000,l=32$...`
+//! - PBKDF2 in Django format, e.g. `pbkdf2_sha256$600000$salt$hash`
+//!
+//! Legacy hashes are only ever verified, never created. They're replaced
+//! with argon2 hashes on the first successful login, see
+//! `auth::needs_rehash`.
+
+use argon2::password_hash::{PasswordHash, PasswordVerifier};
+use base64::Engine;
+use pbkdf2::Pbkdf2;
+use scrypt::Scrypt;
+use sha2::Sha256;
+
+use crate::error::{ErrorKind, Result};
+
+/// Password hash formats recognized on import.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum Format {
+    Argon2,
+    Bcrypt,
+    Scrypt,
+    Pbkdf2,
+    Django,
+}
+
+impl Format {
+    /// Detects the format of the hash string.
+    pub fn detect(hash: &str) -> Option<Self> {
+        if hash.starts_with("$argon2") {
+            Some(Self::Argon2)
+        } else if ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| hash.starts_with(prefix))
+        {
+            Some(Self::Bcrypt)
+        } else if hash.starts_with("$scrypt$") {
+            Some(Self::Scrypt)
+        } else if hash.starts_with("$pbkdf2") {
+            Some(Self::Pbkdf2)
+        } else if hash.starts_with("pbkdf2_sha256$") {
+            Some(Self::Django)
+        } else {
+            None
+        }
+    }
+}
+
+/// Verifies the password against a non-argon2 hash.
+pub fn verify(password: &[u8], hash: &str) -> Result<()> {
+    let format = Format::detect(hash).ok_or(ErrorKind::Other(
+        "unrecognized password hash format".to_string(),
+    ))?;
+
+    let matched = match format {
+        Format::Argon2 => {
+            return Err(ErrorKind::Other("not a legacy password hash".to_string()).into())
+        }
+        Format::Bcrypt => bcrypt::verify(password, hash).unwrap_or(false),
+        Format::Scrypt => Scrypt.verify_password(password, &parse(hash)?).is_ok(),
+        Format::Pbkdf2 => Pbkdf2.verify_password(password, &parse(hash)?).is_ok(),
+        Format::Django => verify_django(password, hash)?,
+    };
+
+    if matched {
+        Ok(())
+    } else {
+        Err(ErrorKind::InvalidCredentials.into())
+    }
+}
+
+fn parse(hash: &str) -> Result<PasswordHash> {
+    PasswordHash::new(hash).map_err(|_| {
+        ErrorKind::Other("Failed to parse hash in PHC string format.".to_string()).into()
+    })
+}
+
+/// Django stores `pbkdf2_sha256$<iterations>$<salt>$<base64 hash>`, with
+/// the salt used as is.
+fn verify_django(password: &[u8], hash: &str) -> Result<bool> {
+    let invalid = || ErrorKind::Other("invalid django password hash".to_string());
+
+    let mut parts = hash.split('$').skip(1);
+    let (Some(iterations), Some(salt), Some(expected), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid().into());
+    };
+    let iterations = iterations.parse::<u32>().map_err(|_| invalid())?;
+    let expected = base64::engine::general_purpose::STANDARD
+        .decode(expected)
+        .map_err(|_| invalid())?;
+
+    let mut derived = vec![0u8; expected.len()];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password, salt.as_bytes(), iterations, &mut derived);
+
+    // constant time comparison
+    Ok(!expected.is_empty()
+        && derived
+            .iter()
+            .zip(expected.iter())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // crypt_blowfish test vector
+    const BCRYPT: &str = "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW";
+    // RFC 7914 scrypt test vector, P = "password", S = "NaCl", N = 1024
+    const SCRYPT: &str = "$scrypt$ln=10,r=8,p=16$TmFDbA$/bq+HJ00cgB4VucZDQHp/nxq18vII3gw53N2Y0s3MWIurzDZLiKjiG/xCSedmDDaxyevuUqD7m2DYMvfoswGQA";
+    // RFC 7914 PBKDF2-HMAC-SHA256 test vector, P = "Password", S = "NaCl"
+    const PBKDF2: &str = "$pbkdf2-sha256$i=80000,l=64$TmFDbA$TdzY9guYviGDDO5e8icB+WQaRBjQTAQUrv8Ih2s0q1ah1CWhIlgzVJrbhBtRybMXaicr3ruh0HhHj2Kzl/M8jQ";
+    const DJANGO: &str = "pbkdf2_sha256$80000$NaCl$TdzY9guYviGDDO5e8icB+WQaRBjQTAQUrv8Ih2s0q1ah1CWhIlgzVJrbhBtRybMXaicr3ruh0HhHj2Kzl/M8jQ==";
+
+    /// Wrong passwords must be rejected as such, not fail on parsing.
+    fn rejected(password: &[u8], hash: &str) -> bool {
+        matches!(
+            verify(password, hash).map_err(|e| e.kind),
+            Err(ErrorKind::InvalidCredentials)
+        )
+    }
+
+    #[test]
+    fn detect() {
+        assert_eq!(Format::detect(BCRYPT), Some(Format::Bcrypt));
+        assert_eq!(
+            Format::detect(&BCRYPT.replace("$2a$", "$2y$")),
+            Some(Format::Bcrypt)
+        );
+        assert_eq!(Format::detect(SCRYPT), Some(Format::Scrypt));
+        assert_eq!(Format::detect(PBKDF2), Some(Format::Pbkdf2));
+        assert_eq!(Format::detect(DJANGO), Some(Format::Django));
+        assert_eq!(
+            Format::detect("$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$aGFzaA"),
+            Some(Format::Argon2)
+        );
+        assert_eq!(Format::detect("5f4dcc3b5aa765d61d8327deb882cf99"), None);
+    }
+
+    #[test]
+    fn bcrypt() {
+        assert!(verify(b"U*U", BCRYPT).is_ok());
+        assert!(rejected(b"U*V", BCRYPT));
+    }
+
+    #[test]
+    fn scrypt() {
+        assert!(verify(b"password", SCRYPT).is_ok());
+        assert!(rejected(b"passw0rd", SCRYPT));
+    }
+
+    #[test]
+    fn pbkdf2() {
+        assert!(verify(b"Password", PBKDF2).is_ok());
+        assert!(rejected(b"password", PBKDF2));
+    }
+
+    #[test]
+    fn django() {
+        assert!(verify(b"Password", DJANGO).is_ok());
+        assert!(rejected(b"password", DJANGO));
+        assert!(verify(b"Password", "pbkdf2_sha256$80000$NaCl").is_err());
+    }
+
+    #[test]
+    fn argon2_is_not_legacy() {
+        assert!(verify(
+            b"password",
+            "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$aGFzaA"
+        )
+        .is_err());
+    }
+}
@@ -5,52 +5,203 @@ use std::sync::Arc;
 // use axum::response::{AppendHeaders, Html, IntoResponse, Redirect};
 // use axum::{Extension, Form};
 // use axum_extra::extract::PrivateCookieJar;
+use std::time::Duration;
+
+use chrono::Utc;
 use cookie::{Cookie, CookieJar, PrivateJar, SameSite};
 use serde_json::json;
 use uuid::Uuid;
 
 use crate::db::{decode, encode};
-use crate::error::{ErrorKind, Result};
+use crate::error::{Error, ErrorKind, Result};
+use crate::user::{UserActivity, UserActivityCategory};
 use crate::{util, Config, Database, User, UserId};
 
-use super::TokenMeta;
+use super::{throttle, TokenMeta};
+
+/// Context of tokens issued for web sessions.
+pub const SESSION_CONTEXT: &str = "session";
 
 /// Generates a cookie for logging in user with user email.
-pub fn log_in_user_email<'c>(user_email: &str, db: &Database) -> Result<Cookie<'c>> {
+pub fn log_in_user_email<'c>(
+    user_email: &str,
+    remember: bool,
+    config: &Config,
+    db: &Database,
+) -> Result<Cookie<'c>> {
     let users = db.get_collection::<User>()?;
 
-    let mut matched_user_id = users.iter().find(|u| &u.email == user_email).map(|u| u.id);
+    let mut matched_user_id = users
+        .iter()
+        .find(|u| util::same_email(&u.email, user_email))
+        .map(|u| u.id);
 
     if let Some(user_id) = matched_user_id {
-        return log_in_user_id(&user_id, db);
+        return log_in_user_id(&user_id, remember, config, db);
     } else {
         return Err(ErrorKind::UserNotFound(format!("email: {}", user_email)).into());
     }
 }
 
 /// Generates a cookie for logging in user by user id.
-pub fn log_in_user_id<'c>(user_id: &UserId, db: &Database) -> Result<Cookie<'c>> {
+///
+/// Remembered sessions last longer and use a persistent cookie, so that
+/// they survive browser restarts. See `config::Session`.
+pub fn log_in_user_id<'c>(
+    user_id: &UserId,
+    remember: bool,
+    config: &Config,
+    db: &Database,
+) -> Result<Cookie<'c>> {
+    let duration = if remember {
+        config.auth.session.remember_duration
+    } else {
+        config.auth.session.duration
+    };
+
     let tokens = db.get_collection::<TokenMeta>()?;
 
-    // check if an active token exists for user
-    for auth_token in tokens {
-        if &auth_token.user_id == user_id {
-            return Ok(Cookie::build(("token", auth_token.id.to_string()))
-                .same_site(SameSite::Lax)
-                .path("/")
-                .secure(true)
-                .finish());
+    // check if an active token of the same kind exists for user,
+    // impersonation tokens are never handed out to the user themselves
+    let existing = tokens.into_iter().find(|t| {
+        &t.user_id == user_id
+            && t.impersonator.is_none()
+            && t.duration == duration
+            && t.context == SESSION_CONTEXT
+            && !t.is_expired()
+    });
+
+    let auth_token = match existing {
+        Some(token) => token,
+        None => {
+            // no active token for user, generate token and create the cookie
+            let mut auth_token = TokenMeta::new(*user_id);
+            auth_token.duration = duration;
+            auth_token.context = SESSION_CONTEXT.to_string();
+            db.set(&auth_token)?;
+            auth_token
         }
+    };
+
+    let mut cookie = Cookie::build(("token", auth_token.id.to_string()))
+        .same_site(SameSite::Lax)
+        .path("/")
+        .secure(true);
+    if remember {
+        let duration: Duration = duration.into();
+        cookie = cookie.max_age(cookie::time::Duration::seconds(duration.as_secs() as i64));
     }
 
-    // no active token for user, generate token and create the cookie
-    let auth_token = TokenMeta::new(*user_id);
+    Ok(cookie.build())
+}
 
-    db.set(&auth_token)?;
+/// Validates provided credentials, returning the matching user.
+///
+/// Login can be either user email or user handle. Failed attempts are
+/// counted per account and per client address, see `config::Lockout`.
+/// Attempts on a locked account fail with `InvalidCredentials`, same as
+/// for a wrong password.
+pub fn authenticate(
+    login: &str,
+    password: &str,
+    ip: Option<&str>,
+    config: &Config,
+    db: &Database,
+) -> Result<User> {
+    let lockout = &config.auth.lockout;
+    let ip_key = ip.map(throttle::ip_key);
+    if let Some(ip_key) = &ip_key {
+        throttle::check(ip_key, lockout, db)?;
+    }
 
-    return Ok(Cookie::build(("token", auth_token.id.to_string()))
-        .same_site(SameSite::Lax)
-        .path("/")
-        .secure(true)
-        .finish());
+    let login = login.to_string();
+    let mut user = match util::find_user_by_email(db, &login) {
+        Ok(u) => u,
+        Err(e) => {
+            log::trace!("didn't find user by email: {e}, trying to find by handle...");
+            match util::find_user_by_handle(db, &login) {
+                Ok(u) => u,
+                Err(_) => {
+                    if let Some(ip_key) = &ip_key {
+                        throttle::record_failure(ip_key, lockout.ip_attempts, lockout, db)?;
+                    }
+                    return Err(ErrorKind::InvalidCredentials.into());
+                }
+            }
+        }
+    };
+
+    // locked accounts get the same response as a wrong password, so that
+    // lockouts can't be used to find out which accounts exist
+    let account_key = throttle::account_key(&user.id);
+    if throttle::check(&account_key, lockout, db).is_err() {
+        if let Some(ip_key) = &ip_key {
+            throttle::record_failure(ip_key, lockout.ip_attempts, lockout, db)?;
+        }
+        return Err(ErrorKind::InvalidCredentials.into());
+    }
+
+    let password_hash = match &user.password_hash {
+        Some(hash) => hash,
+        None => {
+            return Err(Error::new_with(
+                ErrorKind::PasswordNotSet,
+                None,
+                Some(user.id),
+            ))
+        }
+    };
+    if super::validate_password(password.as_bytes(), password_hash).is_err() {
+        if let Some(ip_key) = &ip_key {
+            throttle::record_failure(ip_key, lockout.ip_attempts, lockout, db)?;
+        }
+        let locked = throttle::record_failure(&account_key, lockout.account_attempts, lockout, db)?;
+
+        user.activities.list.push(UserActivity {
+            time: Utc::now(),
+            category: UserActivityCategory::LoginUnsuccesful,
+            message: format!(
+                "Failed login attempt from {}",
+                ip.unwrap_or("unknown address")
+            ),
+        });
+        if let Some(until) = locked {
+            user.activities.list.push(UserActivity {
+                time: Utc::now(),
+                category: UserActivityCategory::LoginLockout,
+                message: format!(
+                    "Account locked until {}",
+                    until.format("%Y-%m-%d %H:%M:%S UTC")
+                ),
+            });
+            if lockout.notify {
+                if let Err(e) = crate::email::lockout(user.email.clone(), until, config, db) {
+                    log::error!("failed sending lockout notification to {}: {e}", user.id);
+                }
+            }
+        }
+        db.set(&user)?;
+
+        return Err(ErrorKind::InvalidCredentials.into());
+    }
+
+    // don't let disabled users log in
+    if user.is_disabled {
+        return Err(Error::new_with(
+            ErrorKind::AccountDisabled,
+            None,
+            Some(user.id),
+        ));
+    }
+
+    throttle::clear(&account_key, db)?;
+
+    // upgrade hashes created with outdated parameters while the plain
+    // password is at hand
+    if super::needs_rehash(password_hash, &config.auth.argon2) {
+        user.password_hash = Some(super::hash_password_with(password, &config.auth.argon2)?);
+        db.set(&user)?;
+    }
+
+    Ok(user)
 }
@@ -0,0 +1,133 @@
+//! Passwordless authentication using single-use links sent by email.
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::config::SequenceTrigger;
+use crate::db::{Collectable, Identifiable};
+use crate::email::sequence;
+use crate::error::{ErrorKind, Result};
+use crate::{util, Config, Database, User, UserId};
+
+/// Key embedded in a magic login link.
+///
+/// The key is random and only ever known to the holder of the target email
+/// inbox. It's removed from the database as soon as it's redeemed, making
+/// each link usable only once.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MagicLinkKey {
+    pub key: Uuid,
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Collectable for MagicLinkKey {
+    fn get_collection_name() -> &'static str {
+        "magic_link_keys"
+    }
+}
+
+impl Identifiable for MagicLinkKey {
+    fn get_id(&self) -> Uuid {
+        // here the key itself is also the identificator
+        self.key
+    }
+}
+
+impl MagicLinkKey {
+    pub fn new(email: String) -> Self {
+        Self {
+            key: Uuid::new_v4(),
+            email,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// Returns true if the key is older than the configured expiry.
+    pub fn is_expired(&self, config: &Config) -> bool {
+        self.created_at + Duration::seconds(config.auth.magic_link_expiry as i64) < Utc::now()
+    }
+}
+
+/// Creates a new magic link key for the provided email and sends the link
+/// to that address.
+///
+/// Returns without sending anything if there's no user with that email and
+/// registration through email is not allowed, including when registration is
+/// invite-only.
+pub fn send_link(email: String, config: &Config, db: &Database) -> Result<()> {
+    if util::find_user_by_email(db, &email).is_err()
+        && (!config.registration.enabled
+            || !config.registration.email
+            || config.registration.invite_only)
+    {
+        log::trace!("not sending magic link to unknown email, registration closed: {email}");
+        return Ok(());
+    }
+
+    let key = MagicLinkKey::new(email);
+    db.set(&key)?;
+
+    crate::email::magic_link(key.email, key.key.to_string(), config, db)
+}
+
+/// Redeems the magic link key, returning the id of the user that should be
+/// logged in.
+///
+/// If the user with the key email doesn't exist yet it's created, given that
+/// registration config allows it.
+pub fn redeem(key: Uuid, config: &Config, db: &Database) -> Result<UserId> {
+    // links are single-use, the key is removed regardless of the outcome and
+    // only the request that actually removed it gets to continue
+    let key = db
+        .take::<MagicLinkKey>(key)
+        .map_err(|_| ErrorKind::AuthFailed("invalid magic link".to_string()))?;
+
+    if key.is_expired(config) {
+        return Err(ErrorKind::AuthFailed("magic link expired".to_string()).into());
+    }
+
+    match util::find_user_by_email(db, &key.email) {
+        Ok(mut user) => {
+            if user.is_disabled {
+                return Err(ErrorKind::AccountDisabled.into());
+            }
+            // following the link proves ownership of the address
+            if !user.email_confirmed {
+                user.email_confirmed = true;
+                db.set(&user)?;
+                trigger(SequenceTrigger::EmailConfirmed, &user, config, db);
+            }
+            Ok(user.id)
+        }
+        Err(_) => {
+            if !config.registration.enabled
+                || !config.registration.email
+                || config.registration.invite_only
+            {
+                return Err(ErrorKind::RegistrationClosed(
+                    "can't create new user based on magic link".to_string(),
+                )
+                .into());
+            }
+
+            let mut user = User::new(db)?;
+            user.email = key.email;
+            user.email_confirmed = true;
+            db.set(&user)?;
+
+            trigger(SequenceTrigger::Registration, &user, config, db);
+            trigger(SequenceTrigger::EmailConfirmed, &user, config, db);
+
+            Ok(user.id)
+        }
+    }
+}
+
+/// Starts sequences for the event. The login goes through regardless, so
+/// failures are only logged.
+fn trigger(event: SequenceTrigger, user: &User, config: &Config, db: &Database) {
+    if let Err(e) = sequence::trigger(event, user, config, db) {
+        log::error!("failed starting sequences for user {}: {e}", user.id);
+    }
+}
@@ -1,30 +1,83 @@
+use std::collections::HashSet;
 use std::time::{Duration, Instant};
 
 use argon2::password_hash::SaltString;
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
 use axum::routing::{get, post};
 use axum::{Extension, Router};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::api::{AuthDuration, AuthScope};
+use crate::api::{AuthDuration, AuthScope, Scope};
 use crate::db::{decode, encode, Collectable, Database, Identifiable};
 use crate::error::{Error, ErrorKind, Result};
-use crate::{Config, UserId};
+use crate::{config, Config, UserId};
 
+pub mod email_change;
+pub mod impersonate;
+pub mod invite;
+pub mod legacy;
 pub mod login;
+pub mod magic;
+pub mod password;
+pub mod refresh;
+pub mod throttle;
 
+/// Hashes the password using default argon2 parameters.
 pub fn hash_password(password: &str) -> Result<String> {
+    hash_password_with(password, &config::Argon2::default())
+}
+
+/// Hashes the password using provided argon2 parameters.
+pub fn hash_password_with(password: &str, params: &config::Argon2) -> Result<String> {
     let salt = SaltString::generate(&mut rand::thread_rng());
-    // TODO argon params?
-    let password_hash = Argon2::default()
+    let password_hash = argon2(params)?
         .hash_password(password.as_bytes(), &salt)?
         .to_string();
     Ok(password_hash)
 }
 
+/// Checks whether the hash was created with different algorithm or
+/// parameters than the ones currently configured.
+pub fn needs_rehash(password_hash: &str, params: &config::Argon2) -> bool {
+    let Ok(hash) = PasswordHash::new(password_hash) else {
+        return true;
+    };
+    if hash.algorithm != Algorithm::Argon2id.ident() || hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(&hash) {
+        Ok(current) => {
+            current.m_cost() != params.memory_cost
+                || current.t_cost() != params.time_cost
+                || current.p_cost() != params.parallelism
+        }
+        Err(_) => true,
+    }
+}
+
+fn argon2(params: &config::Argon2) -> Result<Argon2<'static>> {
+    let params = Params::new(
+        params.memory_cost,
+        params.time_cost,
+        params.parallelism,
+        None,
+    )
+    .map_err(|e| ErrorKind::Other(format!("invalid argon2 parameters: {e}")))?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+/// Verifies the password against the hash. Besides argon2, hashes imported
+/// from other systems are accepted, see `auth::legacy`.
 pub fn validate_password(password: &[u8], expected_password_hash: &str) -> Result<()> {
+    if legacy::Format::detect(expected_password_hash)
+        .is_some_and(|format| format != legacy::Format::Argon2)
+    {
+        return legacy::verify(password, expected_password_hash);
+    }
+
     let expected_password_hash = PasswordHash::new(expected_password_hash)
         .map_err(|_| ErrorKind::Other("Failed to parse hash in PHC string format.".to_string()))?;
     Argon2::default().verify_password(password, &expected_password_hash)?;
@@ -42,12 +95,20 @@ pub struct TokenMeta {
     pub id: Uuid,
     pub user_id: Uuid,
     pub issued_at: DateTime<Utc>,
-    pub scope: AuthScope,
+    /// Time the token was last used. Tokens expire after `duration` of
+    /// inactivity.
+    pub last_seen: Option<DateTime<Utc>>,
+    /// Set of scopes the token grants when used as a bearer token.
+    pub scopes: HashSet<Scope>,
     pub duration: AuthDuration,
 
     pub browser: String,
     pub ip_addr: String,
     pub context: String,
+
+    /// Admin acting as the user, if the token was issued for an
+    /// impersonation session.
+    pub impersonator: Option<UserId>,
 }
 
 impl Collectable for TokenMeta {
@@ -68,17 +129,24 @@ impl TokenMeta {
             id: TokenId::new_v4(),
             user_id,
             issued_at: Utc::now(),
-            scope: AuthScope::Public,
+            last_seen: None,
+            scopes: AuthScope::Public.scopes(),
             duration: AuthDuration::Short,
             context: "".to_string(),
             browser: "Unknown".to_string(),
             ip_addr: "Unknown".to_string(),
+            impersonator: None,
         }
     }
 
+    /// Returns true if the token grants the provided scope.
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
     /// Returns true if the token is expired.
     pub fn is_expired(&self) -> bool {
-        let delta_time = Utc::now() - self.issued_at;
+        let delta_time = Utc::now() - self.last_seen.unwrap_or(self.issued_at);
         let duration: Duration = self.duration.into();
         if delta_time.num_seconds() as u64 > duration.as_secs() {
             true
@@ -86,12 +154,70 @@ impl TokenMeta {
             false
         }
     }
+
+    /// Marks the token as used, moving the expiry forward. Returns true if
+    /// the token changed and needs to be persisted, which only happens once
+    /// per `interval` seconds.
+    pub fn touch(&mut self, interval: usize) -> bool {
+        let now = Utc::now();
+        let last_seen = self.last_seen.unwrap_or(self.issued_at);
+        if (now - last_seen).num_seconds() < interval as i64 {
+            return false;
+        }
+        self.last_seen = Some(now);
+        true
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ConfirmationKey {
     pub user: UserId,
     pub key: Uuid,
+    #[serde(default)]
+    pub purpose: ConfirmationPurpose,
+    /// Email address being confirmed, if different from the current user
+    /// email.
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+/// What confirming the key results in.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfirmationPurpose {
+    /// Confirms the email the account was registered with.
+    #[default]
+    Signup,
+    /// Switches the account email to the one stored with the key.
+    EmailChange,
+    /// Schedules the account for deletion.
+    AccountDeletion,
+}
+
+impl ConfirmationKey {
+    pub fn new(user: UserId) -> Self {
+        Self {
+            user,
+            key: Uuid::new_v4(),
+            purpose: ConfirmationPurpose::Signup,
+            email: None,
+        }
+    }
+
+    pub fn with_purpose(user: UserId, purpose: ConfirmationPurpose) -> Self {
+        Self {
+            purpose,
+            ..Self::new(user)
+        }
+    }
+
+    pub fn email_change(user: UserId, email: String) -> Self {
+        Self {
+            user,
+            key: Uuid::new_v4(),
+            purpose: ConfirmationPurpose::EmailChange,
+            email: Some(email),
+        }
+    }
 }
 
 impl Collectable for ConfirmationKey {
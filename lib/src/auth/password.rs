@@ -0,0 +1,164 @@
+//! Password policy enforcement, see `config::PasswordPolicy`.
+
+use crate::config::PasswordPolicy;
+use crate::error::{ErrorKind, Result};
+
+/// Most commonly used passwords, compiled from public breach statistics.
+const COMMON: &[&str] = &[
+    "123456",
+    "password",
+    "12345678",
+    "qwerty",
+    "123456789",
+    "12345",
+    "1234",
+    "111111",
+    "1234567",
+    "dragon",
+    "123123",
+    "baseball",
+    "abc123",
+    "football",
+    "monkey",
+    "letmein",
+    "696969",
+    "shadow",
+    "master",
+    "666666",
+    "qwertyuiop",
+    "123321",
+    "mustang",
+    "1234567890",
+    "michael",
+    "654321",
+    "superman",
+    "1qaz2wsx",
+    "7777777",
+    "121212",
+    "000000",
+    "qazwsx",
+    "123qwe",
+    "killer",
+    "trustno1",
+    "jordan",
+    "jennifer",
+    "zxcvbnm",
+    "asdfgh",
+    "hunter",
+    "buster",
+    "soccer",
+    "harley",
+    "batman",
+    "andrew",
+    "tigger",
+    "sunshine",
+    "iloveyou",
+    "2000",
+    "charlie",
+    "robert",
+    "thomas",
+    "hockey",
+    "ranger",
+    "daniel",
+    "starwars",
+    "112233",
+    "george",
+    "computer",
+    "michelle",
+    "jessica",
+    "pepper",
+    "1111",
+    "zxcvbn",
+    "555555",
+    "11111111",
+    "131313",
+    "freedom",
+    "777777",
+    "pass",
+    "maggie",
+    "159753",
+    "aaaaaa",
+    "ginger",
+    "princess",
+    "joshua",
+    "cheese",
+    "amanda",
+    "summer",
+    "love",
+    "ashley",
+    "nicole",
+    "chelsea",
+    "biteme",
+    "matthew",
+    "access",
+    "yankees",
+    "987654321",
+    "dallas",
+    "austin",
+    "thunder",
+    "taylor",
+    "matrix",
+    "password1",
+    "password123",
+    "qwerty123",
+    "welcome",
+    "admin",
+    "admin123",
+    "passw0rd",
+    "p@ssw0rd",
+    "changeme",
+    "secret",
+    "qwerty1",
+    "1q2w3e4r",
+    "1q2w3e4r5t",
+    "zaq12wsx",
+    "abcd1234",
+    "login",
+    "welcome1",
+];
+
+/// Checks the password against the policy, returning an error describing
+/// the first violated rule.
+pub fn check(password: &str, policy: &PasswordPolicy) -> Result<()> {
+    let invalid =
+        |reason: &str| Err(ErrorKind::BadInput(format!("invalid password: {reason}")).into());
+
+    let length = password.chars().count();
+    if length < policy.min_length {
+        return invalid(&format!(
+            "must be at least {} characters long",
+            policy.min_length
+        ));
+    }
+    if length > policy.max_length {
+        return invalid(&format!(
+            "must be at most {} characters long",
+            policy.max_length
+        ));
+    }
+
+    if policy.require_lowercase && !password.chars().any(char::is_lowercase) {
+        return invalid("must contain a lowercase letter");
+    }
+    if policy.require_uppercase && !password.chars().any(char::is_uppercase) {
+        return invalid("must contain an uppercase letter");
+    }
+    if policy.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+        return invalid("must contain a digit");
+    }
+    if policy.require_symbol && password.chars().all(char::is_alphanumeric) {
+        return invalid("must contain a symbol");
+    }
+
+    let lowercase = password.to_lowercase();
+    if (policy.deny_common && COMMON.contains(&lowercase.as_str()))
+        || policy
+            .denylist
+            .iter()
+            .any(|p| p.to_lowercase() == lowercase)
+    {
+        return invalid("too common");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,161 @@
+//! Refresh tokens for API clients.
+//!
+//! Each refresh token can be used exactly once. Using it revokes the access
+//! token it was issued with and yields a new access and refresh token pair
+//! belonging to the same family. Presenting an already used refresh token
+//! means it has leaked, in which case the whole family is revoked and the
+//! client has to authenticate again.
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::api::{AuthDuration, Scope};
+use crate::db::{Collectable, Identifiable};
+use crate::error::{ErrorKind, Result};
+use crate::{Config, Database, UserId};
+
+use super::{TokenId, TokenMeta};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub user_id: UserId,
+    /// Identifies the chain of tokens created through rotation.
+    pub family: Uuid,
+    /// Access token issued together with this refresh token.
+    pub access_token: TokenId,
+    pub issued_at: DateTime<Utc>,
+    /// Set once the token gets exchanged.
+    pub used: bool,
+
+    // properties carried over to rotated access tokens
+    pub scopes: HashSet<Scope>,
+    pub duration: AuthDuration,
+    pub context: String,
+}
+
+impl Collectable for RefreshToken {
+    fn get_collection_name() -> &'static str {
+        "refresh_tokens"
+    }
+}
+
+impl Identifiable for RefreshToken {
+    fn get_id(&self) -> Uuid {
+        self.id
+    }
+}
+
+impl RefreshToken {
+    pub fn is_expired(&self, config: &Config) -> bool {
+        (Utc::now() - self.issued_at).num_seconds()
+            > config.auth.session.refresh_token_expiry as i64
+    }
+}
+
+/// Issues a refresh token for the access token. Starts a new family if
+/// none is provided.
+pub fn issue(
+    access_token: &TokenMeta,
+    family: Option<Uuid>,
+    db: &Database,
+) -> Result<RefreshToken> {
+    let token = RefreshToken {
+        id: Uuid::new_v4(),
+        user_id: access_token.user_id,
+        family: family.unwrap_or_else(Uuid::new_v4),
+        access_token: access_token.id,
+        issued_at: Utc::now(),
+        used: false,
+        scopes: access_token.scopes.clone(),
+        duration: access_token.duration,
+        context: access_token.context.clone(),
+    };
+    db.set(&token)?;
+    Ok(token)
+}
+
+/// Exchanges the refresh token for a new access and refresh token pair.
+pub fn rotate(
+    refresh_token: Uuid,
+    config: &Config,
+    db: &Database,
+) -> Result<(TokenMeta, RefreshToken)> {
+    let refresh_token = db
+        .get::<RefreshToken>(refresh_token)
+        .map_err(|_| ErrorKind::AuthFailed("invalid refresh token".to_string()))?;
+
+    if refresh_token.is_expired(config) {
+        revoke_family(refresh_token.family, db)?;
+        return Err(ErrorKind::AuthFailed("refresh token expired".to_string()).into());
+    }
+
+    // used tokens are kept around until the family gets revoked so that
+    // reuse can be detected. The flag is flipped atomically, so that out of
+    // concurrent requests presenting the same token only one gets through.
+    let mut reused = false;
+    let refresh_token = match db.update::<RefreshToken, _>(refresh_token.id, |mut token| {
+        if token.used {
+            reused = true;
+            return Err(ErrorKind::AuthFailed("refresh token already used".to_string()).into());
+        }
+        token.used = true;
+        Ok(token)
+    }) {
+        Ok(token) => token,
+        Err(e) if reused => {
+            log::warn!(
+                "refresh token reuse detected, revoking token family {} of user {}",
+                refresh_token.family,
+                refresh_token.user_id
+            );
+            revoke_family(refresh_token.family, db)?;
+            return Err(e);
+        }
+        // removed in the meantime, e.g. with the family getting revoked
+        Err(_) => return Err(ErrorKind::AuthFailed("invalid refresh token".to_string()).into()),
+    };
+
+    // the previous access token is superseded by the new one
+    if let Ok(old) = db.get::<TokenMeta>(refresh_token.access_token) {
+        db.remove(&old)?;
+    }
+
+    let mut token = TokenMeta::new(refresh_token.user_id);
+    token.scopes = refresh_token.scopes.clone();
+    token.duration = refresh_token.duration;
+    token.context = refresh_token.context.clone();
+    db.set(&token)?;
+
+    let next = issue(&token, Some(refresh_token.family), db)?;
+
+    Ok((token, next))
+}
+
+/// Revokes all refresh tokens of the family along with their access tokens.
+pub fn revoke_family(family: Uuid, db: &Database) -> Result<()> {
+    for token in db.get_collection::<RefreshToken>()? {
+        if token.family != family {
+            continue;
+        }
+        if let Ok(access_token) = db.get::<TokenMeta>(token.access_token) {
+            db.remove(&access_token)?;
+        }
+        db.remove(&token)?;
+    }
+    Ok(())
+}
+
+/// Revokes the family the access token belongs to, if any.
+pub fn revoke_for(access_token: &TokenMeta, db: &Database) -> Result<()> {
+    if let Some(token) = db
+        .get_collection::<RefreshToken>()?
+        .into_iter()
+        .find(|t| t.access_token == access_token.id)
+    {
+        revoke_family(token.family, db)?;
+    }
+    Ok(())
+}
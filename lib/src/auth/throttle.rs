@@ -0,0 +1,129 @@
+//! Failed login attempt tracking with exponential backoff.
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::db::{Collectable, Identifiable};
+use crate::error::{ErrorKind, Result};
+use crate::{config, Database, UserId};
+
+/// Failed login attempts counter for a single key, be it an account or
+/// a client address.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LoginAttempts {
+    pub id: Uuid,
+    pub key: String,
+    pub failures: u32,
+    pub last_failure: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl Collectable for LoginAttempts {
+    fn get_collection_name() -> &'static str {
+        "login_attempts"
+    }
+}
+
+impl Identifiable for LoginAttempts {
+    fn get_id(&self) -> Uuid {
+        self.id
+    }
+}
+
+impl LoginAttempts {
+    pub fn new(key: String) -> Self {
+        Self {
+            id: key_id(&key),
+            key,
+            failures: 0,
+            last_failure: Utc::now(),
+            locked_until: None,
+        }
+    }
+
+    /// Returns the lockout expiry time if the key is currently locked.
+    pub fn locked(&self) -> Option<DateTime<Utc>> {
+        self.locked_until.filter(|until| until > &Utc::now())
+    }
+}
+
+/// Counter key for the user account.
+pub fn account_key(user: &UserId) -> String {
+    format!("account:{}", user)
+}
+
+/// Counter key for the client address.
+pub fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// Counters are stored under ids derived from their keys so that they can
+/// be looked up directly.
+fn key_id(key: &str) -> Uuid {
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, key.as_bytes())
+}
+
+fn get(key: &str, config: &config::Lockout, db: &Database) -> Option<LoginAttempts> {
+    let attempts = db.get::<LoginAttempts>(key_id(key)).ok()?;
+    // forget about failures that happened long enough ago
+    if attempts.last_failure + Duration::seconds(config.reset_after as i64) < Utc::now() {
+        None
+    } else {
+        Some(attempts)
+    }
+}
+
+/// Returns an error if the key is currently locked out.
+pub fn check(key: &str, config: &config::Lockout, db: &Database) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+
+    if let Some(until) = get(key, config, db).and_then(|a| a.locked()) {
+        return Err(ErrorKind::TooManyLoginAttempts((until - Utc::now()).num_seconds()).into());
+    }
+
+    Ok(())
+}
+
+/// Records a failed attempt for the key.
+///
+/// Once the number of failures reaches the threshold, the key gets locked.
+/// Lockout duration doubles with each subsequent failure, up to the
+/// configured maximum. Returns the lockout expiry if this failure resulted
+/// in a lockout.
+pub fn record_failure(
+    key: &str,
+    threshold: u32,
+    config: &config::Lockout,
+    db: &Database,
+) -> Result<Option<DateTime<Utc>>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    let mut attempts = get(key, config, db).unwrap_or_else(|| LoginAttempts::new(key.to_string()));
+    attempts.failures += 1;
+    attempts.last_failure = Utc::now();
+
+    let mut locked = None;
+    if attempts.failures >= threshold {
+        let exponent = (attempts.failures - threshold).min(16);
+        let delay = (config.base_delay as u64 * 2u64.pow(exponent)).min(config.max_delay as u64);
+        let until = Utc::now() + Duration::seconds(delay as i64);
+        attempts.locked_until = Some(until);
+        locked = Some(until);
+    }
+
+    db.set(&attempts)?;
+
+    Ok(locked)
+}
+
+/// Clears recorded failures for the key.
+pub fn clear(key: &str, db: &Database) -> Result<()> {
+    if let Ok(attempts) = db.get::<LoginAttempts>(key_id(key)) {
+        db.remove(&attempts)?;
+    }
+    Ok(())
+}
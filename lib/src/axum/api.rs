@@ -0,0 +1,125 @@
+//! JSON API endpoints for programmatic clients, e.g. `micron-cli`.
+//!
+//! Tokens issued here are meant to be used as bearer tokens with subsequent
+//! requests. They're accepted by the `User` extractor the same way session
+//! cookies are.
+
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use axum::extract::ConnectInfo;
+use axum::http::HeaderMap;
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::{Extension, Json};
+use axum_auth::AuthBearer;
+use http::header::USER_AGENT;
+use http::StatusCode;
+use uuid::Uuid;
+
+use crate::api::{AuthRequest, AuthResponse, RefreshRequest, Scope};
+use crate::auth::{refresh, TokenMeta};
+use crate::error::{Error, ErrorKind, Result};
+use crate::util::token_expired;
+
+use super::{ConfigExt, DbExt, Router};
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/api/auth", post(auth))
+        .route("/api/auth/refresh", post(refresh))
+        .route("/api/auth/revoke", post(revoke))
+}
+
+/// Validates credentials and issues a new access token along with
+/// a refresh token.
+pub async fn auth(
+    Extension(db): DbExt,
+    Extension(config): ConfigExt,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    Json(request): Json<AuthRequest>,
+) -> Result<Json<AuthResponse>> {
+    let ip = super::client_ip(&headers, connect_info, &config);
+    let user = crate::auth::login::authenticate(
+        &request.email,
+        &request.password,
+        ip.as_deref(),
+        &config,
+        &db,
+    )?;
+
+    let mut token = TokenMeta::new(user.id);
+    token.scopes = request.scope.scopes();
+    if token.scopes.contains(&Scope::Admin) && !user.is_admin {
+        return Err(ErrorKind::Forbidden.into());
+    }
+    token.duration = request.term;
+    token.context = request.context;
+    if let Some(user_agent) = headers.get(USER_AGENT).and_then(|h| h.to_str().ok()) {
+        token.browser = user_agent.to_string();
+    }
+    if let Some(ip) = ip {
+        token.ip_addr = ip;
+    }
+    db.set(&token)?;
+    let refresh_token = refresh::issue(&token, None, &db)?;
+
+    Ok(Json(AuthResponse {
+        token: token.id.to_string(),
+        refresh_token: Some(refresh_token.id.to_string()),
+    }))
+}
+
+/// Exchanges a refresh token for a new access and refresh token pair. The
+/// presented refresh token can't be used again.
+pub async fn refresh(
+    Extension(db): DbExt,
+    Extension(config): ConfigExt,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    Json(request): Json<RefreshRequest>,
+) -> Result<Json<AuthResponse>> {
+    let (mut token, refresh_token) =
+        refresh::rotate(Uuid::from_str(&request.refresh_token)?, &config, &db)?;
+
+    if let Some(user_agent) = headers.get(USER_AGENT).and_then(|h| h.to_str().ok()) {
+        token.browser = user_agent.to_string();
+    }
+    if let Some(ip) = super::client_ip(&headers, connect_info, &config) {
+        token.ip_addr = ip;
+    }
+    db.set(&token)?;
+
+    Ok(Json(AuthResponse {
+        token: token.id.to_string(),
+        refresh_token: Some(refresh_token.id.to_string()),
+    }))
+}
+
+/// Revokes the presented access token, along with any refresh tokens
+/// issued for it.
+pub async fn revoke(
+    Extension(db): DbExt,
+    AuthBearer(token): AuthBearer,
+) -> Result<impl IntoResponse> {
+    let token = get_token(&token, &db)?;
+    refresh::revoke_for(&token, &db)?;
+    db.remove(&token)?;
+
+    Ok(StatusCode::OK)
+}
+
+fn get_token(token: &str, db: &crate::Database) -> Result<TokenMeta> {
+    let token = db.get::<TokenMeta>(Uuid::from_str(token)?).map_err(|_| {
+        Error::new(ErrorKind::AuthFailed(
+            "failed getting token meta from db".to_string(),
+        ))
+    })?;
+
+    if token_expired(db, &token) {
+        return Err(ErrorKind::AuthFailed("token expired".to_string()).into());
+    }
+
+    Ok(token)
+}
@@ -1,5 +1,8 @@
 pub mod confirm;
+pub mod impersonate;
+pub mod invite;
 pub mod login;
+pub mod magic;
 pub mod oauth;
 pub mod signup;
 
@@ -14,13 +17,29 @@ use super::Router;
 
 pub fn router(config: &Config) -> Router {
     let mut router = Router::new()
-        .route("/login", post(login::login))
         // .route("/login-retry", get(login::login_retry))
         // .route("/verify", post(verify::verify))
         .route("/redir", get(redir))
         .route("/logout", get(login::logout))
         .route("/signup", post(signup::signup))
-        .route("/confirm/:key", get(confirm::confirm));
+        .route("/confirm/:key", get(confirm::confirm))
+        .route("/admin/impersonate/:user_id", post(impersonate::start))
+        .route("/impersonate/stop", get(impersonate::stop))
+        .route("/admin/invites", get(invite::list).post(invite::create))
+        .route("/admin/invites/:code/revoke", post(invite::revoke));
+
+    if config.auth.mode.password() {
+        router = router.route("/login", post(login::login));
+    }
+
+    if config.auth.mode.magic_link() {
+        router = router
+            .route("/login/email", post(magic::request_link))
+            .route(
+                "/login/email/:key",
+                get(magic::confirm_link).post(magic::login_link),
+            );
+    }
 
     if config.oauth.enabled {
         router = router.merge(oauth::router());
@@ -6,8 +6,9 @@ use http::HeaderMap;
 use uuid::Uuid;
 
 use crate::auth::login::log_in_user_id;
-use crate::auth::ConfirmationKey;
-use crate::axum::DbExt;
+use crate::auth::{ConfirmationKey, ConfirmationPurpose};
+use crate::axum::{ConfigExt, DbExt};
+use crate::config::SequenceTrigger;
 use crate::{ErrorKind, Result, User};
 
 #[derive(Debug, Deserialize)]
@@ -15,9 +16,14 @@ pub struct ConfirmData {
     key: Uuid,
 }
 
-/// Verifies the provided account confirmation token and logs the user in.
+/// Verifies the provided confirmation token and logs the user in.
+///
+/// Depending on the key purpose it either confirms the account email,
+/// completes an email change or schedules account deletion. The latter
+/// doesn't log the user in.
 pub async fn confirm(
     Extension(db): DbExt,
+    Extension(config): ConfigExt,
     headers: HeaderMap,
     mut cookies: PrivateCookieJar,
     Path(key): Path<Uuid>,
@@ -28,16 +34,34 @@ pub async fn confirm(
         .map_err(|e| ErrorKind::Other("verification failed".to_string()))?;
     db.remove(&key)?;
 
-    // set the user email as verified
-    let mut user: User = db.get(key.user)?;
-    user.email_confirmed = true;
-    db.set(&user)?;
+    let user = match key.purpose {
+        ConfirmationPurpose::Signup => {
+            // set the user email as verified
+            let mut user: User = db.get(key.user)?;
+            user.email_confirmed = true;
+            db.set(&user)?;
+            if let Err(e) = crate::email::sequence::trigger(
+                SequenceTrigger::EmailConfirmed,
+                &user,
+                &config,
+                &db,
+            ) {
+                log::error!("failed starting sequences for user {}: {e}", user.id);
+            }
+            user
+        }
+        ConfirmationPurpose::EmailChange => crate::auth::email_change::confirm(&key, &db)?,
+        ConfirmationPurpose::AccountDeletion => {
+            crate::user::data::schedule_deletion(key.user, &config, &db)?;
+            return Ok((cookies, Redirect::to("/")));
+        }
+    };
 
     // just confirming email is not enough to get the verified status
     // user.is_verified = true;
 
     // log the user in
-    cookies = cookies.add(log_in_user_id(&user.id, &db)?);
+    cookies = cookies.add(log_in_user_id(&user.id, false, &config, &db)?);
 
     Ok((cookies, Redirect::to("/")))
 }
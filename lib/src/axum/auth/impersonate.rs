@@ -0,0 +1,94 @@
+use std::str::FromStr;
+
+use axum::extract::Path;
+use axum::response::{IntoResponse, Redirect};
+use axum::Extension;
+use axum_extra::extract::PrivateCookieJar;
+use cookie::{Cookie, SameSite};
+use uuid::Uuid;
+
+use crate::auth::TokenMeta;
+use crate::axum::extract::permission::Admin;
+use crate::axum::extract::{Impersonation, Require};
+use crate::axum::DbExt;
+use crate::{ErrorKind, Result, UserId};
+
+/// Cookie holding the admin's own session token for the duration of the
+/// impersonation session.
+const IMPERSONATOR_COOKIE: &str = "impersonator_token";
+
+/// Starts impersonating the user. The admin's own session is restored once
+/// impersonation is stopped.
+pub async fn start(
+    admin: Require<Admin>,
+    Path(user_id): Path<UserId>,
+    mut cookies: PrivateCookieJar,
+    Extension(db): DbExt,
+) -> Result<(PrivateCookieJar, impl IntoResponse)> {
+    // impersonation is only available to browser sessions
+    let admin_token = cookies.get("token").ok_or(ErrorKind::BadInput(
+        "impersonation requires a session cookie".to_string(),
+    ))?;
+
+    let token = crate::auth::impersonate::start(&admin, user_id, &db)?;
+
+    cookies = cookies
+        .add(
+            Cookie::build((IMPERSONATOR_COOKIE, admin_token.value().to_string()))
+                .same_site(SameSite::Lax)
+                .path("/")
+                .secure(true)
+                .http_only(true)
+                .build(),
+        )
+        .add(
+            Cookie::build(("token", token.id.to_string()))
+                .same_site(SameSite::Lax)
+                .path("/")
+                .secure(true)
+                .build(),
+        );
+
+    Ok((cookies, Redirect::to("/")))
+}
+
+/// Stops the impersonation session and restores the admin's own session.
+pub async fn stop(
+    Impersonation(impersonator): Impersonation,
+    mut cookies: PrivateCookieJar,
+    Extension(db): DbExt,
+) -> Result<(PrivateCookieJar, impl IntoResponse)> {
+    let Some(admin) = impersonator else {
+        return Err(ErrorKind::BadInput("not impersonating anyone".to_string()).into());
+    };
+
+    let token = cookies
+        .get("token")
+        .and_then(|c| Uuid::from_str(c.value()).ok())
+        .and_then(|id| db.get::<TokenMeta>(id).ok())
+        .ok_or(ErrorKind::BadInput(
+            "impersonation token not found".to_string(),
+        ))?;
+    crate::auth::impersonate::stop(&token, &db)?;
+
+    cookies = cookies.remove(Cookie::build("token").path("/"));
+
+    // restore the admin session if it's still valid
+    if let Some(admin_token) = cookies
+        .get(IMPERSONATOR_COOKIE)
+        .and_then(|c| Uuid::from_str(c.value()).ok())
+        .and_then(|id| db.get::<TokenMeta>(id).ok())
+        .filter(|t| t.user_id == admin.id && t.impersonator.is_none())
+    {
+        cookies = cookies.add(
+            Cookie::build(("token", admin_token.id.to_string()))
+                .same_site(SameSite::Lax)
+                .path("/")
+                .secure(true)
+                .build(),
+        );
+    }
+    cookies = cookies.remove(Cookie::build(IMPERSONATOR_COOKIE).path("/"));
+
+    Ok((cookies, Redirect::to("/")))
+}
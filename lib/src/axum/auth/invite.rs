@@ -0,0 +1,51 @@
+use axum::extract::Path;
+use axum::response::IntoResponse;
+use axum::{Extension, Form, Json};
+use http::StatusCode;
+
+use crate::auth::invite::{self, Invite};
+use crate::axum::extract::permission::Admin;
+use crate::axum::extract::Require;
+use crate::axum::DbExt;
+use crate::Result;
+
+/// Lists all invites.
+pub async fn list(_: Require<Admin>, Extension(db): DbExt) -> Result<Json<Vec<Invite>>> {
+    Ok(Json(db.get_collection::<Invite>()?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InviteData {
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    max_uses: Option<u32>,
+    /// Number of days the invite stays valid.
+    #[serde(default)]
+    expires_in: Option<u32>,
+}
+
+/// Creates a new invite.
+pub async fn create(
+    admin: Require<Admin>,
+    Extension(db): DbExt,
+    Form(data): Form<InviteData>,
+) -> Result<Json<Invite>> {
+    let invite = invite::create(
+        data.email.filter(|e| !e.is_empty()),
+        data.max_uses,
+        data.expires_in,
+        Some(admin.id),
+        &db,
+    )?;
+    Ok(Json(invite))
+}
+
+pub async fn revoke(
+    _: Require<Admin>,
+    Path(code): Path<String>,
+    Extension(db): DbExt,
+) -> Result<impl IntoResponse> {
+    invite::revoke(&code, &db)?;
+    Ok(StatusCode::OK)
+}
@@ -1,3 +1,6 @@
+use std::net::SocketAddr;
+
+use axum::extract::ConnectInfo;
 use axum::http::HeaderMap;
 use axum::response::{AppendHeaders, Html, IntoResponse};
 use axum::routing::{get, post};
@@ -25,52 +28,46 @@ pub async fn logout(
 pub struct LoginData {
     email: String,
     password: String,
+    /// Keep the user logged in across browser restarts. Submitted by
+    /// a checkbox, so any value counts as checked.
+    #[serde(default, deserialize_with = "checkbox")]
+    remember: bool,
+}
+
+fn checkbox<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<bool, D::Error> {
+    let value = <Option<String> as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(value.is_some_and(|v| v != "false" && v != "off"))
 }
 
 /// Processes login form data and logs the user in.
 pub async fn login(
     Extension(db): DbExt,
+    Extension(config): ConfigExt,
     headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     mut cookies: PrivateCookieJar,
     Form(user_data): Form<LoginData>,
 ) -> Result<(PrivateCookieJar, impl IntoResponse)> {
-    let user = match util::find_user_by_email(&db, &user_data.email) {
-        Ok(u) => u,
-        Err(e) => {
-            log::trace!("didn't find user by email: {e}, trying to find by handle...");
-            match util::find_user_by_handle(&db, &user_data.email) {
-                Ok(u) => u,
-                Err(e) => return Err(ErrorKind::InvalidCredentials.into()),
-            }
-        }
-    };
-    if user.password_hash == None {
-        return Err(Error::new_with(
-            ErrorKind::PasswordNotSet,
-            None,
-            Some(user.id),
-        ));
-    }
-    if let Err(e) = crate::auth::validate_password(
-        user_data.password.as_bytes(),
-        &user.password_hash.clone().unwrap(),
-    ) {
-        Err(ErrorKind::InvalidCredentials.into())
-    } else {
-        // don't let disabled users log in
-        if user.is_disabled {
-            return Err(Error::new_with(
-                ErrorKind::AccountDisabled,
-                None,
-                Some(user.id),
-            ));
-        }
+    let ip = crate::axum::client_ip(&headers, connect_info, &config);
+    let user = crate::auth::login::authenticate(
+        &user_data.email,
+        &user_data.password,
+        ip.as_deref(),
+        &config,
+        &db,
+    )?;
 
-        cookies = cookies.add(crate::auth::login::log_in_user_id(&user.id, &db)?);
+    cookies = cookies.add(crate::auth::login::log_in_user_id(
+        &user.id,
+        user_data.remember,
+        &config,
+        &db,
+    )?);
 
-        Ok((
-            cookies,
-            AppendHeaders([("HX-Redirect", "/redir")]).into_response(),
-        ))
-    }
+    Ok((
+        cookies,
+        AppendHeaders([("HX-Redirect", "/redir")]).into_response(),
+    ))
 }
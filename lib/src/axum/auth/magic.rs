@@ -0,0 +1,59 @@
+use axum::extract::Path;
+use axum::response::{IntoResponse, Redirect};
+use axum::{Extension, Form};
+use axum_extra::extract::PrivateCookieJar;
+use uuid::Uuid;
+use validator::ValidateEmail;
+
+use crate::auth::login::log_in_user_id;
+use crate::axum::csrf::CsrfToken;
+use crate::axum::{confirmation_page, ConfigExt, DbExt};
+use crate::{ErrorKind, Result};
+
+#[derive(Debug, Deserialize)]
+pub struct MagicLinkData {
+    email: String,
+}
+
+/// Sends a magic login link to the provided email address.
+///
+/// Response is the same whether or not the user exists, so that the endpoint
+/// can't be used to check for registered emails.
+pub async fn request_link(
+    Extension(db): DbExt,
+    Extension(config): ConfigExt,
+    Form(data): Form<MagicLinkData>,
+) -> Result<impl IntoResponse> {
+    if !data.email.validate_email() {
+        return Err(ErrorKind::BadInput("invalid email".to_string()).into());
+    }
+
+    crate::auth::magic::send_link(data.email, &config, &db)?;
+
+    Ok("Sent!")
+}
+
+/// Shows a page confirming the login. The key is only redeemed on submit, so
+/// that link scanners visiting the emailed link don't use it up.
+pub async fn confirm_link(csrf: Option<CsrfToken>, Path(key): Path<Uuid>) -> impl IntoResponse {
+    confirmation_page(
+        "Continue to log in.",
+        "Log in",
+        &format!("/login/email/{key}"),
+        csrf.as_ref(),
+    )
+}
+
+/// Redeems the magic link key and logs the user in.
+pub async fn login_link(
+    Extension(db): DbExt,
+    Extension(config): ConfigExt,
+    mut cookies: PrivateCookieJar,
+    Path(key): Path<Uuid>,
+) -> Result<(PrivateCookieJar, impl IntoResponse)> {
+    let user_id = crate::auth::magic::redeem(key, &config, &db)?;
+
+    cookies = cookies.add(log_in_user_id(&user_id, false, &config, &db)?);
+
+    Ok((cookies, Redirect::to("/redir")))
+}
@@ -0,0 +1,183 @@
+//! Provider-agnostic oauth2 flow.
+//!
+//! Initiating the flow stores the `state` parameter along with a PKCE
+//! verifier in a short-lived private cookie. Both are then checked when the
+//! provider redirects back, so that a callback can't be completed in a
+//! browser that didn't start it.
+//!
+//! The same flow is used by logged in users to link additional identities
+//! to their accounts.
+
+use std::str::FromStr;
+
+use axum::{
+    extract::{Path, Query},
+    response::{AppendHeaders, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Extension,
+};
+use axum_extra::extract::PrivateCookieJar;
+use cookie::{time::Duration, Cookie, SameSite};
+use http::{header::SET_COOKIE, HeaderMap};
+
+use crate::axum::{extract, ConfigExt, DbExt};
+use crate::oauth::{AuthState, Provider};
+use crate::{Config, ErrorKind, Result, UserId};
+
+use super::Router;
+
+/// Name of the private cookie holding pending authorization state.
+const STATE_COOKIE: &str = "oauth_state";
+
+/// Time the user has to complete the authorization with the provider.
+const STATE_MAX_AGE: i64 = 600;
+
+// TODO: linkedin, reddit
+pub fn router() -> Router {
+    Router::new()
+        .route("/login/:provider", get(initiate))
+        .route("/auth/:provider", get(callback))
+        .route("/account/link/:provider", get(link))
+        .route("/account/unlink/:provider", post(unlink))
+}
+
+/// Initiates oauth2 randevous with the provider. Results in a redirect to
+/// provider service.
+pub async fn initiate(
+    cookies: PrivateCookieJar,
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    Extension(config): ConfigExt,
+) -> Result<Response> {
+    redirect_to_provider(cookies, &provider, None, &headers, &config).await
+}
+
+/// Initiates linking another provider identity to the logged in user's
+/// account.
+pub async fn link(
+    cookies: PrivateCookieJar,
+    user: extract::User,
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    Extension(config): ConfigExt,
+) -> Result<Response> {
+    redirect_to_provider(cookies, &provider, Some(user.id), &headers, &config).await
+}
+
+/// Removes the provider identity from the logged in user's account.
+pub async fn unlink(
+    mut user: extract::User,
+    Path(provider): Path<String>,
+    Extension(config): ConfigExt,
+    Extension(db): DbExt,
+) -> Result<impl IntoResponse> {
+    let provider = Provider::from_str(&provider)?;
+    crate::oauth::unlink(&mut user, &provider, &config, &db)?;
+
+    Ok("Unlinked!")
+}
+
+async fn redirect_to_provider(
+    cookies: PrivateCookieJar,
+    provider: &str,
+    link: Option<UserId>,
+    headers: &HeaderMap,
+    config: &Config,
+) -> Result<Response> {
+    let provider = Provider::from_str(provider)?;
+    let (auth_url, state) = crate::oauth::authorize(&provider, link, config).await?;
+
+    let cookie = Cookie::build((STATE_COOKIE, serde_json::to_string(&state)?))
+        // needs to be sent along with the top-level redirect back from the
+        // provider
+        .same_site(SameSite::Lax)
+        .path("/auth")
+        .max_age(Duration::seconds(STATE_MAX_AGE))
+        .secure(true)
+        .http_only(true)
+        .build();
+    let cookies = cookies.add(cookie);
+
+    // Redirect to oauth service
+    if let Some(referer) = headers.get("Referer") {
+        if let Ok(referer_str) = referer.to_str() {
+            return Ok((
+                cookies,
+                AppendHeaders([(
+                    SET_COOKIE,
+                    format!("next={};SameSite=Lax;Secure;Path=/", referer_str),
+                )]),
+                Redirect::to(auth_url.as_str()),
+            )
+                .into_response());
+        }
+    }
+    Ok((cookies, Redirect::to(auth_url.as_str())).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthRequest {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// Callback executed after provider service is done with it's part.
+pub async fn callback(
+    mut cookies: PrivateCookieJar,
+    current_user: Option<extract::User>,
+    Path(provider): Path<String>,
+    Query(query): Query<AuthRequest>,
+    Extension(config): ConfigExt,
+    Extension(db): DbExt,
+) -> Result<Response> {
+    let provider = Provider::from_str(&provider)?;
+
+    // Pending state is single-use, remove it regardless of the outcome
+    let auth_state = cookies
+        .get(STATE_COOKIE)
+        .and_then(|c| serde_json::from_str::<AuthState>(c.value()).ok());
+    cookies = cookies.remove(Cookie::build(STATE_COOKIE).path("/auth"));
+
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        if let Some(e) = query.error {
+            log::warn!("unsuccessful {provider} oauth2: {e}");
+        }
+        return Ok((cookies, Redirect::to("/")).into_response());
+    };
+
+    let Some(auth_state) = auth_state else {
+        log::warn!("unsuccessful {provider} oauth2: missing authorization state");
+        return Ok((cookies, Redirect::to("/")).into_response());
+    };
+    let link = auth_state.link;
+
+    let user_info =
+        match crate::oauth::callback(&provider, code, &state, auth_state, &config, &db).await {
+            Ok(user_info) => user_info,
+            Err(e) => {
+                log::warn!("unsuccessful {provider} oauth2: {e}");
+                return Ok((cookies, Redirect::to("/")).into_response());
+            }
+        };
+
+    if let Some(user_id) = link {
+        // Linking must be completed by the same user that initiated it
+        let mut user =
+            current_user
+                .filter(|user| user.id == user_id)
+                .ok_or(ErrorKind::AuthFailed(
+                    "oauth linking completed by a different user".to_string(),
+                ))?;
+        crate::oauth::link(&mut user, &provider, &user_info, &db)?;
+
+        return Ok((cookies, Redirect::to("/redir")).into_response());
+    }
+
+    let (_, cookie) = crate::oauth::login_or_register(&provider, user_info, &db, &config).await?;
+
+    // Update cookies to actually log the user in
+    cookies = cookies.add(cookie);
+
+    Ok((cookies, Redirect::to("/redir")).into_response())
+}
@@ -4,10 +4,11 @@ use axum_extra::extract::PrivateCookieJar;
 use cookie::Cookie;
 use http::HeaderMap;
 use uuid::Uuid;
-use validator::{ValidateEmail, ValidateLength};
+use validator::ValidateEmail;
 
 use crate::auth::{ConfirmationKey, TokenMeta};
 use crate::axum::{ConfigExt, DbExt};
+use crate::config::SequenceTrigger;
 use crate::{util, ErrorKind, Result, User};
 
 #[derive(Debug, Deserialize)]
@@ -16,6 +17,9 @@ pub struct SignupUserData {
     password: String,
     #[serde(default)]
     consent: bool,
+    /// Required with invite-only registration.
+    #[serde(default)]
+    invite: Option<String>,
 }
 
 pub async fn signup(
@@ -25,31 +29,62 @@ pub async fn signup(
     mut cookies: PrivateCookieJar,
     Form(user_data): Form<SignupUserData>,
 ) -> Result<(PrivateCookieJar, impl IntoResponse)> {
+    if !config.registration.enabled || !config.registration.email {
+        return Err(ErrorKind::RegistrationClosed(
+            "can't create new user based on email signup".to_string(),
+        )
+        .into());
+    }
+
     // validate inputs
     if !user_data.email.validate_email() {
         return Err(ErrorKind::BadInput("invalid email".to_string()).into());
     }
 
-    if !user_data.password.validate_length(Some(8), Some(24), None) {
-        return Err(ErrorKind::BadInput("invalid password length".to_string()).into());
+    crate::auth::password::check(&user_data.password, &config.auth.password)?;
+
+    if util::find_user_by_email(&db, &user_data.email).is_ok() {
+        return Err(ErrorKind::UserWithEmailAlreadyExists(user_data.email).into());
     }
 
     let mut user = User::new(&db)?;
 
     // create a new user entry with unverified email status
     user.email = user_data.email;
-    user.password_hash = Some(crate::auth::hash_password(&user_data.password)?);
-    db.set(&user)?;
+    user.password_hash = Some(crate::auth::hash_password_with(
+        &user_data.password,
+        &config.auth.argon2,
+    )?);
+
+    // the invite use is consumed before the user is stored, give it back
+    // if storing fails
+    let invite = config
+        .registration
+        .invite_only
+        .then(|| user_data.invite.unwrap_or_default());
+    if let Some(code) = &invite {
+        crate::auth::invite::redeem(code, &user.email, &db)?;
+    }
+    if let Err(e) = db.set(&user) {
+        if let Some(code) = &invite {
+            crate::auth::invite::release(code, &db)?;
+        }
+        return Err(e);
+    }
 
     // create a new verification key item and store it
-    let key = ConfirmationKey {
-        user: user.id,
-        key: Uuid::new_v4(),
-    };
+    let key = ConfirmationKey::new(user.id);
     db.set(&key)?;
 
     // send email with the code
-    crate::email::confirmation(user.email, key.key.to_string(), &config)?;
+    crate::email::confirmation(user.email.clone(), key.key.to_string(), &config, &db)?;
+
+    // the account is already in place, don't fail the signup over sequences
+    if let Err(e) =
+        crate::email::sequence::trigger(SequenceTrigger::Registration, &user, &config, &db)
+    {
+        log::error!("failed starting sequences for user {}: {e}", user.id);
+    }
 
     // depending on configuration let the user in or require verification
     if config.auth.require_confirmed_email {
@@ -57,7 +92,9 @@ pub async fn signup(
         Ok((cookies, AppendHeaders([("HX-Redirect", "/verify")])))
     } else {
         // login the user in
-        cookies = cookies.add(crate::auth::login::log_in_user_id(&user.id, &db)?);
+        cookies = cookies.add(crate::auth::login::log_in_user_id(
+            &user.id, false, &config, &db,
+        )?);
         Ok((cookies, AppendHeaders([("HX-Redirect", "/")])))
     }
 }
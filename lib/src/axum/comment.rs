@@ -7,9 +7,11 @@ use axum::{
 use chrono::{Duration, Utc};
 use uuid::Uuid;
 
+use crate::user::role;
 use crate::{Comment, Result};
 
-use super::{extract, ConfigExt, DbExt, Router};
+use super::extract::{self, scope, Scoped};
+use super::{ConfigExt, DbExt, Router};
 
 pub fn router() -> Router {
     Router::new().route("/comment/:parent", post(add_comment))
@@ -27,14 +29,14 @@ pub struct CommentQuery {
 
 pub async fn add_comment(
     Path(parent): Path<Uuid>,
-    user: extract::User,
+    user: Scoped<extract::User, scope::CommentsWrite>,
     Extension(db): DbExt,
     Extension(config): ConfigExt,
     Query(query): Query<CommentQuery>,
     Form(form): Form<CommentForm>,
 ) -> Result<impl IntoResponse> {
     if let Some(rate_limit_secs) = config.comments.rate_limit {
-        if !user.is_admin {
+        if !user.has_permission(role::COMMENTS_UNLIMITED, &config) {
             // TODO: this is really inefficient. Instead we should probably add
             // a separate data table for rate-limit "locks" per user.
             let mut comments = Comment::collection_at(parent, &db)?
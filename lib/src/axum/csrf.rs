@@ -0,0 +1,214 @@
+//! Cross-site request forgery protection.
+//!
+//! Each client gets a random token stored in a private (encrypted) cookie.
+//! State-changing requests must echo that token back, either through the
+//! `X-CSRF-Token` header or a `csrf_token` form field. Cross-site requests
+//! can't read the cookie and so can't provide a matching token.
+//!
+//! # Usage with templates
+//!
+//! Use the `CsrfToken` extractor in page handlers and render the token into
+//! forms:
+//!
+//! ```ignore
+//! <form hx-post="/login">
+//!     {{ csrf.field()|safe }}
+//!     ...
+//! </form>
+//! ```
+//!
+//! With htmx it's often more convenient to set the header once for the whole
+//! page:
+//!
+//! ```ignore
+//! <body hx-headers='{{ csrf.hx_headers()|safe }}'>
+//! ```
+
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::body::Body;
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::request::Parts;
+use axum::http::{header::CONTENT_TYPE, Method};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum_extra::extract::cookie::{Cookie, Key, SameSite};
+use axum_extra::extract::PrivateCookieJar;
+use rand::RngCore;
+
+use crate::error::{Error, ErrorKind};
+use crate::Config;
+
+pub const COOKIE_NAME: &str = "csrf";
+pub const HEADER_NAME: &str = "X-CSRF-Token";
+pub const FIELD_NAME: &str = "csrf_token";
+
+/// Maximum size of form body buffered for token lookup.
+const BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Token tied to the current client.
+#[derive(Clone, Debug)]
+pub struct CsrfToken(pub String);
+
+impl CsrfToken {
+    pub fn new() -> Self {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Self(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    pub fn value(&self) -> &str {
+        &self.0
+    }
+
+    /// Renders a hidden form input carrying the token.
+    pub fn field(&self) -> String {
+        format!(
+            r#"<input type="hidden" name="{}" value="{}">"#,
+            FIELD_NAME, self.0
+        )
+    }
+
+    /// Renders a value for the htmx `hx-headers` attribute.
+    pub fn hx_headers(&self) -> String {
+        format!(r#"{{"{}": "{}"}}"#, HEADER_NAME, self.0)
+    }
+
+    /// Compares tokens in constant time.
+    fn matches(&self, other: &str) -> bool {
+        self.0.len() == other.len()
+            && self
+                .0
+                .bytes()
+                .zip(other.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+impl std::fmt::Display for CsrfToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Makes the token available to handlers. Requires the `middleware` to be
+/// installed, which `start` and `start_with` do if enabled in config.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CsrfToken {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<CsrfToken>().cloned().ok_or(
+            ErrorKind::Other("csrf token unavailable, is the middleware installed?".to_string())
+                .into(),
+        )
+    }
+}
+
+/// Issues the token cookie if missing and verifies the token on
+/// state-changing requests.
+pub async fn middleware(State(key): State<Key>, request: Request, next: Next) -> Response {
+    let config = request
+        .extensions()
+        .get::<Arc<Config>>()
+        .expect("config extension unavailable")
+        .clone();
+
+    let jar = PrivateCookieJar::from_headers(request.headers(), key);
+    let (token, issued) = match jar.get(COOKIE_NAME) {
+        Some(cookie) => (CsrfToken(cookie.value().to_string()), false),
+        None => (CsrfToken::new(), true),
+    };
+
+    let request = if requires_verification(&request, &config) {
+        match verify(request, &token, issued).await {
+            Ok(request) => request,
+            Err(e) => return e.into_response(),
+        }
+    } else {
+        request
+    };
+
+    let mut request = request;
+    request.extensions_mut().insert(token.clone());
+
+    let response = next.run(request).await;
+
+    if issued {
+        let cookie = Cookie::build((COOKIE_NAME, token.0))
+            .same_site(SameSite::Lax)
+            .path("/")
+            .secure(true)
+            .http_only(true)
+            .build();
+        (jar.add(cookie), response).into_response()
+    } else {
+        response
+    }
+}
+
+fn requires_verification(request: &Request, config: &Config) -> bool {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    ) {
+        return false;
+    }
+
+    // bearer-authenticated requests don't carry ambient credentials
+    if request.headers().contains_key(http::header::AUTHORIZATION) {
+        return false;
+    }
+
+    let path = request.uri().path();
+    !config
+        .csrf
+        .exempt
+        .iter()
+        .any(|prefix| path.starts_with(prefix.as_str()))
+}
+
+async fn verify(request: Request, token: &CsrfToken, issued: bool) -> Result<Request, Error> {
+    // newly issued token couldn't have been sent by the client
+    if issued {
+        log::debug!("csrf: missing token cookie");
+        return Err(ErrorKind::Forbidden.into());
+    }
+
+    if let Some(header) = request.headers().get(HEADER_NAME) {
+        return if token.matches(header.to_str().unwrap_or_default()) {
+            Ok(request)
+        } else {
+            log::debug!("csrf: header token mismatch");
+            Err(ErrorKind::Forbidden.into())
+        };
+    }
+
+    let is_form = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| h.starts_with(mime::APPLICATION_WWW_FORM_URLENCODED.essence_str()));
+    if !is_form {
+        log::debug!("csrf: no token provided");
+        return Err(ErrorKind::Forbidden.into());
+    }
+
+    // buffer the form body to look up the token field, then put it back for
+    // the handler to consume
+    let (parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, BODY_LIMIT)
+        .await
+        .map_err(|e| ErrorKind::BadInput(e.to_string()))?;
+    let matched = url::form_urlencoded::parse(&bytes)
+        .find(|(name, _)| name == FIELD_NAME)
+        .is_some_and(|(_, value)| token.matches(&value));
+    if !matched {
+        log::debug!("csrf: form token mismatch");
+        return Err(ErrorKind::Forbidden.into());
+    }
+
+    Ok(Request::from_parts(parts, Body::from(bytes)))
+}
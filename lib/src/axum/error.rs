@@ -2,7 +2,7 @@ use axum::body::HttpBody;
 use axum::http::Uri;
 use axum::response::{AppendHeaders, Html, IntoResponse, Redirect, Response};
 use axum::Json;
-use http::header::SET_COOKIE;
+use http::header::{RETRY_AFTER, SET_COOKIE};
 use http::StatusCode;
 
 use crate::{routes, Error, ErrorKind};
@@ -53,6 +53,16 @@ impl IntoResponse for Error {
                 (StatusCode::FORBIDDEN, Html(self.to_string())).into_response()
             }
 
+            ErrorKind::TooManyLoginAttempts(retry_after) => {
+                tracing::debug!("{}", self.to_string());
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    AppendHeaders([(RETRY_AFTER, retry_after.to_string())]),
+                    Html(self.kind.to_string()),
+                )
+                    .into_response()
+            }
+
             ErrorKind::FailedGettingTokenCookie(target_url) => {
                 tracing::debug!("{}", self.to_string());
                 // Save redirection target to a cookie so that we can perform
@@ -70,6 +80,10 @@ impl IntoResponse for Error {
                 tracing::trace!("{}", self.to_string());
                 Redirect::to(&format!("{}?msg=Registration closed", routes::LOGIN)).into_response()
             }
+            ErrorKind::UserWithEmailAlreadyExists(_) => {
+                tracing::trace!("{}", self.to_string());
+                (StatusCode::CONFLICT, Html(self.to_string())).into_response()
+            }
             ErrorKind::BadInput(e) => {
                 tracing::trace!("{}", self.to_string());
                 (StatusCode::BAD_REQUEST, Html(self.to_string())).into_response()
@@ -1,8 +1,11 @@
+pub mod permission;
+pub mod scope;
 pub mod user;
 
-pub use user::User;
+pub use permission::{require, Require};
+pub use scope::Scoped;
+pub use user::{Impersonation, User};
 
 // TODO
 // pub use tower_http::request_id::RequestId;
-// pub use user::Admin;
 // pub use user::UserId;
@@ -0,0 +1,130 @@
+//! Extractor and middleware enforcing user permissions.
+//!
+//! Permissions are represented at the type level with marker types,
+//! declared using the `permission!` macro:
+//!
+//! ```ignore
+//! micron::permission!(ModerateComments, "comments:moderate");
+//!
+//! async fn hide_comment(user: Require<ModerateComments>) -> Result<()> {
+//!     // only reached by users with a role granting `comments:moderate`
+//! }
+//! ```
+//!
+//! Whole routers can be protected using the `require` middleware:
+//!
+//! ```ignore
+//! let admin = Router::new()
+//!     .route("/admin/users", get(users))
+//!     .route_layer(axum::middleware::from_fn(require::<Admin>));
+//! ```
+
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use axum_extra::extract::cookie::Key as CookieKey;
+
+use crate::api::Scope;
+use crate::error::{Error, ErrorKind};
+use crate::user::role;
+use crate::Config;
+
+use super::user::authenticate;
+use super::User;
+
+/// Type-level marker for a required permission.
+pub trait Permission {
+    const NAME: &'static str;
+}
+
+/// Declares a permission marker type for use with `Require` and `require`.
+#[macro_export]
+macro_rules! permission {
+    ($vis:vis $name:ident, $permission:expr) => {
+        $vis struct $name;
+
+        impl $crate::axum::extract::permission::Permission for $name {
+            const NAME: &'static str = $permission;
+        }
+    };
+}
+
+permission!(pub Admin, "*");
+permission!(pub CommentsUnlimited, role::COMMENTS_UNLIMITED);
+
+/// Extracts the logged in user, rejecting the request with `403` if they
+/// lack the required permission.
+pub struct Require<P: Permission>(pub User, PhantomData<fn() -> P>);
+
+impl<P: Permission> Require<P> {
+    pub fn into_inner(self) -> User {
+        self.0
+    }
+}
+
+impl<P: Permission> Deref for Require<P> {
+    type Target = User;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<P: Permission> DerefMut for Require<P> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[async_trait]
+impl<S, P> FromRequestParts<S> for Require<P>
+where
+    S: Send + Sync,
+    CookieKey: FromRef<S>,
+    P: Permission,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let auth = authenticate(parts, state).await?;
+        let config = parts
+            .extensions
+            .get::<Arc<Config>>()
+            .expect("config extension unavailable");
+
+        if !auth.user.has_permission(P::NAME, config) {
+            log::debug!("user {} is missing permission: {}", auth.user.id, P::NAME);
+            return Err(ErrorKind::Forbidden.into());
+        }
+
+        // tokens need to be explicitly issued for administrative actions
+        if P::NAME == Admin::NAME && !auth.allows(Scope::Admin) {
+            log::debug!("token is missing required scope: {}", Scope::Admin);
+            return Err(ErrorKind::Forbidden.into());
+        }
+        auth.check_method(&parts.method)?;
+
+        Ok(Self(User(auth.user), PhantomData))
+    }
+}
+
+/// Middleware rejecting requests from users lacking the required
+/// permission. Meant to be applied with `Router::route_layer`.
+pub async fn require<P: Permission>(
+    Extension(key): Extension<CookieKey>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+    if let Err(e) = Require::<P>::from_request_parts(&mut parts, &key).await {
+        return e.into_response();
+    }
+    next.run(Request::from_parts(parts, body)).await
+}
@@ -0,0 +1,104 @@
+//! Extractor enforcing token scopes.
+//!
+//! ```ignore
+//! async fn spend(user: Scoped<User, scope::CreditsSpend>) -> Result<()> {
+//!     // only reached with a session cookie or a token having
+//!     // `credits:spend` scope
+//! }
+//! ```
+
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use axum::async_trait;
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use axum_extra::extract::cookie::Key as CookieKey;
+
+use crate::api::Scope;
+use crate::error::{Error, ErrorKind};
+
+use super::user::authenticate;
+
+/// Type-level marker for a required scope.
+pub trait RequiredScope {
+    const SCOPE: Scope;
+}
+
+macro_rules! required_scopes {
+    ($($scope:ident),* $(,)?) => {
+        $(
+            pub struct $scope;
+
+            impl RequiredScope for $scope {
+                const SCOPE: Scope = Scope::$scope;
+            }
+        )*
+    };
+}
+
+required_scopes!(
+    UserRead,
+    UserWrite,
+    CreditsRead,
+    CreditsSpend,
+    OrdersRead,
+    OrdersWrite,
+    CommentsRead,
+    CommentsWrite,
+    Admin,
+);
+
+/// Wraps another extractor, rejecting the request with `403` if it was
+/// authenticated with a token lacking the required scope.
+pub struct Scoped<T, S: RequiredScope>(pub T, PhantomData<fn() -> S>);
+
+impl<T, S: RequiredScope> Scoped<T, S> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T, S: RequiredScope> Deref for Scoped<T, S> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T, S: RequiredScope> DerefMut for Scoped<T, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[async_trait]
+impl<St, T, S> FromRequestParts<St> for Scoped<T, S>
+where
+    St: Send + Sync,
+    CookieKey: FromRef<St>,
+    T: FromRequestParts<St>,
+    T::Rejection: IntoResponse,
+    S: RequiredScope,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &St) -> Result<Self, Self::Rejection> {
+        let auth = authenticate(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        if !auth.allows(S::SCOPE) {
+            log::debug!("token is missing required scope: {}", S::SCOPE);
+            return Err(Error::new(ErrorKind::Forbidden).into_response());
+        }
+
+        let inner = T::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        Ok(Self(inner, PhantomData))
+    }
+}
@@ -6,7 +6,7 @@ use std::sync::Arc;
 use axum::extract::{FromRef, FromRequest, FromRequestParts};
 use axum::http::header::AUTHORIZATION;
 use axum::http::request::Parts;
-use axum::http::{Request, StatusCode};
+use axum::http::{Method, Request, StatusCode};
 use axum::response::Redirect;
 use axum::{async_trait, Extension};
 use axum_auth::AuthBearer;
@@ -17,6 +17,7 @@ use log::debug;
 use tracing::{error, warn};
 use uuid::Uuid;
 
+use crate::api::Scope;
 use crate::auth::TokenMeta;
 use crate::db::{decode, Database};
 use crate::error::{Error, ErrorKind};
@@ -24,7 +25,15 @@ use crate::user::User as RawUser;
 use crate::util::token_expired;
 use crate::Config;
 
+/// Logged in user.
+///
+/// State-changing requests authenticated with a bearer token are rejected
+/// unless the token has at least one write scope, see `Auth::check_method`.
+///
+/// During an impersonation session this is the impersonated user, use the
+/// `Impersonation` extractor to get the admin acting as them.
 #[derive(Default, Clone, Debug, Deserialize, Serialize)]
+#[serde(transparent)]
 pub struct User(pub RawUser);
 
 impl Deref for User {
@@ -53,6 +62,169 @@ impl Into<RawUser> for User {
     }
 }
 
+/// Location of the token the request was authenticated with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TokenSource {
+    /// Private session cookie set on login.
+    Cookie,
+    /// Token presented with the `Authorization: Bearer` header.
+    Bearer,
+    /// Dev mode autologin, no token involved.
+    Autologin,
+}
+
+/// Authentication information resolved for the current request.
+///
+/// It's cached in request extensions so that multiple extractors used with
+/// the same handler don't repeat the lookups.
+#[derive(Clone, Debug)]
+pub struct Auth {
+    pub user: RawUser,
+    pub token: Option<TokenMeta>,
+    pub source: TokenSource,
+    /// Admin impersonating the user, see `auth::impersonate`.
+    pub impersonator: Option<RawUser>,
+}
+
+impl Auth {
+    /// Checks whether the request is allowed to act within provided scope.
+    ///
+    /// Session cookies implicitly have full scope, bearer tokens are limited
+    /// to the scopes they were issued with.
+    pub fn allows(&self, scope: Scope) -> bool {
+        match self.source {
+            TokenSource::Cookie | TokenSource::Autologin => true,
+            TokenSource::Bearer => self.token.as_ref().is_some_and(|t| t.allows(scope)),
+        }
+    }
+
+    /// Checks whether the request is allowed to change state, i.e. it's
+    /// either a session request or the token has at least one write scope.
+    pub fn allows_writes(&self) -> bool {
+        match self.source {
+            TokenSource::Cookie | TokenSource::Autologin => true,
+            TokenSource::Bearer => self
+                .token
+                .as_ref()
+                .is_some_and(|t| t.scopes.iter().any(Scope::is_write)),
+        }
+    }
+
+    /// Rejects state-changing requests made with read-only tokens.
+    ///
+    /// Handlers needing a particular scope should use the `Scoped`
+    /// extractor, this is only a baseline applied to every authenticated
+    /// request.
+    pub fn check_method(&self, method: &Method) -> Result<(), Error> {
+        if !method.is_safe() && !self.allows_writes() {
+            log::debug!("read-only token used with {method} request");
+            return Err(ErrorKind::Forbidden.into());
+        }
+        Ok(())
+    }
+}
+
+/// Resolves the user making the request, either through a bearer token or
+/// the session cookie.
+pub async fn authenticate<S: Send + Sync>(parts: &mut Parts, state: &S) -> Result<Auth, Error>
+where
+    CookieKey: FromRef<S>,
+{
+    if let Some(auth) = parts.extensions.get::<Auth>() {
+        return Ok(auth.clone());
+    }
+
+    let db = parts
+        .extensions
+        .get::<Arc<Database>>()
+        .expect("database extension unavailable")
+        .clone();
+    let config = parts
+        .extensions
+        .get::<Arc<Config>>()
+        .expect("config extension unavailable")
+        .clone();
+
+    // autologin functionality for faster development, can be set in config
+    if let Some(autologin_email) = &config.dev.autologin {
+        debug!("attempting autologin, uri: {}", parts.uri);
+        let users = db.get_collection::<RawUser>()?;
+        if let Some(user) = users.into_iter().find(|u| &u.email == autologin_email) {
+            let auth = Auth {
+                user,
+                token: None,
+                source: TokenSource::Autologin,
+                impersonator: None,
+            };
+            parts.extensions.insert(auth.clone());
+            return Ok(auth);
+        } else {
+            return Err(ErrorKind::AuthFailed(format!(
+                "autologin: provided user email that doesn't exist: {}",
+                autologin_email
+            ))
+            .into());
+        }
+    }
+
+    // first see if the bearer token is presented with authorization header
+    let (token, source) = if let Ok(token) = AuthBearer::from_request_parts(parts, state).await {
+        (token.0, TokenSource::Bearer)
+    } else {
+        // otherwise try accessing cookie jar and extracting the token cookie
+        let jar: PrivateCookieJar<CookieKey> = PrivateCookieJar::from_request_parts(parts, state)
+            .await
+            .unwrap();
+
+        let cookie = jar
+            .get("token")
+            .ok_or(ErrorKind::FailedGettingTokenCookie(parts.uri.clone()))?;
+
+        (cookie.value().to_string(), TokenSource::Cookie)
+    };
+
+    let mut token = db.get::<TokenMeta>(Uuid::from_str(&token)?).map_err(|_| {
+        Error::new(ErrorKind::AuthFailed(
+            "failed getting token meta from db".to_string(),
+        ))
+    })?;
+
+    // check if token hasn't expired
+    if token_expired(&db, &token) {
+        return Err(ErrorKind::AuthFailed("token expired".to_string()).into());
+    }
+
+    // slide the expiry, writes are throttled to keep active sessions from
+    // hitting the database on every request
+    if token.touch(config.auth.session.touch_interval) {
+        db.set(&token)?;
+    }
+
+    // make sure the impersonating admin is still an admin
+    let impersonator = match token.impersonator {
+        Some(admin) => {
+            let admin = db.get::<RawUser>(admin)?;
+            if !admin.is_admin || admin.is_disabled {
+                return Err(
+                    ErrorKind::AuthFailed("impersonator is not an admin".to_string()).into(),
+                );
+            }
+            Some(admin)
+        }
+        None => None,
+    };
+
+    let auth = Auth {
+        user: db.get::<RawUser>(token.user_id)?,
+        token: Some(token),
+        source,
+        impersonator,
+    };
+    parts.extensions.insert(auth.clone());
+
+    Ok(auth)
+}
+
 #[async_trait]
 impl<S: Send + Sync> FromRequestParts<S> for User
 where
@@ -62,62 +234,34 @@ where
 {
     type Rejection = Error;
 
-    async fn from_request_parts(mut parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let db = parts
-            .extensions
-            .get::<Arc<Database>>()
-            .expect("database extension unavailable")
-            .clone();
-        let config = parts
-            .extensions
-            .get::<Arc<Config>>()
-            .expect("config extension unavailable")
-            .clone();
-
-        // autologin functionality for faster development, can be set in config
-        if let Some(autologin_email) = &config.dev.autologin {
-            debug!("attempting autologin, uri: {}", parts.uri);
-            let users = db.get_collection::<RawUser>()?;
-            if let Some(user) = users.into_iter().find(|u| &u.email == autologin_email) {
-                return Ok(User(user));
-            } else {
-                return Err(ErrorKind::AuthFailed(format!(
-                    "autologin: provided user email that doesn't exist: {}",
-                    autologin_email
-                ))
-                .into());
-            }
-        }
-
-        // first see if the bearer token is presented with authorization header
-        let token = if let Ok(token) = AuthBearer::from_request_parts(parts, state).await {
-            token.0
-        } else {
-            // otherwise try accessing cookie jar and extracting the token cookie
-            let jar: PrivateCookieJar<CookieKey> =
-                PrivateCookieJar::from_request_parts(&mut parts, state)
-                    .await
-                    .unwrap();
-
-            let cookie = jar
-                .get("token")
-                .ok_or(ErrorKind::FailedGettingTokenCookie(parts.uri.clone()))?;
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let auth = authenticate(parts, state).await?;
+        auth.check_method(&parts.method)?;
+        Ok(User(auth.user))
+    }
+}
 
-            cookie.value().to_string()
-        };
+/// Admin impersonating the logged in user, if any. See
+/// `auth::impersonate`.
+#[derive(Clone, Debug)]
+pub struct Impersonation(pub Option<RawUser>);
 
-        let token = db.get::<TokenMeta>(Uuid::from_str(&token)?).map_err(|_| {
-            Error::new(ErrorKind::AuthFailed(
-                "failed getting token meta from db".to_string(),
-            ))
-        })?;
+impl Impersonation {
+    pub fn is_active(&self) -> bool {
+        self.0.is_some()
+    }
+}
 
-        // check if token hasn't expired
-        if token_expired(&db, &token) {
-            return Err(ErrorKind::AuthFailed("token expired".to_string()).into());
-        }
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Impersonation
+where
+    CookieKey: FromRef<S>,
+{
+    type Rejection = Error;
 
-        db.get::<RawUser>(token.user_id).map(|u| User(u))
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let auth = authenticate(parts, state).await?;
+        Ok(Impersonation(auth.impersonator))
     }
 }
 
@@ -1,24 +1,51 @@
 use std::collections::HashSet;
+use std::str::FromStr;
 
 use axum::{
     extract::{Path, Query},
     response::{IntoResponse, Redirect},
     routing::{get, post},
-    Extension, Form,
+    Extension, Form, Json,
 };
-use http::HeaderMap;
+use http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use http::{HeaderMap, Uri};
 use uuid::Uuid;
 
-use crate::{auth::ConfirmationKey, email::list::Subscriber, ErrorKind};
+use crate::config::ListVisibility;
+use crate::email::campaign::Campaign;
+use crate::email::list::{self, Subscriber};
+use crate::email::sequence;
+use crate::email::suppression::{self, Provider};
+use crate::email::tracking::{self, EventKind};
+use crate::{auth::ConfirmationKey, util, ErrorKind};
 use crate::{Result, Router};
 
-use super::{ConfigExt, DbExt};
+use super::csrf::CsrfToken;
+use super::extract::permission::Admin;
+use super::extract::{self, scope, Require, Scoped};
+use super::{confirmation_page, ConfigExt, DbExt};
 
 pub fn router() -> Router {
     Router::new()
         .route("/mailing/subscribe", post(subscribe))
-        .route("/mailing/unsubscribe/:subscriber", get(unsubscribe))
+        // post enables one-click unsubscribe from within email clients, see
+        // RFC 8058, get only asks for confirmation
+        .route(
+            "/mailing/unsubscribe/:subscriber",
+            get(confirm_unsubscribe).post(unsubscribe),
+        )
+        .route(
+            "/mailing/unsubscribe/sequence/:enrollment",
+            get(confirm_unsubscribe).post(unsubscribe_sequence),
+        )
         .route("/mailing/confirm/:key", get(confirm))
+        .route("/events/email/:provider", post(feedback))
+        .route("/mailing/open/:campaign/:subscriber", get(track_open))
+        .route("/mailing/click/:campaign/:subscriber", get(track_click))
+        .route("/admin/campaigns/:campaign/stats", get(campaign_stats))
+        .route("/account/mailing", get(subscriptions))
+        .route("/account/mailing/:list/subscribe", post(subscribe_user))
+        .route("/account/mailing/:list/unsubscribe", post(unsubscribe_user))
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -36,15 +63,33 @@ pub async fn subscribe(
     // Consent is given as the caller is actively trying to register to a list
     subscriber.marketing_consent = true;
     // Add subscriber to provided selection of lists or to all of them if no
-    // selection is specified
-    subscriber.lists = form.lists.unwrap_or(config.mailing.lists.clone());
+    // selection is specified. Internal lists are never available here.
+    subscriber.lists = match form.lists {
+        Some(lists) => lists
+            .into_iter()
+            .filter(|list| config.mailing.is_public(list))
+            .collect(),
+        None => config
+            .mailing
+            .public_lists()
+            .map(|list| list.name.clone())
+            .collect(),
+    };
+    if subscriber.lists.is_empty() {
+        return Err(ErrorKind::BadInput("no mailing lists to subscribe to".to_string()).into());
+    }
 
     db.set(&subscriber)?;
 
     // Perform email confirmation if required
     if config.mailing.confirmation {
         // send subscription confirmation email
-        crate::email::mailing_confirmation(subscriber.address, subscriber.id.to_string(), &config)?;
+        crate::email::mailing_confirmation(
+            subscriber.address,
+            subscriber.id.to_string(),
+            &config,
+            &db,
+        )?;
     }
 
     Ok("Sent!")
@@ -75,6 +120,17 @@ pub struct UnsubscribeQuery {
     pub lists: Option<HashSet<String>>,
 }
 
+/// Shows a page confirming the unsubscribe. Links in emails get visited by
+/// scanners and prefetchers, only submitting the form unsubscribes.
+pub async fn confirm_unsubscribe(csrf: Option<CsrfToken>, uri: Uri) -> impl IntoResponse {
+    confirmation_page(
+        "Stop receiving these emails?",
+        "Unsubscribe",
+        &uri.to_string(),
+        csrf.as_ref(),
+    )
+}
+
 pub async fn unsubscribe(
     Extension(db): DbExt,
     Path(subscriber): Path<Uuid>,
@@ -94,3 +150,186 @@ pub async fn unsubscribe(
 
     Ok("Success!")
 }
+
+/// Stops the email sequence, no further steps will be sent to the user.
+pub async fn unsubscribe_sequence(
+    Extension(db): DbExt,
+    Path(enrollment): Path<Uuid>,
+) -> Result<impl IntoResponse> {
+    sequence::stop(enrollment, &db)?;
+    Ok("Success!")
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ListSubscription {
+    pub name: String,
+    pub description: String,
+    pub subscribed: bool,
+}
+
+/// Lists mailing lists available to the user along with their subscription
+/// status. Internal lists are only included if the user is subscribed.
+pub async fn subscriptions(
+    user: Scoped<extract::User, scope::UserRead>,
+    Extension(db): DbExt,
+    Extension(config): ConfigExt,
+) -> Result<impl IntoResponse> {
+    let subscribed = list::find_for_user(&user, &db)?
+        .map(|s| s.lists)
+        .unwrap_or_default();
+
+    let lists = config
+        .mailing
+        .lists
+        .iter()
+        .filter(|list| list.visibility == ListVisibility::Public || subscribed.contains(&list.name))
+        .map(|list| ListSubscription {
+            name: list.name.clone(),
+            description: list.description.clone(),
+            subscribed: subscribed.contains(&list.name),
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Json(lists))
+}
+
+pub async fn subscribe_user(
+    user: Scoped<extract::User, scope::UserWrite>,
+    Extension(db): DbExt,
+    Extension(config): ConfigExt,
+    Path(list): Path<String>,
+) -> Result<impl IntoResponse> {
+    if !config.mailing.is_public(&list) {
+        return Err(ErrorKind::BadInput(format!("unknown mailing list: {list}")).into());
+    }
+    list::subscribe_user(&user, &list, &db)?;
+    Ok("Subscribed")
+}
+
+pub async fn unsubscribe_user(
+    user: Scoped<extract::User, scope::UserWrite>,
+    Extension(db): DbExt,
+    Path(list): Path<String>,
+) -> Result<impl IntoResponse> {
+    list::unsubscribe_user(&user, &list, &db)?;
+    Ok("Unsubscribed")
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct FeedbackQuery {
+    pub token: Option<String>,
+}
+
+/// Receives bounce and complaint notifications from email providers, see
+/// `email::suppression` for supported formats.
+pub async fn feedback(
+    Extension(db): DbExt,
+    Extension(config): ConfigExt,
+    Path(provider): Path<String>,
+    Query(query): Query<FeedbackQuery>,
+    body: String,
+) -> Result<impl IntoResponse> {
+    let Some(secret) = &config.email.bounces.webhook_secret else {
+        return Err(ErrorKind::Forbidden.into());
+    };
+    if !query
+        .token
+        .is_some_and(|token| util::constant_time_eq(token.as_bytes(), secret.as_bytes()))
+    {
+        return Err(ErrorKind::Forbidden.into());
+    }
+
+    let provider = Provider::from_str(&provider)
+        .map_err(|_| ErrorKind::BadInput(format!("unknown email provider: {provider}")))?;
+
+    if provider == Provider::Ses {
+        suppression::verify_sns(&body).await?;
+        if let Some(url) = suppression::ses_subscription_url(&body) {
+            reqwest::get(url).await?.error_for_status()?;
+            return Ok("Subscription confirmed");
+        }
+    }
+
+    let notifications = suppression::parse(provider, &body)?;
+    suppression::record(&notifications, &provider.to_string(), &db)?;
+
+    Ok("Ok")
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct TrackingQuery {
+    pub url: Option<String>,
+    pub sig: String,
+}
+
+/// Serves the tracking pixel, recording the campaign open.
+pub async fn track_open(
+    Extension(db): DbExt,
+    Extension(config): ConfigExt,
+    Path((campaign, subscriber)): Path<(Uuid, Uuid)>,
+    Query(query): Query<TrackingQuery>,
+) -> impl IntoResponse {
+    // the pixel is served regardless, there's nothing to show to the reader
+    if tracking::verify(EventKind::Open, campaign, subscriber, None, &query.sig, &db) {
+        if let Err(e) = tracking::record(EventKind::Open, campaign, subscriber, None, &config, &db)
+        {
+            log::warn!("failed recording open of campaign {campaign}: {e}");
+        }
+    }
+
+    (
+        [
+            (CONTENT_TYPE, "image/gif"),
+            (CACHE_CONTROL, "no-store, no-cache, must-revalidate"),
+        ],
+        tracking::PIXEL,
+    )
+}
+
+/// Records the link click and redirects to the link target. Only links with
+/// a valid signature are followed.
+pub async fn track_click(
+    Extension(db): DbExt,
+    Extension(config): ConfigExt,
+    Path((campaign, subscriber)): Path<(Uuid, Uuid)>,
+    Query(query): Query<TrackingQuery>,
+) -> Result<impl IntoResponse> {
+    let Some(url) = query.url else {
+        return Err(ErrorKind::BadInput("missing link url".to_string()).into());
+    };
+    if !tracking::verify(
+        EventKind::Click,
+        campaign,
+        subscriber,
+        Some(&url),
+        &query.sig,
+        &db,
+    ) {
+        return Err(ErrorKind::BadInput("invalid tracking link".to_string()).into());
+    }
+
+    if let Err(e) = tracking::record(
+        EventKind::Click,
+        campaign,
+        subscriber,
+        Some(url.clone()),
+        &config,
+        &db,
+    ) {
+        log::warn!("failed recording click in campaign {campaign}: {e}");
+    }
+
+    Ok(Redirect::to(&url))
+}
+
+/// Returns aggregate open and click stats of the campaign.
+pub async fn campaign_stats(
+    _: Require<Admin>,
+    Extension(db): DbExt,
+    Path(campaign): Path<Uuid>,
+) -> Result<Json<tracking::Stats>> {
+    let campaign = db
+        .get::<Campaign>(campaign)
+        .map_err(|_| ErrorKind::BadInput(format!("unknown campaign: {campaign}")))?;
+    Ok(Json(tracking::stats(&campaign, &db)?))
+}
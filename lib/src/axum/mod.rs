@@ -1,5 +1,7 @@
+pub mod api;
 pub mod auth;
 pub mod comment;
+pub mod csrf;
 pub mod extract;
 pub mod image;
 pub mod mailing;
@@ -14,8 +16,12 @@ mod error;
 
 pub use extract::user::User;
 
-use std::{net::ToSocketAddrs, sync::Arc};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
 
+use axum::extract::ConnectInfo;
+use axum::http::HeaderMap;
+use axum::response::Html;
 use axum::Extension;
 
 use crate::Result;
@@ -54,6 +60,7 @@ pub fn router(mut router: Router, config: &Config) -> Router {
     router = conditional_merge("comment", router, comment::router(), config);
     router = conditional_merge("mailing", router, mailing::router(), config);
     router = conditional_merge("auth", router, auth::router(config), config);
+    router = conditional_merge("api", router, api::router(), config);
     conditional_merge("image", router, image::router(), config)
 }
 
@@ -67,6 +74,58 @@ fn conditional_merge(route: &str, routera: Router, routerb: Router, config: &Con
     }
 }
 
+/// Determines the address of the client making the request.
+///
+/// Connection info is only available if the application was started with
+/// `start` or `start_with`, or served with connect info otherwise.
+pub fn client_ip(
+    headers: &HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    config: &Config,
+) -> Option<String> {
+    if config.auth.lockout.trust_forwarded {
+        if let Some(forwarded) = headers
+            .get("X-Forwarded-For")
+            .and_then(|h| h.to_str().ok())
+            // the rightmost entry is the one appended by the trusted proxy,
+            // anything before it is controlled by the client
+            .and_then(|h| h.rsplit(',').next())
+        {
+            return Some(forwarded.trim().to_string());
+        }
+    }
+    connect_info.map(|ConnectInfo(addr)| addr.ip().to_string())
+}
+
+/// Renders a minimal page asking the user to confirm an action with a button
+/// submitting a POST form.
+///
+/// Meant for links sent out by email. Mail scanners and link prefetchers
+/// follow such links with GET requests, which must not change any state.
+pub(crate) fn confirmation_page(
+    message: &str,
+    button: &str,
+    action: &str,
+    csrf: Option<&csrf::CsrfToken>,
+) -> Html<String> {
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><meta name="viewport" content="width=device-width, initial-scale=1"></head>
+<body>
+<form method="post" action="{}">
+<p>{message}</p>
+{}
+<button type="submit">{button}</button>
+</form>
+</body>
+</html>"#,
+        // the action can carry a query string taken from the request
+        action.replace('&', "&amp;").replace('"', "&quot;"),
+        csrf.map(|t| t.field()).unwrap_or_default()
+    ))
+}
+
 /// Registers micron routes on the provided router, initializes application
 /// state and starts the web server.
 pub async fn start(mut router: Router, config: Config) -> Result<()> {
@@ -78,6 +137,18 @@ pub async fn start_with(db: Database, mut router: Router, config: Config) -> Res
         log::warn!("failed to initialize tracing (perhaps it was already initialized?): {e}")
     });
 
+    // Configs built in code don't go through deserialization checks
+    if let Some(provider) = config.oauth.providers.iter().find(|p| !p.has_valid_name()) {
+        return Err(crate::ErrorKind::Other(format!(
+            "invalid oauth provider name: `{}`",
+            provider.name
+        ))
+        .into());
+    }
+
+    crate::email::template::load(&config)?;
+    crate::email::dkim::load(&config)?;
+
     // Provide initial state as defined in config
     if config.init.enabled {
         crate::init::initialize(&config, &db)?;
@@ -117,6 +188,39 @@ pub async fn start_with(db: Database, mut router: Router, config: Config) -> Res
         );
     }
 
+    // Periodically delete accounts with expired deletion grace period
+    {
+        let config = config.clone();
+        let db = db.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                if let Err(e) = crate::user::data::process_scheduled_deletions(&config, &db) {
+                    log::error!("failed processing scheduled account deletions: {e}");
+                }
+            }
+        });
+    }
+
+    // Deliver queued outbound emails
+    tokio::spawn(crate::email::queue::worker(config.clone(), db.clone()));
+
+    // Queue due email sequence steps
+    {
+        let config = config.clone();
+        let db = db.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                if let Err(e) = crate::email::sequence::process(&config, &db) {
+                    log::error!("failed processing email sequences: {e}");
+                }
+            }
+        });
+    }
+
     // Encapsulate application state
     let addr = config.address;
 
@@ -130,10 +234,24 @@ pub async fn start_with(db: Database, mut router: Router, config: Config) -> Res
         router.layer(Extension(Arc::new(::stripe::Client::new(secret))))
     };
 
+    // Verify CSRF tokens on state-changing requests. Needs to be layered
+    // before (inside) the config extension so that it can read the config.
+    let router = if config.csrf.enabled {
+        router.layer(axum::middleware::from_fn_with_state(
+            key.clone(),
+            csrf::middleware,
+        ))
+    } else {
+        router
+    };
+
     let mut router = router
         // Register common state extension for all routes
         .layer(Extension(Arc::new(config)))
         .layer(Extension(Arc::new(db)))
+        // Needed by middleware that can't access router state, e.g.
+        // `extract::require`
+        .layer(Extension(key.clone()))
         .with_state(key);
 
     // Serve the application
@@ -141,5 +259,10 @@ pub async fn start_with(db: Database, mut router: Router, config: Config) -> Res
     let listener = tokio::net::TcpListener::bind(addr)
         .await
         .expect("failed binding to addr: {addr}");
-    axum::serve(listener, router).await.map_err(|e| e.into())
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .map_err(|e| e.into())
 }
@@ -11,13 +11,14 @@ use axum::{
 use chrono::Utc;
 use http::{Request, StatusCode};
 
+use crate::config::SequenceTrigger;
 use crate::{
     order::{self, Order},
     payment::Payment,
 };
-use crate::{payment, Result};
+use crate::{payment, Result, User};
 
-use super::{DbExt, Router};
+use super::{ConfigExt, DbExt, Router};
 
 struct StripeEvent(stripe::Event);
 
@@ -71,7 +72,11 @@ where
     }
 }
 
-async fn webhook(Extension(db): DbExt, StripeEvent(event): StripeEvent) -> Result<()> {
+async fn webhook(
+    Extension(db): DbExt,
+    Extension(config): ConfigExt,
+    StripeEvent(event): StripeEvent,
+) -> Result<()> {
     use stripe::{EventObject, EventType};
 
     match event.type_ {
@@ -99,8 +104,36 @@ async fn webhook(Extension(db): DbExt, StripeEvent(event): StripeEvent) -> Resul
                     db.set(payment)?;
 
                     // Start fulfiling the order payment is pointing at
-                    let mut order: Order = db.get(payment.order)?;
-                    order.fulfill(&db).await?;
+                    let order: Order = db.get(payment.order)?;
+                    order.clone().fulfill(&db).await?;
+
+                    // Let the customer know the payment went through. The
+                    // order is already fulfilled at this point, errors are
+                    // only logged so that the webhook isn't retried.
+                    let user = match db.get::<User>(order.user) {
+                        Ok(user) => user,
+                        Err(e) => {
+                            log::error!("failed getting user of order {}: {e}", order.id);
+                            return Ok(());
+                        }
+                    };
+                    if let Err(e) = crate::email::sequence::trigger(
+                        SequenceTrigger::OrderCompleted,
+                        &user,
+                        &config,
+                        &db,
+                    ) {
+                        log::error!("failed starting sequences for order {}: {e}", order.id);
+                    }
+                    if let Err(e) = crate::email::receipt(
+                        user.email,
+                        &order,
+                        user.currency.to_string(),
+                        &config,
+                        &db,
+                    ) {
+                        log::error!("failed sending receipt for order {}: {e}", order.id);
+                    }
                 } else {
                     // There's no payments linked to the session we got the
                     // event for, weird!
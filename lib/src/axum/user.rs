@@ -2,23 +2,89 @@ use std::sync::Arc;
 
 use axum::extract::Path;
 use axum::response::IntoResponse;
-use axum::routing::get;
-use axum::Extension;
+use axum::routing::{get, post};
+use axum::{Extension, Form};
 
 use crate::db::Database;
 use crate::Result;
 use crate::{Image, User, UserId};
 
-use super::extract;
-use super::{DbExt, Router};
+use super::extract::{self, scope, Scoped};
+use super::{ConfigExt, DbExt, Router};
 
 pub fn router() -> Router {
     Router::new()
         .route("/avatar", get(my_avatar))
         .route("/avatar/:user_id", get(avatar))
+        .route("/account/email", post(change_email))
+        .route("/account/export", get(export))
+        .route("/account/delete", post(delete))
+        .route("/account/delete/cancel", post(cancel_delete))
 }
 
-pub async fn my_avatar(user: extract::User, Extension(db): DbExt) -> Result<impl IntoResponse> {
+/// Downloads an archive with all the data tied to the user.
+pub async fn export(
+    user: Scoped<extract::User, scope::UserRead>,
+    Extension(db): DbExt,
+) -> Result<impl IntoResponse> {
+    let archive = crate::user::data::export(user.id, &db)?;
+    Ok((
+        axum::response::AppendHeaders([
+            (
+                axum::http::header::CONTENT_TYPE,
+                "application/zip".to_string(),
+            ),
+            (
+                axum::http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.zip\"", user.id),
+            ),
+        ]),
+        archive,
+    ))
+}
+
+/// Requests account deletion. The deletion needs to be confirmed with the
+/// link sent to the account email.
+pub async fn delete(
+    user: Scoped<extract::User, scope::UserWrite>,
+    Extension(db): DbExt,
+    Extension(config): ConfigExt,
+) -> Result<impl IntoResponse> {
+    crate::user::data::request_deletion(&user, &config, &db)?;
+    Ok("Confirmation link sent")
+}
+
+/// Cancels scheduled account deletion.
+pub async fn cancel_delete(
+    user: Scoped<extract::User, scope::UserWrite>,
+    Extension(db): DbExt,
+) -> Result<impl IntoResponse> {
+    let mut user = user.into_inner();
+    crate::user::data::cancel_deletion(&mut user, &db)?;
+    Ok("Deletion cancelled")
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ChangeEmailForm {
+    pub email: String,
+}
+
+/// Requests changing the account email. The change takes effect once the
+/// link sent to the new address is followed.
+pub async fn change_email(
+    user: Scoped<extract::User, scope::UserWrite>,
+    Extension(db): DbExt,
+    Extension(config): ConfigExt,
+    Form(form): Form<ChangeEmailForm>,
+) -> Result<impl IntoResponse> {
+    crate::auth::email_change::request(&user, form.email, &config, &db)?;
+    Ok("Confirmation link sent to the new address")
+}
+
+pub async fn my_avatar(
+    user: Scoped<extract::User, scope::UserRead>,
+    Extension(db): DbExt,
+) -> Result<impl IntoResponse> {
     let image = db.get::<Image>(user.avatar)?;
     Ok((
         axum::response::AppendHeaders([(axum::http::header::CONTENT_TYPE, "image/png")]),
@@ -1,8 +1,11 @@
-use std::{collections::HashSet, net::SocketAddr};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+};
 
 use serde::de::DeserializeOwned;
 
-use crate::{user::Plan, Result};
+use crate::{api::AuthDuration, user::Plan, Result};
 
 pub static CONFIG_FILE: &'static str = "micron.toml";
 
@@ -44,7 +47,9 @@ pub struct Config {
     pub routers: Routers,
 
     pub auth: Auth,
+    pub account: Account,
     pub oauth: Oauth,
+    pub csrf: Csrf,
 
     pub registration: Registration,
     pub comments: Comments,
@@ -62,6 +67,10 @@ pub struct Config {
     /// List of initial users.
     pub users: Vec<User>,
 
+    /// Roles assignable to users, keyed by role name. The `admin` role is
+    /// built-in and grants all permissions.
+    pub roles: HashMap<String, Role>,
+
     /// List of phrases/quotes to be showed randomly on the app pages, because
     /// why not.
     pub phrases: Vec<String>,
@@ -87,9 +96,12 @@ impl Default for Config {
             dev: DevMode::default(),
             plans: vec![],
             auth: Auth::default(),
+            account: Account::default(),
             oauth: Oauth::default(),
+            csrf: Csrf::default(),
             registration: Registration::default(),
             users: vec![],
+            roles: HashMap::new(),
             phrases: vec![],
             payments: Payments::default(),
             company: Company::default(),
@@ -157,6 +169,24 @@ pub struct User {
     pub avatar: Option<String>,
 }
 
+/// Named set of permissions.
+///
+/// ```toml
+/// [roles.moderator]
+/// description = "Keeps the discussions civil"
+/// permissions = ["comments:*", "users:read"]
+/// ```
+///
+/// Permissions are arbitrary strings defined by the application. Trailing
+/// `*` matches any permission with the preceding prefix, a lone `*` matches
+/// all permissions.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Role {
+    pub description: String,
+    pub permissions: Vec<String>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Assets {
@@ -268,7 +298,29 @@ pub struct Stripe {
     pub test_signing_secret: String,
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// Self-service account management.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Account {
+    /// Number of seconds between confirming account deletion and actually
+    /// deleting the data. The deletion can be cancelled in the meantime.
+    /// Zero deletes the account immediately upon confirmation.
+    pub deletion_grace_period: usize,
+    /// If true, comments of deleted users are removed. Otherwise they're
+    /// kept but no longer attributed to anyone.
+    pub delete_comments: bool,
+}
+
+impl Default for Account {
+    fn default() -> Self {
+        Self {
+            deletion_grace_period: 14 * 24 * 60 * 60,
+            delete_comments: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Auth {
     /// Switch defining whether user must have confirmed email to be able
@@ -282,6 +334,229 @@ pub struct Auth {
     /// successful oauth requires verified email information from the
     /// third-party provider.
     pub require_confirmed_email: bool,
+
+    /// Selects the way users authenticate with email. Defaults to
+    /// `password`.
+    pub mode: AuthMode,
+    /// Number of seconds after which an unused magic login link expires.
+    pub magic_link_expiry: usize,
+
+    /// Brute-force protection for password logins.
+    pub lockout: Lockout,
+
+    /// Session lifetime settings.
+    pub session: Session,
+
+    /// Rules new passwords have to satisfy.
+    pub password: PasswordPolicy,
+    /// Password hashing costs. Hashes created with different parameters are
+    /// upgraded on the next successful login.
+    pub argon2: Argon2,
+}
+
+impl Default for Auth {
+    fn default() -> Self {
+        Self {
+            require_confirmed_email: false,
+            mode: AuthMode::default(),
+            magic_link_expiry: 15 * 60,
+            lockout: Lockout::default(),
+            session: Session::default(),
+            password: PasswordPolicy::default(),
+            argon2: Argon2::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    /// Require at least one character that's neither a letter nor a digit.
+    pub require_symbol: bool,
+
+    /// Reject passwords found on the built-in list of most common
+    /// passwords.
+    pub deny_common: bool,
+    /// Additional passwords to reject, e.g. the application name. Matched
+    /// case-insensitively.
+    pub denylist: Vec<String>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 128,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            deny_common: true,
+            denylist: vec![],
+        }
+    }
+}
+
+/// Argon2id cost parameters. Defaults follow the OWASP recommendation.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Argon2 {
+    /// Memory size in KiB.
+    pub memory_cost: u32,
+    /// Number of iterations.
+    pub time_cost: u32,
+    /// Degree of parallelism.
+    pub parallelism: u32,
+}
+
+impl Default for Argon2 {
+    fn default() -> Self {
+        Self {
+            memory_cost: 19 * 1024,
+            time_cost: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Sessions expire after a period of inactivity rather than a fixed time
+/// after login. Each authenticated request moves the expiry forward.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Session {
+    /// Session duration for regular web logins.
+    pub duration: AuthDuration,
+    /// Session duration for web logins with "remember me" checked.
+    pub remember_duration: AuthDuration,
+
+    /// Minimum number of seconds between persisting token last-seen
+    /// timestamps. Limits database writes for active sessions.
+    pub touch_interval: usize,
+
+    /// Number of seconds after which an unused refresh token expires.
+    pub refresh_token_expiry: usize,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            duration: AuthDuration::Short,
+            remember_duration: AuthDuration::Long,
+            touch_interval: 60,
+            refresh_token_expiry: 90 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// Failed login attempts are counted per account and per client address.
+/// Once a counter reaches its threshold, further attempts are rejected for
+/// a period that doubles with each subsequent failure.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Lockout {
+    pub enabled: bool,
+
+    /// Number of failed attempts per account before it gets locked.
+    pub account_attempts: u32,
+    /// Number of failed attempts per client address before it gets locked.
+    pub ip_attempts: u32,
+
+    /// Initial lockout duration in seconds.
+    pub base_delay: usize,
+    /// Upper bound for the lockout duration in seconds.
+    pub max_delay: usize,
+    /// Number of seconds without failures after which counters are reset.
+    pub reset_after: usize,
+
+    /// Use `X-Forwarded-For` header to determine client address. Only enable
+    /// when running behind a single reverse proxy that appends to it, the
+    /// rightmost address in the header is used.
+    pub trust_forwarded: bool,
+
+    /// Send an email to the account owner when their account gets locked.
+    pub notify: bool,
+}
+
+impl Default for Lockout {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            account_attempts: 5,
+            ip_attempts: 20,
+            base_delay: 30,
+            max_delay: 60 * 60,
+            reset_after: 24 * 60 * 60,
+            trust_forwarded: false,
+            notify: false,
+        }
+    }
+}
+
+/// Email-based authentication modes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMode {
+    /// Classic email and password login.
+    #[default]
+    Password,
+    /// Passwordless login with single-use links sent to user email.
+    MagicLink,
+    /// Both of the above are available.
+    PasswordAndMagicLink,
+}
+
+impl AuthMode {
+    pub fn password(&self) -> bool {
+        matches!(self, Self::Password | Self::PasswordAndMagicLink)
+    }
+
+    pub fn magic_link(&self) -> bool {
+        matches!(self, Self::MagicLink | Self::PasswordAndMagicLink)
+    }
+}
+
+/// Cross-site request forgery protection for state-changing requests.
+///
+/// Disabled by default. Before enabling, make sure that all forms and
+/// scripts submitting to the application include the token, see
+/// `axum::csrf`, otherwise their requests get rejected with `403`.
+///
+/// ```toml
+/// [csrf]
+/// enabled = true
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Csrf {
+    pub enabled: bool,
+    /// Path prefixes excluded from verification, e.g. webhook endpoints
+    /// called by third-party services.
+    ///
+    /// Requests authenticated with a bearer token are always excluded as
+    /// they don't rely on ambient cookie credentials.
+    pub exempt: Vec<String>,
+}
+
+impl Default for Csrf {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            exempt: vec![
+                // token endpoints take credentials in the body and don't
+                // rely on cookies
+                "/api/auth".to_string(),
+                "/events/".to_string(),
+                // one-click unsubscribe requests come from mail providers
+                "/mailing/unsubscribe/".to_string(),
+            ],
+        }
+    }
 }
 
 /// OAuth2 authentication configuration, including ability to enable support
@@ -294,6 +569,37 @@ pub struct Oauth {
     pub facebook: OauthEntry,
     pub github: OauthEntry,
     pub google: OauthEntry,
+    /// Additional generic OAuth2/OIDC providers, e.g. Keycloak, Authentik
+    /// or Microsoft. Defined using `[[oauth.providers]]` tables.
+    #[serde(deserialize_with = "deserialize_providers")]
+    pub providers: Vec<OauthProvider>,
+}
+
+/// Names that can't be used for generic providers, as they're taken by
+/// built-in providers or other routes under `/login/`.
+pub const RESERVED_PROVIDER_NAMES: &[&str] = &["github", "google", "discord", "facebook", "email"];
+
+fn deserialize_providers<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Vec<OauthProvider>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let providers = <Vec<OauthProvider> as serde::Deserialize>::deserialize(deserializer)?;
+    if let Some(provider) = providers.iter().find(|p| !p.has_valid_name()) {
+        return Err(serde::de::Error::custom(format!(
+            "invalid oauth provider name: `{}`",
+            provider.name
+        )));
+    }
+    Ok(providers)
+}
+
+impl Oauth {
+    /// Finds a generic provider by name.
+    pub fn provider(&self, name: &str) -> Option<&OauthProvider> {
+        self.providers.iter().find(|p| p.name == name)
+    }
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -304,6 +610,119 @@ pub struct OauthEntry {
     pub client_secret: String,
 }
 
+/// Generic OAuth2/OIDC provider.
+///
+/// Endpoints are either discovered using the OIDC discovery document or
+/// provided explicitly. Explicit endpoints take precedence over discovered
+/// ones.
+///
+/// ```toml
+/// [[oauth.providers]]
+/// name = "keycloak"
+/// client_id = "micron"
+/// client_secret = "secret"
+/// discovery_url = "https://sso.example.com/realms/main"
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OauthProvider {
+    /// Name used in routes, e.g. `/login/keycloak`. Can't be one of the
+    /// built-in provider names or collide with other `/login/` routes, see
+    /// `RESERVED_PROVIDER_NAMES`.
+    pub name: String,
+    pub enabled: bool,
+    pub client_id: String,
+    pub client_secret: String,
+
+    /// Issuer url or full url of the OIDC discovery document. Issuer urls
+    /// get `/.well-known/openid-configuration` appended.
+    pub discovery_url: Option<String>,
+    pub auth_url: Option<String>,
+    pub token_url: Option<String>,
+    pub userinfo_url: Option<String>,
+
+    pub scopes: Vec<String>,
+    pub claims: OauthClaims,
+
+    /// Overrides the default `https://{domain}/auth/{name}` redirect url,
+    /// useful e.g. when running against a local mock server over plain http.
+    pub redirect_url: Option<String>,
+
+    /// Accept emails without the `email_verified` claim. Only enable for
+    /// providers that don't let users set unverified addresses, e.g. an
+    /// organization-managed directory.
+    pub trust_email: bool,
+}
+
+impl OauthProvider {
+    /// Checks that the name is not empty or reserved.
+    pub fn has_valid_name(&self) -> bool {
+        !self.name.is_empty() && !RESERVED_PROVIDER_NAMES.contains(&self.name.as_str())
+    }
+}
+
+impl Default for OauthProvider {
+    fn default() -> Self {
+        Self {
+            name: "".to_string(),
+            enabled: true,
+            client_id: "".to_string(),
+            client_secret: "".to_string(),
+            discovery_url: None,
+            auth_url: None,
+            token_url: None,
+            userinfo_url: None,
+            scopes: vec![
+                "openid".to_string(),
+                "email".to_string(),
+                "profile".to_string(),
+            ],
+            claims: OauthClaims::default(),
+            redirect_url: None,
+            trust_email: false,
+        }
+    }
+}
+
+/// Mapping of userinfo claims to user information. Defaults follow the
+/// standard OIDC claims. Empty claim names are ignored.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OauthClaims {
+    pub subject: String,
+    pub email: String,
+    /// Claim checked for email verification status. Emails not marked as
+    /// verified are rejected, unless `OauthProvider::trust_email` is set.
+    pub email_verified: String,
+    pub name: String,
+    pub handle: String,
+    pub location: String,
+    pub avatar: String,
+}
+
+impl Default for OauthClaims {
+    fn default() -> Self {
+        Self {
+            subject: "sub".to_string(),
+            email: "email".to_string(),
+            email_verified: "email_verified".to_string(),
+            name: "name".to_string(),
+            handle: "preferred_username".to_string(),
+            location: "".to_string(),
+            avatar: "picture".to_string(),
+        }
+    }
+}
+
+/// Registration is closed by default. To let users sign up set `enabled`
+/// along with `email` and/or `oauth`.
+///
+/// ```toml
+/// [registration]
+/// enabled = true
+/// email = true
+/// oauth = true
+/// ```
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Registration {
@@ -316,6 +735,10 @@ pub struct Registration {
 
     /// Oauth2 registration switch, further configured through `config::oauth`.
     pub oauth: bool,
+
+    /// Require a valid invite code to sign up, see `auth::invite`. Other
+    /// registration paths are closed while enabled.
+    pub invite_only: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -341,48 +764,326 @@ pub struct Email {
     /// non-registered subscribers.
     pub address: String,
 
+    /// Transport used for sending emails, see `email::transport`.
+    pub transport: MailTransport,
+    /// Outbound queue settings, see `email::queue`.
+    pub queue: MailQueue,
+    /// Bounce and complaint handling, see `email::suppression`.
+    pub bounces: Bounces,
+    /// Signs outgoing messages if set, see `email::dkim`.
+    pub dkim: Option<Dkim>,
+
     // Smtp server and credentials.
     pub smtp_server: String,
     pub smtp_port: u16,
     pub smtp_user: String,
     pub smtp_password: String,
 
-    // Standard message overrides. The tuples are made out of subject,
-    // plain body and html body, in that order.
-    pub confirmation: Option<(String, String, String)>,
-    pub mailing_confirmation: Option<(String, String, String)>,
-    pub password_reset: Option<(String, String, String)>,
+    /// Directory with email templates overriding the built-in ones by
+    /// name, see `email::template`.
+    pub templates: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MailQueue {
+    /// Number of delivery attempts after which the message is moved to the
+    /// dead-letter state.
+    pub max_attempts: u32,
+    /// Delay before the first retry in seconds, doubled with each
+    /// subsequent failure.
+    pub base_delay: usize,
+    /// Upper bound for the retry delay in seconds.
+    pub max_delay: usize,
+
+    /// Maximum number of messages sent per minute through the transport.
+    pub rate_limit: Option<u32>,
+    /// Number of seconds between checking the queue for due messages.
+    pub poll_interval: usize,
+
+    /// Number of seconds sent messages are kept in the queue before being
+    /// removed. Dead messages are kept until removed manually.
+    pub keep_sent: usize,
+}
+
+impl Default for MailQueue {
+    fn default() -> Self {
+        Self {
+            max_attempts: 8,
+            base_delay: 30,
+            max_delay: 6 * 60 * 60,
+            rate_limit: None,
+            poll_interval: 5,
+            keep_sent: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// DKIM signing configuration.
+///
+/// ```toml
+/// [email.dkim]
+/// selector = "micron"
+/// private_key = "dkim.pem"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Dkim {
+    /// Selector under which the public key is published, i.e. the record
+    /// at `{selector}._domainkey.{domain}`.
+    pub selector: String,
+    /// Signing domain, defaults to the application domain.
+    pub domain: Option<String>,
+    /// Path to the private key. RSA keys are expected in PKCS#1 PEM format,
+    /// ed25519 keys as base64 encoded 32 byte secrets.
+    pub private_key: String,
+    pub algorithm: DkimAlgorithm,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DkimAlgorithm {
+    #[default]
+    Rsa,
+    Ed25519,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Bounces {
+    /// Secret expected in the `token` query parameter of bounce and
+    /// complaint webhook requests, e.g.
+    /// `https://example.com/events/email/ses?token=secret`. Webhooks are
+    /// disabled if not set. Apart from SES notifications, which are signed,
+    /// this is the only thing keeping anyone from adding addresses to the
+    /// suppression list, so keep it long and random.
+    pub webhook_secret: Option<String>,
+    /// Path to a maildir receiving delivery status notifications. New
+    /// messages are checked by the queue worker and moved to `cur` once
+    /// processed.
+    pub mailbox: Option<String>,
+}
+
+/// Selects the way outgoing emails are delivered.
+///
+/// ```toml
+/// [email.transport]
+/// type = "file"
+/// path = "mail"
+/// maildir = true
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MailTransport {
+    /// Authenticated SMTP relay using STARTTLS, configured with the
+    /// `smtp_*` settings.
+    #[default]
+    Smtp,
+    /// Unencrypted, unauthenticated SMTP, e.g. for local catchers like
+    /// MailHog. Uses `smtp_server` and `smtp_port`.
+    SmtpPlain,
+    /// Writes messages as `.eml` files into a directory, or into a maildir
+    /// structure if `maildir` is set.
+    File {
+        path: String,
+        #[serde(default)]
+        maildir: bool,
+    },
+    /// Pipes messages to a local `sendmail` compatible binary.
+    Sendmail {
+        #[serde(default)]
+        command: Option<String>,
+    },
+    /// Keeps messages in memory, see `email::transport::memory`.
+    Memory,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Mailing {
-    /// All maintained mailing lists that can be subscribed by users and
-    /// non-users alike.
-    pub lists: HashSet<String>,
+    /// All maintained mailing lists.
+    ///
+    /// ```toml
+    /// [[mailing.lists]]
+    /// name = "main"
+    /// description = "Product news and updates"
+    /// tracking = true
+    ///
+    /// [[mailing.lists]]
+    /// name = "beta"
+    /// description = "Early access testers"
+    /// visibility = "internal"
+    /// ```
+    ///
+    /// Public lists can also be given by name only, e.g. `lists = ["main"]`.
+    #[serde(deserialize_with = "deserialize_lists")]
+    pub lists: Vec<MailingList>,
 
     /// Require email confirmation upon subscribing as non-registered user.
     /// For unconfirmed subscriptions the email information will be removed
     /// from the database after a standard period.
     pub confirmation: bool,
+
+    /// Maximum number of campaign messages sent per minute.
+    pub send_rate: u32,
+
+    /// Automated email sequences, see `email::sequence`.
+    pub sequences: Vec<Sequence>,
 }
 
 impl Default for Mailing {
     fn default() -> Self {
-        let mut lists = HashSet::new();
-        lists.insert("main".to_string());
         Self {
-            lists,
+            lists: vec![MailingList {
+                name: "main".to_string(),
+                description: String::new(),
+                visibility: ListVisibility::Public,
+                tracking: false,
+            }],
             confirmation: true,
+            send_rate: 60,
+            sequences: vec![],
         }
     }
 }
 
+impl Mailing {
+    pub fn list(&self, name: &str) -> Option<&MailingList> {
+        self.lists.iter().find(|l| l.name == name)
+    }
+
+    pub fn list_names(&self) -> HashSet<String> {
+        self.lists.iter().map(|l| l.name.clone()).collect()
+    }
+
+    /// Lists that can be subscribed to by anyone.
+    pub fn public_lists(&self) -> impl Iterator<Item = &MailingList> {
+        self.lists
+            .iter()
+            .filter(|l| l.visibility == ListVisibility::Public)
+    }
+
+    pub fn is_public(&self, name: &str) -> bool {
+        self.list(name)
+            .is_some_and(|l| l.visibility == ListVisibility::Public)
+    }
+
+    /// Checks if tracking is enabled for all of the lists. Subscribers
+    /// receiving a campaign through any list with tracking disabled are not
+    /// tracked.
+    pub fn is_tracked<'a>(&self, lists: impl IntoIterator<Item = &'a String>) -> bool {
+        let mut lists = lists.into_iter().peekable();
+        lists.peek().is_some() && lists.all(|name| self.list(name).is_some_and(|l| l.tracking))
+    }
+}
+
+/// Accepts lists given by name only, as well as full definitions.
+fn deserialize_lists<'de, D>(deserializer: D) -> std::result::Result<Vec<MailingList>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Entry {
+        Name(String),
+        List(MailingList),
+    }
+
+    let entries = <Vec<Entry> as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| match entry {
+            Entry::Name(name) => MailingList {
+                name,
+                ..Default::default()
+            },
+            Entry::List(list) => list,
+        })
+        .collect())
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(default)]
 pub struct MailingList {
     pub name: String,
     pub description: String,
+    pub visibility: ListVisibility,
+    /// Track opens and link clicks of campaigns sent to the list, see
+    /// `email::tracking`. Disabled by default for subscriber privacy.
+    pub tracking: bool,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListVisibility {
+    /// Anyone can subscribe, either through the public subscription form or
+    /// from their account.
+    #[default]
+    Public,
+    /// Subscribers can only be added by administrators. Users already on
+    /// the list can still unsubscribe.
+    Internal,
+}
+
+/// Series of emails sent after a user lifecycle event.
+///
+/// ```toml
+/// [[mailing.sequences]]
+/// name = "onboarding"
+/// trigger = "registration"
+///
+/// [[mailing.sequences.steps]]
+/// template = "welcome"
+///
+/// [[mailing.sequences.steps]]
+/// template = "tips"
+/// delay = 259200 # 3 days
+///
+/// [[mailing.sequences.steps]]
+/// template = "upgrade"
+/// delay = 604800 # 7 days
+/// conditions = ["free_plan"]
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Sequence {
+    /// Unique name of the sequence.
+    pub name: String,
+    pub trigger: SequenceTrigger,
+    /// Mailing list the user needs to stay subscribed to. Leaving the list
+    /// stops the sequence.
+    pub list: Option<String>,
+    pub steps: Vec<SequenceStep>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SequenceTrigger {
+    #[default]
+    Registration,
+    EmailConfirmed,
+    OrderCompleted,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SequenceStep {
+    /// Name of the email template, see `email::template`.
+    pub template: String,
+    /// Number of seconds after the trigger the step is sent.
+    pub delay: usize,
+    /// Conditions the user needs to meet at the time of sending, otherwise
+    /// the step is skipped.
+    pub conditions: Vec<StepCondition>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepCondition {
+    EmailConfirmed,
+    EmailUnconfirmed,
+    FreePlan,
+    PaidPlan,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
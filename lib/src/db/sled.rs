@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use sled::{IVec, Tree};
@@ -14,12 +16,14 @@ pub struct SledDb {
 
 impl SledDb {
     pub fn new() -> Result<Self> {
-        let inner = sled::Config::default()
-            // TODO: specify this path better, perhaps relative to project root
-            // or relative to `app` dir root
-            .path("./db")
-            .open()
-            .expect("failed to open db");
+        // TODO: specify this path better, perhaps relative to project root
+        // or relative to `app` dir root
+        Self::open("./db")
+    }
+
+    /// Opens the database stored at the provided path.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let inner = sled::Config::default().path(path).open()?;
         Ok(Self { inner })
     }
 
@@ -134,6 +138,45 @@ impl SledDb {
         Ok(())
     }
 
+    /// Atomically updates an existing item using the provided function.
+    ///
+    /// If the item is changed by someone else in the meantime the update is
+    /// retried with the new value, so the function may be called multiple
+    /// times. Errors returned by the function abort the update.
+    pub fn update<T, F>(&self, id: Uuid, mut f: F) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned + Collectable,
+        F: FnMut(T) -> Result<T>,
+    {
+        let tree = self.inner.open_tree(T::get_collection_name())?;
+        loop {
+            let current = tree.get(id)?.ok_or(ErrorKind::DbError(format!(
+                "entity with id '{}' not found in collection {}",
+                id,
+                T::get_collection_name()
+            )))?;
+            let new = f(decode(&current)?)?;
+            if tree
+                .compare_and_swap(id, Some(current), Some(encode(&new)?))?
+                .is_ok()
+            {
+                return Ok(new);
+            }
+        }
+    }
+
+    /// Atomically removes an item, returning it. Fails if the item doesn't
+    /// exist, including when it was just removed by someone else.
+    pub fn take<T: DeserializeOwned + Collectable>(&self, id: Uuid) -> Result<T> {
+        let tree = self.inner.open_tree(T::get_collection_name())?;
+        let value = tree.remove(id)?.ok_or(ErrorKind::DbError(format!(
+            "entity with id '{}' not found in collection {}",
+            id,
+            T::get_collection_name()
+        )))?;
+        decode(&value)
+    }
+
     pub fn remove<T: Identifiable + Collectable>(&self, value: &T) -> Result<()> {
         self.remove_at(T::get_collection_name(), value)
     }
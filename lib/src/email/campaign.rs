@@ -0,0 +1,343 @@
+//! Newsletter campaigns sent to mailing list subscribers.
+//!
+//! Campaign content is written in markdown or html. It's rendered separately
+//! for each recipient and can make use of `{{ email }}` and
+//! `{{ unsubscribe_link }}` values. Messages are wrapped in the `campaign`
+//! template and carry `List-Unsubscribe` headers, allowing email clients to
+//! offer one-click unsubscribe as described in RFC 8058.
+//!
+//! Messages are put into the outbound queue, spread out according to the
+//! configured send rate, and delivered by the queue worker. Campaign progress
+//! is picked up from the status of the queued messages, see `update_progress`.
+//! An interrupted campaign can be resumed without sending duplicates.
+//!
+//! Opens and clicks are tracked for lists with tracking enabled, see
+//! `email::tracking`.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+use lettre::Message;
+use uuid::Uuid;
+
+use crate::db::{Collectable, Identifiable};
+use crate::error::{ErrorKind, Result};
+use crate::{Config, Database, User};
+
+use super::list::Subscriber;
+use super::queue::{QueueStatus, QueuedEmail};
+use super::suppression;
+use super::template::{self, EmailTemplate};
+use super::tracking;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum CampaignStatus {
+    /// Not yet started, recipients are not selected yet.
+    Draft,
+    Sending,
+    Completed,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContentFormat {
+    Markdown,
+    Html,
+}
+
+impl ContentFormat {
+    /// Guesses the format based on file extension, defaulting to markdown.
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".html") || path.ends_with(".htm") {
+            Self::Html
+        } else {
+            Self::Markdown
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Campaign {
+    pub id: Uuid,
+    pub subject: String,
+    /// Message content, without the surrounding layout.
+    pub content: String,
+    pub format: ContentFormat,
+    pub lists: HashSet<String>,
+
+    pub status: CampaignStatus,
+    /// Subscribers selected when the campaign was started.
+    pub recipients: Vec<Uuid>,
+    /// Recipients with the message waiting in the outbound queue, along with
+    /// the id of the queued message.
+    #[serde(default)]
+    pub queued: HashMap<Uuid, Uuid>,
+    pub sent: HashSet<Uuid>,
+    /// Recipients that unsubscribed or got suppressed before the message was
+    /// sent to them.
+    pub skipped: HashSet<Uuid>,
+    /// Recipients the sending failed for, along with the error. These are
+    /// retried when the campaign is resumed.
+    pub failed: HashMap<Uuid, String>,
+
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl Collectable for Campaign {
+    fn get_collection_name() -> &'static str {
+        "campaigns"
+    }
+}
+
+impl Identifiable for Campaign {
+    fn get_id(&self) -> Uuid {
+        self.id
+    }
+}
+
+impl Campaign {
+    pub fn new(
+        subject: String,
+        content: String,
+        format: ContentFormat,
+        lists: HashSet<String>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            subject,
+            content,
+            format,
+            lists,
+            status: CampaignStatus::Draft,
+            recipients: vec![],
+            queued: HashMap::new(),
+            sent: HashSet::new(),
+            skipped: HashSet::new(),
+            failed: HashMap::new(),
+            created_at: Utc::now(),
+            started_at: None,
+            completed_at: None,
+        }
+    }
+
+    /// Recipients that haven't received the campaign yet, nor have it
+    /// waiting in the queue.
+    pub fn remaining(&self) -> Vec<Uuid> {
+        self.recipients
+            .iter()
+            .filter(|id| {
+                !self.sent.contains(id)
+                    && !self.skipped.contains(id)
+                    && !self.queued.contains_key(id)
+            })
+            .copied()
+            .collect()
+    }
+}
+
+/// Finds subscribers that should receive messages sent to any of the lists.
+///
+/// Only subscribers with marketing consent are included and suppressed
+/// addresses are left out. Unconfirmed subscriptions are included only if
+/// they're linked to a registered user who already confirmed their email.
+/// Merely sharing the address with such a user is not enough, as anyone can
+/// subscribe any address through the anonymous form.
+pub fn recipients(lists: &HashSet<String>, db: &Database) -> Result<Vec<Subscriber>> {
+    let confirmed_users = db
+        .get_collection::<User>()?
+        .into_iter()
+        .filter(|u| u.email_confirmed)
+        .map(|u| u.id)
+        .collect::<HashSet<_>>();
+
+    let mut addresses = HashSet::new();
+    let recipients = db
+        .get_collection::<Subscriber>()?
+        .into_iter()
+        .filter(|s| s.marketing_consent && !s.lists.is_disjoint(lists))
+        .filter(|s| s.confirmed || s.user.is_some_and(|u| confirmed_users.contains(&u)))
+        .filter(|s| !suppression::is_suppressed(&s.address, db))
+        .filter(|s| addresses.insert(s.address.clone()))
+        .collect();
+
+    Ok(recipients)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CampaignEmail {
+    pub subject: String,
+    pub content: String,
+    pub unsubscribe_link: String,
+}
+
+impl EmailTemplate for CampaignEmail {
+    const NAME: &'static str = "campaign";
+}
+
+#[derive(Serialize)]
+struct RecipientContext<'a> {
+    email: &'a str,
+    unsubscribe_link: &'a str,
+}
+
+/// Renders the campaign message for a single subscriber.
+pub fn message(
+    campaign: &Campaign,
+    subscriber: &Subscriber,
+    config: &Config,
+    db: &Database,
+) -> Result<Message> {
+    let unsubscribe_link = super::link(config, &format!("/mailing/unsubscribe/{}", subscriber.id));
+
+    let context = tera::Context::from_serialize(RecipientContext {
+        email: &subscriber.address,
+        unsubscribe_link: &unsubscribe_link,
+    })
+    .map_err(|e| ErrorKind::Other(e.to_string()))?;
+    let mut content = tera::Tera::one_off(&campaign.content, &context, false)
+        .map_err(|e| ErrorKind::Other(format!("campaign content: {e}")))?;
+    if campaign.format == ContentFormat::Markdown {
+        content = markdown::to_html_with_options(&content, &markdown::Options::gfm())
+            .map_err(|e| ErrorKind::ParsingError(e.to_string()))?;
+    }
+    if tracking::is_tracked(campaign, subscriber, config) {
+        content = tracking::track(
+            &content,
+            campaign.id,
+            subscriber.id,
+            &[&unsubscribe_link],
+            config,
+            db,
+        )?;
+    }
+
+    let rendered = template::render(
+        &CampaignEmail {
+            subject: campaign.subject.clone(),
+            content,
+            unsubscribe_link: unsubscribe_link.clone(),
+        },
+        config,
+    )?;
+
+    let builder = super::with_unsubscribe(
+        super::builder(&subscriber.address, config)?,
+        &unsubscribe_link,
+    );
+    super::finish(builder, rendered, config)
+}
+
+/// Queues the campaign message for all remaining recipients. Messages are
+/// scheduled one after another according to the configured send rate.
+///
+/// Starting a draft campaign selects the recipients. Calling this again on
+/// an already started campaign resumes it, retrying failed recipients.
+pub fn send(campaign: &mut Campaign, config: &Config, db: &Database) -> Result<()> {
+    if campaign.status == CampaignStatus::Draft {
+        campaign.recipients = recipients(&campaign.lists, db)?
+            .into_iter()
+            .map(|s| s.id)
+            .collect();
+        campaign.started_at = Some(Utc::now());
+    }
+    campaign.status = CampaignStatus::Sending;
+    db.set(campaign)?;
+
+    let delay = Duration::milliseconds(60_000 / config.mailing.send_rate.max(1) as i64);
+    let mut next_attempt = Utc::now();
+
+    for id in campaign.remaining() {
+        // subscribers can leave while the campaign is being sent
+        let subscriber = match db.get::<Subscriber>(id) {
+            Ok(s)
+                if s.marketing_consent
+                    && !s.lists.is_disjoint(&campaign.lists)
+                    && !suppression::is_suppressed(&s.address, db) =>
+            {
+                s
+            }
+            _ => {
+                campaign.failed.remove(&id);
+                campaign.skipped.insert(id);
+                db.set(campaign)?;
+                continue;
+            }
+        };
+
+        match message(campaign, &subscriber, config, db) {
+            Ok(message) => {
+                let mut email = QueuedEmail::new(&message);
+                email.next_attempt = next_attempt;
+                db.set(&email)?;
+                next_attempt += delay;
+
+                campaign.failed.remove(&id);
+                campaign.queued.insert(id, email.id);
+            }
+            Err(e) => {
+                log::warn!(
+                    "failed preparing campaign {} for {}: {e}",
+                    campaign.id,
+                    subscriber.address
+                );
+                campaign.failed.insert(id, e.kind.to_string());
+            }
+        }
+        db.set(campaign)?;
+    }
+
+    update_progress(campaign, db)
+}
+
+/// Picks up the status of queued campaign messages. The campaign is
+/// completed once none of its messages are waiting in the queue.
+///
+/// Messages that ended up in the dead-letter state count as failed and are
+/// queued again when the campaign is resumed.
+pub fn update_progress(campaign: &mut Campaign, db: &Database) -> Result<()> {
+    if campaign.status != CampaignStatus::Sending {
+        return Ok(());
+    }
+
+    for (recipient, email) in campaign.queued.clone() {
+        match db.get::<QueuedEmail>(email) {
+            Ok(email) if email.status == QueueStatus::Pending => continue,
+            Ok(email) if email.status == QueueStatus::Sent => {
+                campaign.sent.insert(recipient);
+            }
+            Ok(email) => {
+                campaign
+                    .failed
+                    .insert(recipient, email.last_error.unwrap_or_default());
+            }
+            // dead messages can be purged before their status is picked up
+            Err(_) => {
+                campaign
+                    .failed
+                    .insert(recipient, "message removed from the queue".to_string());
+            }
+        }
+        campaign.queued.remove(&recipient);
+    }
+
+    if campaign.queued.is_empty() {
+        campaign.status = CampaignStatus::Completed;
+        campaign.completed_at = Some(Utc::now());
+    }
+    db.set(campaign)?;
+
+    Ok(())
+}
+
+/// Updates progress of all campaigns that are being sent, called by the
+/// queue worker.
+pub fn process(db: &Database) -> Result<()> {
+    for mut campaign in db.get_collection::<Campaign>()? {
+        if campaign.status == CampaignStatus::Sending {
+            update_progress(&mut campaign, db)?;
+        }
+    }
+    Ok(())
+}
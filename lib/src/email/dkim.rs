@@ -0,0 +1,154 @@
+//! DKIM signing of outgoing messages.
+//!
+//! With `config::Email::dkim` set, every message built in the `email` module
+//! is signed before being queued or sent. The public key needs to be
+//! published in DNS for receivers to verify the signature, `dns_record`
+//! returns the record to publish, e.g. through `micron mail dkim`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use base64::Engine;
+use lettre::message::dkim::{
+    DkimCanonicalization, DkimConfig, DkimSigningAlgorithm, DkimSigningKey,
+};
+use lettre::message::header::HeaderName;
+use lettre::Message;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::EncodePublicKey;
+
+use crate::config::{self, DkimAlgorithm};
+use crate::error::{ErrorKind, Result};
+use crate::Config;
+
+/// Headers covered by the signature, including the ones mailbox providers
+/// rely on for one-click unsubscribe.
+const SIGNED_HEADERS: &[&str] = &[
+    "From",
+    "Subject",
+    "To",
+    "Date",
+    "Reply-To",
+    "Message-ID",
+    "List-Unsubscribe",
+    "List-Unsubscribe-Post",
+];
+
+/// Signs the message if DKIM is configured.
+pub fn sign(message: &mut Message, config: &Config) -> Result<()> {
+    if let Some(dkim) = signing_config(config)? {
+        message.sign(&dkim);
+    }
+    Ok(())
+}
+
+/// Signing configs are built once per selector, domain and key file and kept
+/// for the lifetime of the application.
+fn cache() -> &'static RwLock<HashMap<(String, String, String), Arc<DkimConfig>>> {
+    static CACHE: OnceLock<RwLock<HashMap<(String, String, String), Arc<DkimConfig>>>> =
+        OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// Parses the private key ahead of time, so that a missing or invalid key is
+/// reported on startup instead of when the first email is sent.
+pub fn load(config: &Config) -> Result<()> {
+    signing_config(config).map(|_| ())
+}
+
+fn signing_config(config: &Config) -> Result<Option<Arc<DkimConfig>>> {
+    let Some(dkim) = &config.email.dkim else {
+        return Ok(None);
+    };
+
+    let key = (
+        dkim.selector.clone(),
+        domain(dkim, config),
+        dkim.private_key.clone(),
+    );
+    if let Some(dkim) = cache()
+        .read()
+        .ok()
+        .and_then(|cache| cache.get(&key).cloned())
+    {
+        return Ok(Some(dkim));
+    }
+
+    let private_key = DkimSigningKey::new(
+        &read_key(dkim)?,
+        match dkim.algorithm {
+            DkimAlgorithm::Rsa => DkimSigningAlgorithm::Rsa,
+            DkimAlgorithm::Ed25519 => DkimSigningAlgorithm::Ed25519,
+        },
+    )
+    .map_err(|e| ErrorKind::Other(format!("invalid dkim private key: {e}")))?;
+
+    let signing = Arc::new(DkimConfig::new(
+        key.0.clone(),
+        key.1.clone(),
+        private_key,
+        SIGNED_HEADERS
+            .iter()
+            .map(|name| HeaderName::new_from_ascii_str(name))
+            .collect(),
+        DkimCanonicalization::default(),
+    ));
+    if let Ok(mut cache) = cache().write() {
+        cache.insert(key, signing.clone());
+    }
+    Ok(Some(signing))
+}
+
+/// Returns the name and value of the DNS TXT record with the public key.
+pub fn dns_record(config: &Config) -> Result<(String, String)> {
+    let dkim = config
+        .email
+        .dkim
+        .as_ref()
+        .ok_or(ErrorKind::Other("dkim is not configured".to_string()))?;
+    let private_key = read_key(dkim)?;
+    let invalid =
+        |e: &dyn std::fmt::Display| ErrorKind::Other(format!("invalid dkim private key: {e}"));
+
+    let (k, public_key) = match dkim.algorithm {
+        DkimAlgorithm::Rsa => {
+            let key = rsa::RsaPrivateKey::from_pkcs1_pem(&private_key).map_err(|e| invalid(&e))?;
+            let der = key
+                .to_public_key()
+                .to_public_key_der()
+                .map_err(|e| invalid(&e))?;
+            ("rsa", der.as_bytes().to_vec())
+        }
+        DkimAlgorithm::Ed25519 => {
+            let secret: [u8; 32] = base64::engine::general_purpose::STANDARD
+                .decode(&private_key)
+                .map_err(|e| invalid(&e))?
+                .try_into()
+                .map_err(|_| invalid(&"expected 32 byte secret"))?;
+            let key = ed25519_dalek::SigningKey::from_bytes(&secret);
+            ("ed25519", key.verifying_key().to_bytes().to_vec())
+        }
+    };
+
+    Ok((
+        format!("{}._domainkey.{}", dkim.selector, domain(dkim, config)),
+        format!(
+            "v=DKIM1; k={k}; p={}",
+            base64::engine::general_purpose::STANDARD.encode(public_key)
+        ),
+    ))
+}
+
+fn domain(dkim: &config::Dkim, config: &Config) -> String {
+    dkim.domain.clone().unwrap_or(config.domain.clone())
+}
+
+fn read_key(dkim: &config::Dkim) -> Result<String> {
+    let key = std::fs::read_to_string(&dkim.private_key).map_err(|e| {
+        ErrorKind::Other(format!(
+            "failed reading dkim private key {}: {e}",
+            dkim.private_key
+        ))
+    })?;
+    Ok(key.trim().to_string())
+}
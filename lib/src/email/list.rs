@@ -12,6 +12,7 @@ use std::collections::HashSet;
 use uuid::Uuid;
 
 use crate::db::{Collectable, Identifiable};
+use crate::{util, Database, ErrorKind, Result, User, UserId};
 
 /// A standard subscriber definition.
 ///
@@ -24,6 +25,11 @@ pub struct Subscriber {
 
     pub address: String,
 
+    /// Registered user the subscription belongs to. The address of a linked
+    /// subscription follows the user email.
+    #[serde(default)]
+    pub user: Option<UserId>,
+
     /// If confirmation is required, unconfirmed subscribers will be cleaned
     /// regularly.
     pub confirmed: bool,
@@ -45,6 +51,7 @@ impl Subscriber {
         Self {
             id: Uuid::new_v4(),
             address,
+            user: None,
             confirmed: false,
             lists: Default::default(),
             marketing_consent: false,
@@ -64,3 +71,74 @@ impl Identifiable for Subscriber {
         self.id
     }
 }
+
+/// Finds the subscription belonging to the user.
+///
+/// Subscriptions made with the user email before registering, or through
+/// the anonymous subscription form, get linked to the user along the way.
+/// That only happens once the user confirmed owning the address, otherwise
+/// anyone could claim someone else's subscription by signing up with their
+/// address.
+pub fn find_for_user(user: &User, db: &Database) -> Result<Option<Subscriber>> {
+    let subscribers = db.get_collection::<Subscriber>()?;
+    if let Some(subscriber) = subscribers.iter().find(|s| s.user == Some(user.id)) {
+        return Ok(Some(subscriber.clone()));
+    }
+    if !user.email_confirmed {
+        return Ok(None);
+    }
+
+    match subscribers
+        .into_iter()
+        .find(|s| s.user.is_none() && util::same_email(&s.address, &user.email))
+    {
+        Some(mut subscriber) => {
+            subscriber.user = Some(user.id);
+            db.set(&subscriber)?;
+            Ok(Some(subscriber))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Adds the user to the list. Since the user is acting from within their
+/// account no additional confirmation is needed, consent is implied.
+pub fn subscribe_user(user: &User, list: &str, db: &Database) -> Result<Subscriber> {
+    let mut subscriber = find_for_user(user, db)?.unwrap_or_else(|| {
+        let mut subscriber = Subscriber::new(user.email.clone());
+        subscriber.user = Some(user.id);
+        subscriber
+    });
+    subscriber.confirmed = subscriber.confirmed || user.email_confirmed;
+    subscriber.marketing_consent = true;
+    subscriber.lists.insert(list.to_string());
+    db.set(&subscriber)?;
+    Ok(subscriber)
+}
+
+/// Removes the user from the list. The subscription is removed altogether
+/// once there are no more lists left.
+pub fn unsubscribe_user(user: &User, list: &str, db: &Database) -> Result<()> {
+    let Some(mut subscriber) = find_for_user(user, db)? else {
+        return Err(ErrorKind::BadInput(format!("not subscribed to list: {list}")).into());
+    };
+    subscriber.lists.remove(list);
+    if subscriber.lists.is_empty() {
+        db.remove(&subscriber)?;
+    } else {
+        db.set(&subscriber)?;
+    }
+    Ok(())
+}
+
+/// Updates the address of subscriptions linked to the user, called after
+/// the user email changes.
+pub fn update_address(user_id: UserId, address: &str, db: &Database) -> Result<()> {
+    for mut subscriber in db.get_collection::<Subscriber>()? {
+        if subscriber.user == Some(user_id) {
+            subscriber.address = address.to_string();
+            db.set(&subscriber)?;
+        }
+    }
+    Ok(())
+}
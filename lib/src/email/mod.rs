@@ -1,53 +1,72 @@
-use futures::TryFutureExt;
 use lettre::{
     address::AddressError,
-    message::{MultiPart, SinglePart},
-    transport::smtp::authentication::Credentials,
-    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor, Transport,
+    message::{
+        header::{Header, HeaderName, HeaderValue},
+        MessageBuilder, MultiPart, SinglePart,
+    },
+    Message,
 };
+use rust_decimal::Decimal;
 
+use crate::order::Order;
 use crate::{Error, ErrorKind, Result};
 
+pub mod campaign;
+pub mod dkim;
 pub mod list;
+pub mod queue;
+pub mod sequence;
+pub mod suppression;
+pub mod template;
+pub mod tracking;
+pub mod transport;
 
-pub async fn send_async(message: Message, config: crate::config::Email) -> Result<()> {
-    let creds = Credentials::new(config.smtp_user.clone(), config.smtp_password.clone());
-
-    // Open a remote connection to mail server
-    let mailer = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_server)
-        .map_err(|e| Error::new(ErrorKind::Other(e.to_string())))?
-        .port(config.smtp_port)
-        .credentials(creds)
-        .build();
-
-    // Send the email
-    let response = mailer.send(message).await?;
-    if response.is_positive() {
-        Ok(())
-    } else {
-        Err(ErrorKind::EmailBadResponse(response.code().to_string()).into())
+pub use template::EmailTemplate;
+pub use transport::Mailer;
+
+/// Sends the message right away using the transport selected in config,
+/// bypassing the queue.
+pub async fn send_async(
+    message: Message,
+    config: crate::config::Email,
+    db: &crate::Database,
+) -> Result<()> {
+    if let Some(address) = suppressed_recipient(&message, db) {
+        return Err(ErrorKind::EmailSuppressed(address).into());
     }
+    transport::mailer(&config)?.send(message).await
 }
 
-/// Sends an email message containing a link used for email confirmation.
-pub fn confirmation(email_addr: String, key: String, config: &crate::Config) -> Result<()> {
-    let (subject, plain_body, html_body) = config
-        .email
-        .confirmation
-        .clone()
-        .map(|(s, plain, html)| (s, plain.replace("{key}", &key), html.replace("{key}", &key)))
-        .unwrap_or((
-            format!("Welcome to {}", config.domain),
-            format!(
-                "You have created a new account at {}. Click the link below to confirm your\n\
-            email address and activate your account:\n\n\
-            https://{}/confirm/{}",
-                config.domain, config.domain, key
-            ),
-            format!("unimplemented"),
-        ));
-
-    let message = Message::builder()
+/// Returns the first recipient of the message found on the suppression list.
+fn suppressed_recipient(message: &Message, db: &crate::Database) -> Option<String> {
+    message
+        .envelope()
+        .to()
+        .iter()
+        .map(|a| a.to_string())
+        .find(|a| suppression::is_suppressed(a, db))
+}
+
+/// Renders the template into a message addressed to provided email.
+pub fn message<T: EmailTemplate>(
+    email_addr: &str,
+    template: &T,
+    config: &crate::Config,
+) -> Result<Message> {
+    let rendered = template::render(template, config)?;
+    finish(builder(email_addr, config)?, rendered, config)
+}
+
+/// Prepares a message addressed to provided email, with the sender set
+/// according to config. Messages get their own `Message-ID`, so that
+/// bounces can be matched to them, see `suppression::process_mailbox`.
+pub(crate) fn builder(email_addr: &str, config: &crate::Config) -> Result<MessageBuilder> {
+    Ok(Message::builder()
+        .message_id(Some(format!(
+            "<{}@{}>",
+            uuid::Uuid::new_v4(),
+            config.domain
+        )))
         .from(
             format!("{} <{}>", config.name, config.email.address)
                 .parse()
@@ -60,72 +79,321 @@ pub fn confirmation(email_addr: String, key: String, config: &crate::Config) ->
         )
         .to(email_addr
             .parse()
-            .map_err(|e: AddressError| Error::new(ErrorKind::EmailParseError(e.to_string())))?)
-        .subject(subject)
-        .multipart(
-            MultiPart::alternative()
-                .singlepart(SinglePart::plain(plain_body))
-                .singlepart(SinglePart::html(html_body)),
-        )?;
-
-    let email_config = config.email.clone();
-    tokio::spawn(async move {
-        if let Err(e) = send_async(message, email_config).await {
-            log::error!("{e}")
-        }
-    });
+            .map_err(|e: AddressError| Error::new(ErrorKind::EmailParseError(e.to_string())))?))
+}
+
+/// Finishes the message with the rendered subject and alternative plain text
+/// and html bodies, signing it if DKIM is configured.
+pub(crate) fn finish(
+    builder: MessageBuilder,
+    rendered: template::Rendered,
+    config: &crate::Config,
+) -> Result<Message> {
+    let mut message = builder.subject(rendered.subject).multipart(
+        MultiPart::alternative()
+            .singlepart(SinglePart::plain(rendered.plain))
+            .singlepart(SinglePart::html(rendered.html)),
+    )?;
+    dkim::sign(&mut message, config)?;
+    Ok(message)
+}
+
+/// Adds headers allowing email clients to offer unsubscribing, including
+/// one-click unsubscribe described in RFC 8058. The link needs to accept
+/// post requests.
+pub(crate) fn with_unsubscribe(builder: MessageBuilder, link: &str) -> MessageBuilder {
+    builder
+        .header(ListUnsubscribe(format!("<{link}>")))
+        .header(ListUnsubscribePost)
+}
+
+/// `List-Unsubscribe` header, see RFC 2369.
+#[derive(Clone, Debug)]
+struct ListUnsubscribe(String);
 
+impl Header for ListUnsubscribe {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe")
+    }
+
+    fn parse(s: &str) -> std::result::Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self(s.to_string()))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), self.0.clone())
+    }
+}
+
+/// `List-Unsubscribe-Post` header signaling one-click unsubscribe support,
+/// see RFC 8058.
+#[derive(Clone, Debug)]
+struct ListUnsubscribePost;
+
+impl Header for ListUnsubscribePost {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("List-Unsubscribe-Post")
+    }
+
+    fn parse(_: &str) -> std::result::Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self)
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), "List-Unsubscribe=One-Click".to_string())
+    }
+}
+
+/// Renders the template and puts the message into the outbound queue.
+pub fn send<T: EmailTemplate>(
+    email_addr: &str,
+    template: &T,
+    config: &crate::Config,
+    db: &crate::Database,
+) -> Result<()> {
+    if suppression::is_suppressed(email_addr, db) {
+        log::info!(
+            "not sending {} email to suppressed address {email_addr}",
+            T::NAME
+        );
+        return Ok(());
+    }
+    let message = message(email_addr, template, config)?;
+    queue::push(&message, db)?;
     Ok(())
 }
 
+pub(crate) fn link(config: &crate::Config, path: &str) -> String {
+    format!("https://{}{}", config.domain, path)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Confirmation {
+    pub link: String,
+}
+
+impl EmailTemplate for Confirmation {
+    const NAME: &'static str = "confirmation";
+}
+
 /// Sends an email message containing a link used for email confirmation.
-pub fn mailing_confirmation(email_addr: String, key: String, config: &crate::Config) -> Result<()> {
-    let (subject, plain_body, html_body) = config
-        .email
-        .mailing_confirmation
-        .clone()
-        .map(|(s, plain, html)| (s, plain.replace("{key}", &key), html.replace("{key}", &key)))
-        .unwrap_or((
-            format!(
-                "Hi! Please confirm your {} newsletter subscription",
-                config.domain
-            ),
-            format!(
-                "Looks like you have requested to receive an occasional email from us.\n\
-                Click the link below to confirm your email address and activate your subscription:\n\n\
-            https://{}/mailing/confirm/{}",
-                config.domain, key
-            ),
-            format!("unimplemented"),
-        ));
-
-    let message = Message::builder()
-        .from(
-            format!("{} <{}>", config.name, config.email.address)
-                .parse()
-                .map_err(|e: AddressError| Error::new(ErrorKind::EmailParseError(e.to_string())))?,
-        )
-        .reply_to(
-            format!("noreply <noreply@{}>", config.domain)
-                .parse()
-                .map_err(|e: AddressError| Error::new(ErrorKind::EmailParseError(e.to_string())))?,
-        )
-        .to(email_addr
-            .parse()
-            .map_err(|e: AddressError| Error::new(ErrorKind::EmailParseError(e.to_string())))?)
-        .subject(subject)
-        .multipart(
-            MultiPart::alternative()
-                .singlepart(SinglePart::plain(plain_body))
-                .singlepart(SinglePart::html(html_body)),
-        )?;
-
-    let email_config = config.email.clone();
-    tokio::spawn(async move {
-        if let Err(e) = send_async(message, email_config).await {
-            log::error!("{e}")
+pub fn confirmation(
+    email_addr: String,
+    key: String,
+    config: &crate::Config,
+    db: &crate::Database,
+) -> Result<()> {
+    let template = Confirmation {
+        link: link(config, &format!("/confirm/{key}")),
+    };
+    send(&email_addr, &template, config, db)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MailingConfirmation {
+    pub link: String,
+}
+
+impl EmailTemplate for MailingConfirmation {
+    const NAME: &'static str = "mailing_confirmation";
+}
+
+/// Sends an email message containing a link used for email confirmation.
+pub fn mailing_confirmation(
+    email_addr: String,
+    key: String,
+    config: &crate::Config,
+    db: &crate::Database,
+) -> Result<()> {
+    let template = MailingConfirmation {
+        link: link(config, &format!("/mailing/confirm/{key}")),
+    };
+    send(&email_addr, &template, config, db)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PasswordReset {
+    pub link: String,
+}
+
+impl EmailTemplate for PasswordReset {
+    const NAME: &'static str = "password_reset";
+}
+
+/// Sends an email message containing a link used for resetting the
+/// password.
+pub fn password_reset(
+    email_addr: String,
+    key: String,
+    config: &crate::Config,
+    db: &crate::Database,
+) -> Result<()> {
+    let template = PasswordReset {
+        link: link(config, &format!("/reset/{key}")),
+    };
+    send(&email_addr, &template, config, db)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MagicLink {
+    pub link: String,
+    pub expiry_minutes: usize,
+}
+
+impl EmailTemplate for MagicLink {
+    const NAME: &'static str = "magic_link";
+}
+
+/// Sends an email message containing a single-use login link.
+pub fn magic_link(
+    email_addr: String,
+    key: String,
+    config: &crate::Config,
+    db: &crate::Database,
+) -> Result<()> {
+    let template = MagicLink {
+        link: link(config, &format!("/login/email/{key}")),
+        expiry_minutes: config.auth.magic_link_expiry / 60,
+    };
+    send(&email_addr, &template, config, db)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Lockout {
+    pub until: String,
+}
+
+impl EmailTemplate for Lockout {
+    const NAME: &'static str = "lockout";
+}
+
+/// Notifies the account owner that their account was temporarily locked
+/// after too many failed login attempts.
+pub fn lockout(
+    email_addr: String,
+    until: chrono::DateTime<chrono::Utc>,
+    config: &crate::Config,
+    db: &crate::Database,
+) -> Result<()> {
+    let template = Lockout {
+        until: until.format("%Y-%m-%d %H:%M UTC").to_string(),
+    };
+    send(&email_addr, &template, config, db)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EmailChange {
+    pub link: String,
+}
+
+impl EmailTemplate for EmailChange {
+    const NAME: &'static str = "email_change";
+}
+
+/// Sends an email message containing a link confirming the new account
+/// email address.
+pub fn email_change(
+    email_addr: String,
+    key: String,
+    config: &crate::Config,
+    db: &crate::Database,
+) -> Result<()> {
+    let template = EmailChange {
+        link: link(config, &format!("/confirm/{key}")),
+    };
+    send(&email_addr, &template, config, db)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EmailChangeNotice {
+    pub new_email: String,
+}
+
+impl EmailTemplate for EmailChangeNotice {
+    const NAME: &'static str = "email_change_notice";
+}
+
+/// Notifies the current account email address about a requested change.
+pub fn email_change_notice(
+    email_addr: String,
+    new_email: String,
+    config: &crate::Config,
+    db: &crate::Database,
+) -> Result<()> {
+    send(&email_addr, &EmailChangeNotice { new_email }, config, db)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AccountDeletion {
+    pub link: String,
+    pub grace_days: usize,
+}
+
+impl EmailTemplate for AccountDeletion {
+    const NAME: &'static str = "account_deletion";
+}
+
+/// Sends an email message containing a link confirming account deletion.
+pub fn account_deletion(
+    email_addr: String,
+    key: String,
+    config: &crate::Config,
+    db: &crate::Database,
+) -> Result<()> {
+    let template = AccountDeletion {
+        link: link(config, &format!("/confirm/{key}")),
+        grace_days: config.account.deletion_grace_period / (24 * 60 * 60),
+    };
+    send(&email_addr, &template, config, db)
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Receipt {
+    pub order_id: String,
+    pub date: String,
+    pub items: Vec<ReceiptItem>,
+    pub total: Decimal,
+    pub currency: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ReceiptItem {
+    pub name: String,
+    pub amount: usize,
+    pub cost: Decimal,
+}
+
+impl EmailTemplate for Receipt {
+    const NAME: &'static str = "receipt";
+}
+
+impl Receipt {
+    pub fn new(order: &Order, currency: String) -> Self {
+        Self {
+            order_id: order.id.to_string(),
+            date: order.time.format("%Y-%m-%d").to_string(),
+            items: order
+                .items
+                .iter()
+                .map(|item| ReceiptItem {
+                    name: item.name(),
+                    amount: item.amount(),
+                    cost: item.cost(),
+                })
+                .collect(),
+            total: order.total_cost(),
+            currency,
         }
-    });
+    }
+}
 
-    Ok(())
+/// Sends a receipt for the paid order.
+pub fn receipt(
+    email_addr: String,
+    order: &Order,
+    currency: String,
+    config: &crate::Config,
+    db: &crate::Database,
+) -> Result<()> {
+    send(&email_addr, &Receipt::new(order, currency), config, db)
 }
@@ -0,0 +1,305 @@
+//! Persistent outbound email queue.
+//!
+//! Messages are stored in the db before being sent, so that they survive
+//! transport failures and application restarts. The queue worker delivers
+//! due messages, retrying failed ones with exponential backoff. Messages
+//! that keep failing end up in the dead-letter state, where they stay until
+//! retried or removed manually, e.g. with `micron mail queue`.
+
+use std::collections::VecDeque;
+use std::time::{Duration as StdDuration, Instant};
+
+use chrono::{DateTime, Duration, Utc};
+use lettre::address::{AddressError, Envelope};
+use lettre::message::header;
+use lettre::{Address, Message};
+use uuid::Uuid;
+
+use crate::db::{Collectable, Identifiable};
+use crate::error::{ErrorKind, Result};
+use crate::{config, Config, Database};
+
+use super::suppression;
+use super::transport::{self, Mailer};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum QueueStatus {
+    /// Waiting for the next delivery attempt.
+    Pending,
+    Sent,
+    /// Delivery failed too many times.
+    Dead,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuedEmail {
+    pub id: Uuid,
+    pub from: Option<String>,
+    pub to: Vec<String>,
+    pub subject: String,
+    /// `Message-ID` header of the message, used for matching bounces.
+    #[serde(default)]
+    pub message_id: Option<String>,
+    /// Formatted message, ready to be sent over the wire.
+    pub raw: Vec<u8>,
+
+    pub status: QueueStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+
+    pub created_at: DateTime<Utc>,
+    pub next_attempt: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+impl Collectable for QueuedEmail {
+    fn get_collection_name() -> &'static str {
+        "email_queue"
+    }
+}
+
+impl Identifiable for QueuedEmail {
+    fn get_id(&self) -> Uuid {
+        self.id
+    }
+}
+
+impl QueuedEmail {
+    pub fn new(message: &Message) -> Self {
+        let envelope = message.envelope();
+        Self {
+            id: Uuid::new_v4(),
+            from: envelope.from().map(|a| a.to_string()),
+            to: envelope.to().iter().map(|a| a.to_string()).collect(),
+            subject: message
+                .headers()
+                .get::<header::Subject>()
+                .map(|subject| subject.as_ref().to_string())
+                .unwrap_or_default(),
+            message_id: message.headers().get_raw("Message-ID").map(str::to_string),
+            raw: message.formatted(),
+            status: QueueStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            created_at: Utc::now(),
+            next_attempt: Utc::now(),
+            sent_at: None,
+        }
+    }
+
+    pub fn envelope(&self) -> Result<Envelope> {
+        let parse = |a: &String| -> Result<Address> {
+            a.parse()
+                .map_err(|e: AddressError| ErrorKind::EmailParseError(e.to_string()).into())
+        };
+        let from = self.from.as_ref().map(parse).transpose()?;
+        let to = self.to.iter().map(parse).collect::<Result<Vec<_>>>()?;
+        Envelope::new(from, to).map_err(|e| ErrorKind::EmailParseError(e.to_string()).into())
+    }
+
+    fn is_due(&self) -> bool {
+        self.status == QueueStatus::Pending && self.next_attempt <= Utc::now()
+    }
+}
+
+/// Stores the message in the queue for the worker to deliver.
+pub fn push(message: &Message, db: &Database) -> Result<QueuedEmail> {
+    let email = QueuedEmail::new(message);
+    db.set(&email)?;
+    Ok(email)
+}
+
+/// Limits the number of messages sent within a minute.
+#[derive(Default)]
+pub struct RateLimiter {
+    limit: Option<u32>,
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: Option<u32>) -> Self {
+        Self {
+            limit,
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Records the send if the limit allows it.
+    fn acquire(&mut self) -> bool {
+        let Some(limit) = self.limit else {
+            return true;
+        };
+        while self
+            .sent
+            .front()
+            .is_some_and(|t| t.elapsed() >= StdDuration::from_secs(60))
+        {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= limit as usize {
+            return false;
+        }
+        self.sent.push_back(Instant::now());
+        true
+    }
+}
+
+/// Attempts delivery of all due messages. Returns the number of messages
+/// sent.
+pub async fn process(
+    mailer: &dyn Mailer,
+    limiter: &mut RateLimiter,
+    config: &config::MailQueue,
+    db: &Database,
+) -> Result<usize> {
+    let mut due = db
+        .get_collection::<QueuedEmail>()?
+        .into_iter()
+        .filter(|e| e.is_due())
+        .collect::<Vec<_>>();
+    due.sort_by_key(|e| e.next_attempt);
+
+    let mut sent = 0;
+    for mut email in due {
+        if !limiter.acquire() {
+            break;
+        }
+
+        // the address could have been suppressed after queueing
+        if let Some(address) = email.to.iter().find(|a| suppression::is_suppressed(a, db)) {
+            email.status = QueueStatus::Dead;
+            email.last_error = Some(ErrorKind::EmailSuppressed(address.clone()).to_string());
+            db.set(&email)?;
+            continue;
+        }
+
+        email.attempts += 1;
+        let result = match email.envelope() {
+            Ok(envelope) => mailer.send_raw(&envelope, &email.raw).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => {
+                email.status = QueueStatus::Sent;
+                email.sent_at = Some(Utc::now());
+                email.last_error = None;
+                sent += 1;
+            }
+            Err(e) => {
+                log::warn!(
+                    "failed sending email {} to {:?} (attempt {}): {e}",
+                    email.id,
+                    email.to,
+                    email.attempts
+                );
+                email.last_error = Some(e.kind.to_string());
+                if email.attempts >= config.max_attempts {
+                    log::error!("email {} moved to dead letters", email.id);
+                    email.status = QueueStatus::Dead;
+                } else {
+                    email.next_attempt = Utc::now() + backoff(email.attempts, config);
+                }
+            }
+        }
+        db.set(&email)?;
+    }
+
+    Ok(sent)
+}
+
+/// Delivers all due messages right away using the transport selected in
+/// config, ignoring the rate limit.
+///
+/// The worker is only started along with the server, this lets tests
+/// driving the router directly deliver queued messages, e.g. to inspect
+/// them with `transport::memory`.
+pub async fn flush(config: &Config, db: &Database) -> Result<usize> {
+    let mailer = transport::mailer(&config.email)?;
+    process(
+        mailer.as_ref(),
+        &mut RateLimiter::new(None),
+        &config.email.queue,
+        db,
+    )
+    .await
+}
+
+/// Delay before the next attempt, doubling with each failed one.
+fn backoff(attempts: u32, config: &config::MailQueue) -> Duration {
+    let delay = config
+        .base_delay
+        .saturating_mul(2usize.saturating_pow(attempts.saturating_sub(1)))
+        .min(config.max_delay);
+    Duration::seconds(delay as i64)
+}
+
+/// Removes sent messages older than the configured retention period.
+pub fn cleanup(config: &config::MailQueue, db: &Database) -> Result<()> {
+    let cutoff = Utc::now() - Duration::seconds(config.keep_sent as i64);
+    for email in db.get_collection::<QueuedEmail>()? {
+        if email.status == QueueStatus::Sent && email.sent_at.is_some_and(|at| at < cutoff) {
+            db.remove(&email)?;
+        }
+    }
+    Ok(())
+}
+
+/// Puts a dead message back into the queue.
+pub fn retry(id: Uuid, db: &Database) -> Result<()> {
+    let mut email = db.get::<QueuedEmail>(id)?;
+    if email.status != QueueStatus::Dead {
+        return Err(ErrorKind::BadInput("only dead messages can be retried".to_string()).into());
+    }
+    email.status = QueueStatus::Pending;
+    email.attempts = 0;
+    email.next_attempt = Utc::now();
+    db.set(&email)?;
+    Ok(())
+}
+
+/// Removes all dead messages, returning their number.
+pub fn purge(db: &Database) -> Result<usize> {
+    let mut count = 0;
+    for email in db.get_collection::<QueuedEmail>()? {
+        if email.status == QueueStatus::Dead {
+            db.remove(&email)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Delivers queued messages until the application exits.
+pub async fn worker(config: Config, db: Database) {
+    let mailer = match transport::mailer(&config.email) {
+        Ok(mailer) => mailer,
+        Err(e) => {
+            log::error!("email queue worker failed to create mailer: {e}");
+            return;
+        }
+    };
+    let queue = &config.email.queue;
+    let mut limiter = RateLimiter::new(queue.rate_limit);
+
+    let mut interval =
+        tokio::time::interval(StdDuration::from_secs(queue.poll_interval.max(1) as u64));
+    loop {
+        interval.tick().await;
+        if let Err(e) = process(mailer.as_ref(), &mut limiter, queue, &db).await {
+            log::error!("failed processing email queue: {e}");
+        }
+        if let Err(e) = super::campaign::process(&db) {
+            log::error!("failed updating campaign progress: {e}");
+        }
+        if let Err(e) = cleanup(queue, &db) {
+            log::error!("failed cleaning up email queue: {e}");
+        }
+        if let Some(mailbox) = &config.email.bounces.mailbox {
+            if let Err(e) = suppression::process_mailbox(mailbox, &db) {
+                log::error!("failed processing bounce mailbox: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! Scheduled email sequences tied to user lifecycle events.
+//!
+//! Sequences are defined with `config::Mailing::sequences`, either in the
+//! config file or in code before starting the application. When the trigger
+//! event happens the user gets enrolled in all matching sequences. Each step
+//! is sent once its delay, counted from the trigger, passes and the user
+//! meets the step conditions. Steps with unmet conditions are skipped.
+//!
+//! Step messages are rendered from application-provided templates, see
+//! `email::template`, with `user` and `unsubscribe_link` values available,
+//! and delivered through the outbound queue.
+//!
+//! An enrollment stops when the user follows the unsubscribe link, leaves
+//! the mailing list the sequence is tied to, or the sequence is removed from
+//! config. It's marked failed if a step can't be rendered, e.g. because of
+//! a broken template.
+
+use chrono::{DateTime, Duration, Utc};
+use lettre::Message;
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+use crate::config::{self, SequenceTrigger, StepCondition};
+use crate::db::{Collectable, Identifiable};
+use crate::error::{ErrorKind, Result};
+use crate::{Config, Database, User, UserId};
+
+use super::{list, queue, suppression, template};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum EnrollmentStatus {
+    Active,
+    Completed,
+    Stopped,
+    /// A step message couldn't be rendered, e.g. because of a broken
+    /// template.
+    Failed,
+}
+
+/// User's progress through a single sequence.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Enrollment {
+    pub id: Uuid,
+    pub user: UserId,
+    pub sequence: String,
+    pub status: EnrollmentStatus,
+    /// Index of the next step.
+    pub step: usize,
+    pub started_at: DateTime<Utc>,
+}
+
+impl Collectable for Enrollment {
+    fn get_collection_name() -> &'static str {
+        "email_sequence_enrollments"
+    }
+}
+
+impl Identifiable for Enrollment {
+    fn get_id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// Enrolls the user in all sequences started by the event, sending out any
+/// steps that are due right away.
+///
+/// Users are never enrolled in the same sequence twice, unless the previous
+/// enrollment was completed. Failing to send the due steps doesn't prevent
+/// the enrollment, they're retried with the next `process` run.
+pub fn trigger(event: SequenceTrigger, user: &User, config: &Config, db: &Database) -> Result<()> {
+    let existing = db
+        .get_collection::<Enrollment>()?
+        .into_iter()
+        .filter(|e| e.user == user.id && e.status != EnrollmentStatus::Completed)
+        .map(|e| e.sequence)
+        .collect::<Vec<_>>();
+
+    for sequence in &config.mailing.sequences {
+        if sequence.trigger != event || existing.contains(&sequence.name) {
+            continue;
+        }
+        let mut enrollment = Enrollment {
+            id: Uuid::new_v4(),
+            user: user.id,
+            sequence: sequence.name.clone(),
+            status: EnrollmentStatus::Active,
+            step: 0,
+            started_at: Utc::now(),
+        };
+        db.set(&enrollment)?;
+        if let Err(e) = advance(&mut enrollment, config, db) {
+            log::error!("failed sending {} sequence step: {e}", sequence.name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends due steps of all active enrollments. Steps that failed to be
+/// queued are retried on the next run.
+pub fn process(config: &Config, db: &Database) -> Result<()> {
+    for mut enrollment in db.get_collection::<Enrollment>()? {
+        if enrollment.status != EnrollmentStatus::Active {
+            continue;
+        }
+        if let Err(e) = advance(&mut enrollment, config, db) {
+            log::error!(
+                "failed sending {} sequence step to user {}: {e}",
+                enrollment.sequence,
+                enrollment.user
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Stops the enrollment, no more steps will be sent.
+pub fn stop(id: Uuid, db: &Database) -> Result<()> {
+    db.update::<Enrollment, _>(id, |mut enrollment| {
+        enrollment.status = EnrollmentStatus::Stopped;
+        Ok(enrollment)
+    })
+    .map_err(|_| ErrorKind::BadInput("unknown sequence enrollment".to_string()))?;
+    Ok(())
+}
+
+/// Sends all due steps and stores the progress.
+fn advance(enrollment: &mut Enrollment, config: &Config, db: &Database) -> Result<()> {
+    let Some(sequence) = config
+        .mailing
+        .sequences
+        .iter()
+        .find(|s| s.name == enrollment.sequence)
+    else {
+        return end(enrollment, EnrollmentStatus::Stopped, db);
+    };
+    let Ok(user) = db.get::<User>(enrollment.user) else {
+        return end(enrollment, EnrollmentStatus::Stopped, db);
+    };
+    if let Some(list) = &sequence.list {
+        let subscribed = list::find_for_user(&user, db)?.is_some_and(|s| s.lists.contains(list));
+        if !subscribed {
+            return end(enrollment, EnrollmentStatus::Stopped, db);
+        }
+    }
+
+    while let Some(step) = sequence.steps.get(enrollment.step) {
+        if enrollment.started_at + Duration::seconds(step.delay as i64) > Utc::now() {
+            break;
+        }
+        if step.conditions.iter().all(|c| meets(&user, c))
+            && !suppression::is_suppressed(&user.email, db)
+        {
+            // retrying won't fix a broken template, only flood the log
+            let message = match message(enrollment, step, &user, config) {
+                Ok(message) => message,
+                Err(e) => {
+                    log::error!(
+                        "failed rendering {} sequence step {} for user {}, giving up: {e}",
+                        enrollment.sequence,
+                        enrollment.step,
+                        enrollment.user
+                    );
+                    return end(enrollment, EnrollmentStatus::Failed, db);
+                }
+            };
+            queue::push(&message, db)?;
+        }
+        enrollment.step += 1;
+        // progress is stored after every step, so that a stop coming in
+        // while steps are being sent is noticed right away
+        if !save(enrollment, db)? {
+            return Ok(());
+        }
+    }
+    if enrollment.step >= sequence.steps.len() {
+        return end(enrollment, EnrollmentStatus::Completed, db);
+    }
+
+    Ok(())
+}
+
+fn end(enrollment: &mut Enrollment, status: EnrollmentStatus, db: &Database) -> Result<()> {
+    enrollment.status = status;
+    save(enrollment, db).map(|_| ())
+}
+
+/// Stores the enrollment, unless it's no longer active, e.g. because the
+/// user followed the unsubscribe link in the meantime. Returns false if
+/// nothing was stored.
+fn save(enrollment: &Enrollment, db: &Database) -> Result<bool> {
+    let mut active = true;
+    let result = db.update::<Enrollment, _>(enrollment.id, |stored| {
+        if stored.status != EnrollmentStatus::Active {
+            active = false;
+            return Err(
+                ErrorKind::Other("sequence enrollment is no longer active".to_string()).into(),
+            );
+        }
+        Ok(enrollment.clone())
+    });
+    match result {
+        Ok(_) => Ok(true),
+        Err(_) if !active => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn meets(user: &User, condition: &StepCondition) -> bool {
+    match condition {
+        StepCondition::EmailConfirmed => user.email_confirmed,
+        StepCondition::EmailUnconfirmed => !user.email_confirmed,
+        StepCondition::FreePlan => user.plan.price == Decimal::ZERO,
+        StepCondition::PaidPlan => user.plan.price > Decimal::ZERO,
+    }
+}
+
+#[derive(Serialize)]
+struct StepContext<'a> {
+    user: UserContext<'a>,
+    unsubscribe_link: &'a str,
+}
+
+#[derive(Serialize)]
+struct UserContext<'a> {
+    name: &'a str,
+    handle: &'a str,
+    email: &'a str,
+    plan: &'a str,
+}
+
+fn message(
+    enrollment: &Enrollment,
+    step: &config::SequenceStep,
+    user: &User,
+    config: &Config,
+) -> Result<Message> {
+    let unsubscribe_link = super::link(
+        config,
+        &format!("/mailing/unsubscribe/sequence/{}", enrollment.id),
+    );
+    let rendered = template::render_named(
+        &step.template,
+        &StepContext {
+            user: UserContext {
+                name: &user.name,
+                handle: &user.handle,
+                email: &user.email,
+                plan: &user.plan.name,
+            },
+            unsubscribe_link: &unsubscribe_link,
+        },
+        config,
+    )?;
+
+    let builder = super::with_unsubscribe(super::builder(&user.email, config)?, &unsubscribe_link);
+    super::finish(builder, rendered, config)
+}
@@ -0,0 +1,1001 @@
+//! Suppression list of addresses that shouldn't receive any more emails.
+//!
+//! Addresses end up on the list after a permanent bounce or a spam complaint.
+//! Notifications are accepted from email providers through the
+//! `/events/email/:provider` webhook, and from delivery status notification
+//! (DSN) messages delivered to a local maildir, see `config::Bounces`.
+//!
+//! Supported webhook formats:
+//!
+//! - `ses`, Amazon SES notifications delivered through SNS
+//! - `postmark`, Postmark bounce and spam complaint webhooks
+//! - `generic`, a single object or an array of objects like
+//!   `{"type": "bounce", "email": "...", "permanent": true, "reason": "..."}`
+//!   where `type` is either `bounce` or `complaint`
+//!
+//! All webhook requests need to carry the shared secret, see
+//! `config::Bounces::webhook_secret`. SNS messages are additionally checked
+//! for a valid signature, see `verify_sns`. For the other formats the secret
+//! is the only protection against forged notifications.
+//!
+//! Delivery status notifications are only trusted if they refer to a message
+//! we sent, see `process_mailbox`.
+//!
+//! Transient bounces are only logged, sending to such addresses continues.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{OnceLock, RwLock};
+
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use rsa::pkcs8::der::{self, Reader, SliceReader, Tag, TagNumber};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::db::{Collectable, Identifiable};
+use crate::error::{ErrorKind, Result};
+use crate::Database;
+
+use super::queue::QueuedEmail;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum Reason {
+    Bounce,
+    Complaint,
+    /// Added by an administrator.
+    Manual,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Suppression {
+    pub id: Uuid,
+    pub address: String,
+    pub reason: Reason,
+    /// Where the notification came from, e.g. the webhook provider.
+    pub source: String,
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Collectable for Suppression {
+    fn get_collection_name() -> &'static str {
+        "email_suppressions"
+    }
+}
+
+impl Identifiable for Suppression {
+    fn get_id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// Suppression ids are derived from the address, so that entries can be
+/// looked up directly.
+fn address_id(address: &str) -> Uuid {
+    Uuid::new_v5(
+        &Uuid::NAMESPACE_OID,
+        address.trim().to_lowercase().as_bytes(),
+    )
+}
+
+pub fn is_suppressed(address: &str, db: &Database) -> bool {
+    db.get::<Suppression>(address_id(address)).is_ok()
+}
+
+/// Puts the address on the suppression list. Existing entries are left
+/// untouched.
+pub fn suppress(
+    address: &str,
+    reason: Reason,
+    source: &str,
+    detail: Option<String>,
+    db: &Database,
+) -> Result<()> {
+    if is_suppressed(address, db) {
+        return Ok(());
+    }
+    db.set(&Suppression {
+        id: address_id(address),
+        address: address.trim().to_string(),
+        reason,
+        source: source.to_string(),
+        detail,
+        created_at: Utc::now(),
+    })
+}
+
+/// Takes the address off the suppression list.
+pub fn remove(address: &str, db: &Database) -> Result<()> {
+    let entry = db
+        .get::<Suppression>(address_id(address))
+        .map_err(|_| ErrorKind::BadInput(format!("address not suppressed: {address}")))?;
+    db.remove(&entry)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Feedback {
+    Bounce { permanent: bool },
+    Complaint,
+}
+
+/// Single bounce or complaint notification.
+#[derive(Clone, Debug)]
+pub struct Notification {
+    pub address: String,
+    pub feedback: Feedback,
+    pub detail: Option<String>,
+}
+
+/// Updates the suppression list based on received notifications. Returns
+/// the number of addresses suppressed.
+pub fn record(notifications: &[Notification], source: &str, db: &Database) -> Result<usize> {
+    let mut count = 0;
+    for n in notifications {
+        let reason = match n.feedback {
+            Feedback::Bounce { permanent: true } => Reason::Bounce,
+            Feedback::Complaint => Reason::Complaint,
+            Feedback::Bounce { permanent: false } => {
+                log::info!(
+                    "transient bounce for {} from {source}: {}",
+                    n.address,
+                    n.detail.as_deref().unwrap_or("no details")
+                );
+                continue;
+            }
+        };
+        log::info!("suppressing {} after {reason} from {source}", n.address);
+        suppress(&n.address, reason, source, n.detail.clone(), db)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum Provider {
+    Ses,
+    Postmark,
+    Generic,
+}
+
+/// Parses the webhook request body sent by the provider.
+pub fn parse(provider: Provider, body: &str) -> Result<Vec<Notification>> {
+    let value: Value = serde_json::from_str(body)?;
+    let notifications = match provider {
+        Provider::Ses => parse_ses(&value)?,
+        Provider::Postmark => parse_postmark(&value),
+        Provider::Generic => match &value {
+            Value::Array(items) => items.iter().filter_map(parse_generic).collect(),
+            item => parse_generic(item).into_iter().collect(),
+        },
+    };
+    Ok(notifications)
+}
+
+/// Returns the url that needs to be visited to confirm the SNS topic
+/// subscription, if the body is a subscription confirmation request.
+pub fn ses_subscription_url(body: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(body).ok()?;
+    if value["Type"] != "SubscriptionConfirmation" {
+        return None;
+    }
+    let url = value["SubscribeURL"].as_str()?;
+    // only ever call back to aws
+    if !is_sns_url(url) {
+        log::warn!("ignoring sns subscription confirmation with unexpected url: {url}");
+        return None;
+    }
+    Some(url.to_string())
+}
+
+fn is_sns_url(url: &str) -> bool {
+    url::Url::parse(url)
+        .is_ok_and(|url| url.scheme() == "https" && url.host_str().is_some_and(is_sns_host))
+}
+
+/// Matches `sns.<region>.amazonaws.com(.cn)`. Exactly one label is allowed
+/// in between, other hosts under `amazonaws.com`, e.g. S3 buckets, can be
+/// claimed by anyone.
+fn is_sns_host(host: &str) -> bool {
+    host.strip_prefix("sns.")
+        .and_then(|rest| {
+            rest.strip_suffix(".amazonaws.com")
+                .or(rest.strip_suffix(".amazonaws.com.cn"))
+        })
+        .is_some_and(|region| {
+            !region.is_empty()
+                && region
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        })
+}
+
+/// Signing certificates are fetched once per url and kept for the lifetime
+/// of the application.
+fn sns_keys() -> &'static RwLock<HashMap<String, RsaPublicKey>> {
+    static CACHE: OnceLock<RwLock<HashMap<String, RsaPublicKey>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// Verifies the signature of an SNS message, as described in
+/// <https://docs.aws.amazon.com/sns/latest/dg/sns-verify-signature-of-message.html>.
+///
+/// Only signature version 2 is accepted, it needs to be enabled on the topic
+/// with the `SignatureVersion` attribute. Raw message delivery strips the
+/// signature and can't be used.
+pub async fn verify_sns(body: &str) -> Result<()> {
+    let invalid = |reason: &str| {
+        log::warn!("rejecting sns message: {reason}");
+        ErrorKind::Forbidden
+    };
+
+    let value: Value = serde_json::from_str(body)?;
+    if value["SignatureVersion"] != "2" {
+        return Err(invalid("unsupported signature version").into());
+    }
+    let cert_url = value["SigningCertURL"].as_str().unwrap_or_default();
+    if !is_sns_url(cert_url) {
+        return Err(invalid("unexpected signing certificate url").into());
+    }
+    let signature = base64::engine::general_purpose::STANDARD
+        .decode(value["Signature"].as_str().unwrap_or_default())
+        .map_err(|_| invalid("malformed signature"))?;
+
+    let keys: &[&str] = match value["Type"].as_str() {
+        Some("Notification") => &[
+            "Message",
+            "MessageId",
+            "Subject",
+            "Timestamp",
+            "TopicArn",
+            "Type",
+        ],
+        Some("SubscriptionConfirmation" | "UnsubscribeConfirmation") => &[
+            "Message",
+            "MessageId",
+            "SubscribeURL",
+            "Timestamp",
+            "Token",
+            "TopicArn",
+            "Type",
+        ],
+        _ => return Err(invalid("unknown message type").into()),
+    };
+    let mut signed = String::new();
+    for key in keys {
+        // subject is the only optional field
+        if let Some(value) = value[key].as_str() {
+            signed.push_str(&format!("{key}\n{value}\n"));
+        }
+    }
+
+    let cached = sns_keys()
+        .read()
+        .ok()
+        .and_then(|keys| keys.get(cert_url).cloned());
+    let key = match cached {
+        Some(key) => key,
+        None => {
+            let pem = reqwest::get(cert_url)
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            let key = certificate_key(&pem)?;
+            if let Ok(mut keys) = sns_keys().write() {
+                keys.insert(cert_url.to_string(), key.clone());
+            }
+            key
+        }
+    };
+
+    key.verify(
+        Pkcs1v15Sign::new::<Sha256>(),
+        &Sha256::digest(signed.as_bytes()),
+        &signature,
+    )
+    .map_err(|_| invalid("signature mismatch"))?;
+
+    Ok(())
+}
+
+/// Extracts the public key from a PEM encoded X.509 certificate.
+fn certificate_key(pem: &str) -> Result<RsaPublicKey> {
+    let invalid = |e: &dyn std::fmt::Display| ErrorKind::Other(format!("invalid certificate: {e}"));
+
+    let (_, cert) = der::pem::decode_vec(pem.as_bytes()).map_err(|e| invalid(&e))?;
+    let mut reader = SliceReader::new(&cert).map_err(|e| invalid(&e))?;
+    let spki = reader
+        .sequence(|cert| {
+            let spki = cert.sequence(|tbs| {
+                // optional explicit version
+                let version = Tag::ContextSpecific {
+                    constructed: true,
+                    number: TagNumber::N0,
+                };
+                if tbs.peek_tag()? == version {
+                    tbs.tlv_bytes()?;
+                }
+                // serial number, signature algorithm, issuer, validity and
+                // subject precede the public key info
+                for _ in 0..5 {
+                    tbs.tlv_bytes()?;
+                }
+                let spki = tbs.tlv_bytes()?;
+                while !tbs.is_finished() {
+                    tbs.tlv_bytes()?;
+                }
+                Ok(spki)
+            })?;
+            while !cert.is_finished() {
+                cert.tlv_bytes()?;
+            }
+            Ok(spki)
+        })
+        .map_err(|e| invalid(&e))?;
+
+    Ok(RsaPublicKey::from_public_key_der(spki).map_err(|e| invalid(&e))?)
+}
+
+fn parse_ses(value: &Value) -> Result<Vec<Notification>> {
+    // unwrap the sns envelope unless raw message delivery is used
+    let message = match value["Message"].as_str() {
+        Some(message) => serde_json::from_str(message)?,
+        None => value.clone(),
+    };
+
+    let kind = message["notificationType"]
+        .as_str()
+        .or(message["eventType"].as_str())
+        .unwrap_or_default();
+    let notifications = match kind {
+        "Bounce" => {
+            let bounce = &message["bounce"];
+            let permanent = bounce["bounceType"] == "Permanent";
+            recipients(&bounce["bouncedRecipients"])
+                .map(|(address, recipient)| Notification {
+                    address,
+                    feedback: Feedback::Bounce { permanent },
+                    detail: recipient["diagnosticCode"]
+                        .as_str()
+                        .or(bounce["bounceSubType"].as_str())
+                        .map(str::to_string),
+                })
+                .collect()
+        }
+        "Complaint" => {
+            let complaint = &message["complaint"];
+            recipients(&complaint["complainedRecipients"])
+                .map(|(address, _)| Notification {
+                    address,
+                    feedback: Feedback::Complaint,
+                    detail: complaint["complaintFeedbackType"]
+                        .as_str()
+                        .map(str::to_string),
+                })
+                .collect()
+        }
+        _ => vec![],
+    };
+    Ok(notifications)
+}
+
+fn recipients(value: &Value) -> impl Iterator<Item = (String, &Value)> {
+    value.as_array().into_iter().flatten().filter_map(|r| {
+        r["emailAddress"]
+            .as_str()
+            .map(|address| (address.to_string(), r))
+    })
+}
+
+fn parse_postmark(value: &Value) -> Vec<Notification> {
+    let Some(address) = value["Email"].as_str().map(str::to_string) else {
+        return vec![];
+    };
+    let detail = value["Details"]
+        .as_str()
+        .or(value["Description"].as_str())
+        .map(str::to_string);
+
+    let feedback = match value["RecordType"].as_str() {
+        Some("Bounce") => Feedback::Bounce {
+            permanent: matches!(
+                value["Type"].as_str(),
+                Some("HardBounce" | "BadEmailAddress" | "ManuallyDeactivated")
+            ),
+        },
+        Some("SpamComplaint") => Feedback::Complaint,
+        _ => return vec![],
+    };
+    vec![Notification {
+        address,
+        feedback,
+        detail,
+    }]
+}
+
+fn parse_generic(value: &Value) -> Option<Notification> {
+    let address = value["email"].as_str()?.to_string();
+    let feedback = match value["type"].as_str()? {
+        "bounce" => Feedback::Bounce {
+            permanent: value["permanent"].as_bool().unwrap_or(true),
+        },
+        "complaint" => Feedback::Complaint,
+        _ => return None,
+    };
+    Some(Notification {
+        address,
+        feedback,
+        detail: value["reason"].as_str().map(str::to_string),
+    })
+}
+
+/// Extracts failed recipients from a delivery status notification as
+/// described in RFC 3464. Returns nothing if the message is not a DSN.
+pub fn parse_dsn(raw: &str) -> Vec<Notification> {
+    if !raw.to_lowercase().contains("message/delivery-status") {
+        return vec![];
+    }
+
+    #[derive(Default)]
+    struct Recipient {
+        address: String,
+        action: String,
+        status: String,
+        diagnostic: Option<String>,
+    }
+
+    let mut recipients: Vec<Recipient> = vec![];
+    for field in fields(raw) {
+        let Some((name, value)) = field.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_lowercase().as_str() {
+            "final-recipient" => recipients.push(Recipient {
+                // `rfc822; user@example.com`
+                address: value
+                    .split_once(';')
+                    .map(|(_, a)| a)
+                    .unwrap_or(value)
+                    .trim()
+                    .trim_matches(['<', '>'])
+                    .to_string(),
+                ..Default::default()
+            }),
+            "action" => {
+                if let Some(r) = recipients.last_mut() {
+                    r.action = value.to_lowercase();
+                }
+            }
+            "status" => {
+                if let Some(r) = recipients.last_mut() {
+                    r.status = value.to_string();
+                }
+            }
+            "diagnostic-code" => {
+                if let Some(r) = recipients.last_mut() {
+                    r.diagnostic = Some(value.to_string());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    recipients
+        .into_iter()
+        .filter(|r| r.action == "failed" || r.action == "delayed")
+        .map(|r| Notification {
+            feedback: Feedback::Bounce {
+                permanent: r.action == "failed" && r.status.starts_with('5'),
+            },
+            detail: r.diagnostic.or(Some(r.status)),
+            address: r.address,
+        })
+        .collect()
+}
+
+/// Splits the message into header fields, unfolding continued lines.
+fn fields(raw: &str) -> Vec<String> {
+    let mut fields: Vec<String> = vec![];
+    for line in raw.lines() {
+        match fields.last_mut() {
+            Some(last) if line.starts_with([' ', '\t']) && !line.trim().is_empty() => {
+                last.push(' ');
+                last.push_str(line.trim());
+            }
+            _ => fields.push(line.trim_end().to_string()),
+        }
+    }
+    fields
+}
+
+/// Keeps notifications about recipients of messages found in the outbound
+/// queue. DSNs include the headers of the original message, which need to
+/// carry the `Message-ID` of a message we sent to one of the failed
+/// recipients. The DSN's own headers are skipped.
+fn own_bounces(
+    raw: &str,
+    notifications: Vec<Notification>,
+    db: &Database,
+) -> Result<Vec<Notification>> {
+    if notifications.is_empty() {
+        return Ok(notifications);
+    }
+
+    let body = raw
+        .split_once("\r\n\r\n")
+        .or(raw.split_once("\n\n"))
+        .map(|(_, body)| body)
+        .unwrap_or_default();
+    let message_ids = fields(body)
+        .into_iter()
+        .filter_map(|field| {
+            let (name, value) = field.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case("message-id")
+                .then(|| value.trim().to_string())
+        })
+        .collect::<HashSet<_>>();
+    if message_ids.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let recipients = db
+        .get_collection::<QueuedEmail>()?
+        .into_iter()
+        .filter(|e| {
+            e.message_id
+                .as_ref()
+                .is_some_and(|id| message_ids.contains(id))
+        })
+        .flat_map(|e| e.to)
+        .collect::<Vec<_>>();
+    Ok(notifications
+        .into_iter()
+        .filter(|n| {
+            recipients
+                .iter()
+                .any(|r| r.eq_ignore_ascii_case(&n.address))
+        })
+        .collect())
+}
+
+/// Processes new messages in the maildir, recording any delivery status
+/// notifications found. Processed messages are moved to `cur`. Returns the
+/// number of addresses suppressed.
+///
+/// Only notifications about messages still kept in the outbound queue are
+/// recorded, see `config::MailQueue::keep_sent`. Anyone can deliver a
+/// message looking like a DSN to the bounce address, the original
+/// `Message-ID` is what proves the bounce is genuine.
+pub fn process_mailbox(path: impl AsRef<Path>, db: &Database) -> Result<usize> {
+    let path = path.as_ref();
+    let new = path.join("new");
+    if !new.is_dir() {
+        return Ok(0);
+    }
+    std::fs::create_dir_all(path.join("cur"))?;
+
+    let mut count = 0;
+    for entry in std::fs::read_dir(new)? {
+        let file = entry?.path();
+        if !file.is_file() {
+            continue;
+        }
+        let raw = String::from_utf8_lossy(&std::fs::read(&file)?).to_string();
+        let notifications = own_bounces(&raw, parse_dsn(&raw), db)?;
+        if notifications.is_empty() {
+            log::debug!("no bounces of our messages found in {}", file.display());
+        }
+        count += record(&notifications, "dsn", db)?;
+
+        // mark as seen, as described in the maildir spec
+        if let Some(name) = file.file_name().and_then(|n| n.to_str()) {
+            std::fs::rename(&file, path.join("cur").join(format!("{name}:2,S")))?;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bounce report as generated by Postfix. `{message_id}` is replaced
+    /// with the `Message-ID` of the bounced message.
+    const DSN: &str = r#"Return-Path: <>
+Received: by mail.example.com (Postfix)
+	id 4C3F81C0036; Mon, 19 Oct 2026 10:00:01 +0000 (UTC)
+Date: Mon, 19 Oct 2026 10:00:01 +0000 (UTC)
+From: MAILER-DAEMON@mail.example.com (Mail Delivery System)
+Subject: Undelivered Mail Returned to Sender
+To: bounces@example.com
+Auto-Submitted: auto-replied
+MIME-Version: 1.0
+Content-Type: multipart/report; report-type=delivery-status;
+	boundary="4C3F81C0036.1792404001/mail.example.com"
+Message-Id: <20261019100001.4C3F81C0036@mail.example.com>
+
+This is a MIME-encapsulated message.
+
+--4C3F81C0036.1792404001/mail.example.com
+Content-Description: Notification
+Content-Type: text/plain; charset=us-ascii
+
+This is the mail system at host mail.example.com.
+
+I'm sorry to have to inform you that your message could not
+be delivered to one or more recipients.
+
+<jane@example.org>: host mx.example.org[203.0.113.25] said: 550 5.1.1
+    <jane@example.org>: Recipient address rejected: User unknown in virtual
+    mailbox table (in reply to RCPT TO command)
+
+--4C3F81C0036.1792404001/mail.example.com
+Content-Description: Delivery report
+Content-Type: message/delivery-status
+
+Reporting-MTA: dns; mail.example.com
+X-Postfix-Queue-ID: 4C3F81C0036
+X-Postfix-Sender: rfc822; app@example.com
+Arrival-Date: Mon, 19 Oct 2026 10:00:00 +0000 (UTC)
+
+Final-Recipient: rfc822; jane@example.org
+Original-Recipient: rfc822;jane@example.org
+Action: failed
+Status: 5.1.1
+Remote-MTA: dns; mx.example.org
+Diagnostic-Code: smtp; 550 5.1.1 <jane@example.org>: Recipient address
+    rejected: User unknown in virtual mailbox table
+
+Final-Recipient: rfc822; bob@example.net
+Original-Recipient: rfc822;bob@example.net
+Action: delayed
+Status: 4.4.1
+Remote-MTA: dns; mx.example.net
+Diagnostic-Code: X-Postfix; connect to mx.example.net[198.51.100.7]:25:
+    Connection timed out
+
+--4C3F81C0036.1792404001/mail.example.com
+Content-Description: Undelivered Message Headers
+Content-Type: text/rfc822-headers
+
+Return-Path: <app@example.com>
+Received: by mail.example.com (Postfix, from userid 1000)
+	id 4C3F81C0036; Mon, 19 Oct 2026 10:00:00 +0000 (UTC)
+From: app@example.com
+To: jane@example.org, bob@example.net
+Subject: Welcome
+Message-ID: {message_id}
+Date: Mon, 19 Oct 2026 10:00:00 +0000
+
+--4C3F81C0036.1792404001/mail.example.com--
+"#;
+
+    /// SES bounce notification delivered through SNS, signed with the key
+    /// of `CERT`.
+    const SNS: &str = r#"{
+    "Type": "Notification",
+    "MessageId": "22b80b92-fdea-4c2c-8f9d-bdfb0c7bf324",
+    "TopicArn": "arn:aws:sns:us-east-1:123456789012:ses-bounces",
+    "Message": "{\"notificationType\":\"Bounce\",\"bounce\":{\"bounceType\":\"Permanent\",\"bounceSubType\":\"General\",\"bouncedRecipients\":[{\"emailAddress\":\"jane@example.com\",\"action\":\"failed\",\"status\":\"5.1.1\",\"diagnosticCode\":\"smtp; 550 5.1.1 user unknown\"}],\"timestamp\":\"2016-01-27T14:59:38.237Z\",\"feedbackId\":\"00000137860315fd-869464a4-8680-4114-98d3-716fe35851f9-000000\",\"remoteMtaIp\":\"127.0.2.0\",\"reportingMTA\":\"dsn; a8-70.smtp-out.amazonses.com\"},\"mail\":{\"timestamp\":\"2016-01-27T14:59:38.237Z\",\"source\":\"john@example.com\",\"sourceArn\":\"arn:aws:ses:us-east-1:123456789012:identity/example.com\",\"sourceIp\":\"127.0.3.0\",\"sendingAccountId\":\"123456789012\",\"messageId\":\"00000137860315fd-34208509-5b74-41f3-95c5-22c1edc3c924-000000\",\"destination\":[\"jane@example.com\",\"mary@example.com\"]}}",
+    "Timestamp": "2016-01-27T14:59:38.276Z",
+    "SignatureVersion": "2",
+    "SigningCertURL": "https://sns.us-east-1.amazonaws.com/SimpleNotificationService-test.pem",
+    "UnsubscribeURL": "https://sns.us-east-1.amazonaws.com/?Action=Unsubscribe&SubscriptionArn=arn:aws:sns:us-east-1:123456789012:ses-bounces:0",
+    "Signature": "lr7ZYDP02irkm8dkNcQL0RcYu/aNMSVR09/mL92v7M+k+dsqrMiq+oIoM6sCCl31NNuoiEd4ElHcqjSHWFJzTLIFh22XPhjuYf/tWyF10yTe5rctW8BaInoJGeSXQalEbpwit3oFDSKsJy8e6wdsBqTVFdewXM0PamFUteZL3M3Laqy3UyNltU9KkwNm37gPBAiGWpRBJIuZDjK2gSntBTRs1a3/Hm3kE9O40vkn1dv38W49YvdA5rhGTHdcZR3Te+7B6FAUOOOMDy0BPoFufq1MANPvwRvLPxbO7II89PeSHK9A8p0jgfqeChNls45XX6j9uWA5UBzWSPHIFbMEjQ=="
+}"#;
+
+    /// Self-signed certificate standing in for the SNS signing certificate.
+    const CERT: &str = "-----BEGIN CERTIFICATE-----
+MIIDLzCCAhegAwIBAgIUXwB17rXCkzb0A+gqAbFhmmdj+n0wDQYJKoZIhvcNAQEL
+BQAwJjEkMCIGA1UEAwwbc25zLnVzLWVhc3QtMS5hbWF6b25hd3MuY29tMCAXDTI2
+MTAxOTAwNDAzNVoYDzIxMjYwOTI1MDA0MDM1WjAmMSQwIgYDVQQDDBtzbnMudXMt
+ZWFzdC0xLmFtYXpvbmF3cy5jb20wggEiMA0GCSqGSIb3DQEBAQUAA4IBDwAwggEK
+AoIBAQDFoZ1S1iJmcn8SMJC9LXrwSrTdsVIIOJg2y2hTtgJa4gMUGBzKF7S1Bf0u
+12K2+12PJnZVke8mqHoZSK+pMHXWUZOi9dNu2zrz7H4stqSLzppj4lGRXzt1DHsi
+h0ZKu9N7QAuDqUaZ4W6O4lWEo6YJmH4Q6LzKk0lpZLfvU9OCpeHQRml9rs0yqPLY
+3j6KaTZ+7H3sZ8gZ8sEegvtLVcDpopfyahYZ2eHUi6PZknQ7XpGw693V7RdMdwbC
+M1XL5b0eDsSFhvai8/jLK7V1mEWj+kpkIm+px13aK3vc9CPq77Oqc5hkXzhvetPL
+zv4oOZkhGnqGtsUvuEtYiRUKCz7/AgMBAAGjUzBRMB0GA1UdDgQWBBQu+OuG+pwS
+QVzgS+776j3exUr8pjAfBgNVHSMEGDAWgBQu+OuG+pwSQVzgS+776j3exUr8pjAP
+BgNVHRMBAf8EBTADAQH/MA0GCSqGSIb3DQEBCwUAA4IBAQCnZLACnuzZz1FJ1rVO
+Yw3JIpU9zDa5O7egT7UIr9em5dmOuiqB1HJstj4sbVaJE3l74bcJV3be4zwA70/p
+YwM99BtoPQ5G/8ShzCTgXuSfjEswPVSmmarDLoRA71DmDPTUAgIgtMPpit1K9F/q
+JtvAALX4vaI33ZdSfsg/c6wBd62Z3c4fIcQsnUl/eY56CYM8eanLUOphl8K3DZQr
+Q21axI9id0XfxErjIEQIAoPTTSWtyYQ7xqVhcF0++9K4fwh5jhJYX9RKFcZfHmE6
+lS9JshI2q41mpW2XWX9cQAxM6rdlbkj6KgOACRZCzXHeZxnmgdUoECjhweUcLExD
+aq4/
+-----END CERTIFICATE-----";
+
+    const POSTMARK_BOUNCE: &str = r#"{
+    "RecordType": "Bounce",
+    "MessageStream": "outbound",
+    "ID": 4323372036854775807,
+    "Type": "HardBounce",
+    "TypeCode": 1,
+    "Name": "Hard bounce",
+    "Tag": "Test",
+    "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+    "Metadata": {"a_key": "a_value", "b_key": "b_value"},
+    "ServerID": 23,
+    "Description": "The server was unable to deliver your message (ex: unknown user, mailbox not found).",
+    "Details": "Test bounce details",
+    "Email": "john@example.com",
+    "From": "sender@example.com",
+    "BouncedAt": "2019-11-05T16:33:54.9070259Z",
+    "DumpAvailable": true,
+    "Inactive": true,
+    "CanActivate": true,
+    "Subject": "Test subject",
+    "Content": "<Full dump of bounce>"
+}"#;
+
+    const POSTMARK_SOFT_BOUNCE: &str = r#"{
+    "RecordType": "Bounce",
+    "ID": 42,
+    "Type": "SoftBounce",
+    "TypeCode": 4096,
+    "Name": "Soft bounce",
+    "Description": "The server could not temporarily deliver your message (ex: Message is delayed due to network troubles).",
+    "Details": "mailbox full",
+    "Email": "john@example.com",
+    "BouncedAt": "2019-11-05T16:33:54.9070259Z"
+}"#;
+
+    const POSTMARK_COMPLAINT: &str = r#"{
+    "RecordType": "SpamComplaint",
+    "MessageStream": "outbound",
+    "ID": 42,
+    "Type": "SpamComplaint",
+    "TypeCode": 512,
+    "Name": "Spam complaint",
+    "Tag": "Test",
+    "MessageID": "00000000-0000-0000-0000-000000000000",
+    "ServerID": 1234,
+    "Description": "",
+    "Details": "Test spam complaint details",
+    "Email": "john@example.com",
+    "From": "sender@example.com",
+    "BouncedAt": "2019-11-05T16:33:54.9070259Z",
+    "DumpAvailable": true,
+    "Inactive": true,
+    "CanActivate": false,
+    "Subject": "Test subject",
+    "Content": "<Abuse report dump>"
+}"#;
+
+    const SES_COMPLAINT: &str = r#"{
+    "notificationType": "Complaint",
+    "complaint": {
+        "userAgent": "AnyCompany Feedback Loop (V0.01)",
+        "complainedRecipients": [{"emailAddress": "richard@example.com"}],
+        "complaintFeedbackType": "abuse",
+        "arrivalDate": "2016-01-27T14:59:38.237Z",
+        "timestamp": "2016-01-27T14:59:38.237Z",
+        "feedbackId": "000001378603177f-18c07c78-fa81-4a58-9dd1-fedc3cb8f49a-000000"
+    },
+    "mail": {
+        "timestamp": "2016-01-27T14:59:38.237Z",
+        "messageId": "000001378603177f-7a5433e7-8edb-42ae-af10-f0181f34d6ee-000000",
+        "source": "john@example.com",
+        "destination": ["jane@example.com", "richard@example.com"]
+    }
+}"#;
+
+    fn db(name: &str) -> Database {
+        let dir = std::env::temp_dir().join(format!(
+            "micron-test-suppression-{name}-{}",
+            std::process::id()
+        ));
+        std::fs::remove_dir_all(&dir).ok();
+        Database::open(dir).unwrap()
+    }
+
+    /// Queues a message to both recipients of the DSN sample, returning its
+    /// `Message-ID`.
+    fn queue_message(db: &Database) -> String {
+        let message = lettre::Message::builder()
+            .from("app@example.com".parse().unwrap())
+            .to("jane@example.org".parse().unwrap())
+            .to("bob@example.net".parse().unwrap())
+            .message_id(Some(format!("<{}@example.com>", Uuid::new_v4())))
+            .subject("Welcome")
+            .body(String::from("Hello"))
+            .unwrap();
+        super::super::queue::push(&message, db)
+            .unwrap()
+            .message_id
+            .unwrap()
+    }
+
+    #[test]
+    fn dsn() {
+        let notifications = parse_dsn(&DSN.replace("{message_id}", "<a@example.com>"));
+        assert_eq!(notifications.len(), 2);
+
+        assert_eq!(notifications[0].address, "jane@example.org");
+        assert_eq!(
+            notifications[0].feedback,
+            Feedback::Bounce { permanent: true }
+        );
+        assert_eq!(
+            notifications[0].detail.as_deref(),
+            Some(
+                "smtp; 550 5.1.1 <jane@example.org>: Recipient address rejected: \
+                 User unknown in virtual mailbox table"
+            )
+        );
+
+        assert_eq!(notifications[1].address, "bob@example.net");
+        assert_eq!(
+            notifications[1].feedback,
+            Feedback::Bounce { permanent: false }
+        );
+    }
+
+    #[test]
+    fn dsn_ignores_other_messages() {
+        let auto_reply = "From: jane@example.org\r\nSubject: Out of office\r\n\r\n\
+                          Final-Recipient: rfc822; jane@example.org\r\nAction: failed\r\n";
+        assert!(parse_dsn(auto_reply).is_empty());
+    }
+
+    #[test]
+    fn own_dsn() {
+        let db = db("own");
+        let message_id = queue_message(&db);
+
+        let raw = DSN.replace("{message_id}", &message_id);
+        let notifications = own_bounces(&raw, parse_dsn(&raw), &db).unwrap();
+        assert_eq!(notifications.len(), 2);
+    }
+
+    #[test]
+    fn forged_dsn() {
+        let db = db("forged");
+        queue_message(&db);
+
+        // looks like a genuine bounce, but refers to a message we never sent
+        let raw = DSN.replace("{message_id}", "<forged@example.com>");
+        assert_eq!(parse_dsn(&raw).len(), 2);
+        assert!(own_bounces(&raw, parse_dsn(&raw), &db).unwrap().is_empty());
+
+        // the DSN's own Message-Id doesn't count
+        let raw = DSN.replace(
+            "{message_id}",
+            "<20261019100001.4C3F81C0036@mail.example.com>",
+        );
+        assert!(own_bounces(&raw, parse_dsn(&raw), &db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn own_dsn_only_for_recipients() {
+        let db = db("recipients");
+        let message_id = queue_message(&db);
+
+        // one of the recipients swapped for an address we didn't send to
+        let raw = DSN
+            .replace("{message_id}", &message_id)
+            .replace("bob@example.net", "victim@example.net");
+        let notifications = own_bounces(&raw, parse_dsn(&raw), &db).unwrap();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].address, "jane@example.org");
+    }
+
+    #[test]
+    fn ses() {
+        let notifications = parse(Provider::Ses, SNS).unwrap();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].address, "jane@example.com");
+        assert_eq!(
+            notifications[0].feedback,
+            Feedback::Bounce { permanent: true }
+        );
+        assert_eq!(
+            notifications[0].detail.as_deref(),
+            Some("smtp; 550 5.1.1 user unknown")
+        );
+
+        // raw message delivery
+        let notifications = parse(Provider::Ses, SES_COMPLAINT).unwrap();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].address, "richard@example.com");
+        assert_eq!(notifications[0].feedback, Feedback::Complaint);
+        assert_eq!(notifications[0].detail.as_deref(), Some("abuse"));
+    }
+
+    #[test]
+    fn postmark() {
+        let notifications = parse(Provider::Postmark, POSTMARK_BOUNCE).unwrap();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].address, "john@example.com");
+        assert_eq!(
+            notifications[0].feedback,
+            Feedback::Bounce { permanent: true }
+        );
+        assert_eq!(
+            notifications[0].detail.as_deref(),
+            Some("Test bounce details")
+        );
+
+        let notifications = parse(Provider::Postmark, POSTMARK_SOFT_BOUNCE).unwrap();
+        assert_eq!(
+            notifications[0].feedback,
+            Feedback::Bounce { permanent: false }
+        );
+
+        let notifications = parse(Provider::Postmark, POSTMARK_COMPLAINT).unwrap();
+        assert_eq!(notifications[0].feedback, Feedback::Complaint);
+
+        // delivery and open webhooks go to the same endpoint
+        let delivery = r#"{"RecordType": "Delivery", "Email": "john@example.com"}"#;
+        assert!(parse(Provider::Postmark, delivery).unwrap().is_empty());
+    }
+
+    #[test]
+    fn sns_hosts() {
+        assert!(is_sns_url(
+            "https://sns.us-east-1.amazonaws.com/SimpleNotificationService-abc.pem"
+        ));
+        assert!(is_sns_url(
+            "https://sns.cn-north-1.amazonaws.com.cn/SimpleNotificationService-abc.pem"
+        ));
+        assert!(!is_sns_url(
+            "http://sns.us-east-1.amazonaws.com/SimpleNotificationService-abc.pem"
+        ));
+        // buckets anyone can create
+        assert!(!is_sns_url(
+            "https://sns.attacker.s3.amazonaws.com/cert.pem"
+        ));
+        assert!(!is_sns_url("https://sns..amazonaws.com/cert.pem"));
+        assert!(!is_sns_url(
+            "https://sns.amazonaws.com.attacker.com/cert.pem"
+        ));
+        assert!(!is_sns_url(
+            "https://attacker.com/sns.us-east-1.amazonaws.com"
+        ));
+    }
+
+    #[test]
+    fn certificate() {
+        assert!(certificate_key(CERT).is_ok());
+        assert!(
+            certificate_key("-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----")
+                .is_err()
+        );
+        assert!(certificate_key("not a certificate").is_err());
+    }
+
+    #[tokio::test]
+    async fn sns_signature() {
+        let value: Value = serde_json::from_str(SNS).unwrap();
+        let cert_url = value["SigningCertURL"].as_str().unwrap().to_string();
+        sns_keys()
+            .write()
+            .unwrap()
+            .insert(cert_url, certificate_key(CERT).unwrap());
+
+        assert!(verify_sns(SNS).await.is_ok());
+        assert!(verify_sns(&SNS.replace("Permanent", "Transient"))
+            .await
+            .is_err());
+        assert!(verify_sns(
+            &SNS.replace(r#""SignatureVersion": "2""#, r#""SignatureVersion": "1""#)
+        )
+        .await
+        .is_err());
+        assert!(verify_sns(&SNS.replace(
+            "https://sns.us-east-1.amazonaws.com/",
+            "https://sns.attacker.s3.amazonaws.com/"
+        ))
+        .await
+        .is_err());
+    }
+}
@@ -0,0 +1,305 @@
+//! Transactional email templates.
+//!
+//! Each email is made out of up to three templates sharing the same name:
+//!
+//! - `{name}.subject`, single line subject
+//! - `{name}.html`, html body, usually extending the shared `layout.html`
+//! - `{name}.txt`, optional plain text body, generated from the html body
+//!   if not present
+//!
+//! Default templates are embedded in the library. Applications can override
+//! any of them, including the layout, by placing files with the same names
+//! in the directory set with `config::Email::templates`. The directory is
+//! read once, on startup, see `load`.
+//!
+//! Templates use tera syntax. Besides the values of the typed context, all
+//! templates have access to `app.name`, `app.domain` and `app.url`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use serde::Serialize;
+use tera::{Context, Tera};
+
+use crate::{Config, ErrorKind, Result};
+
+const DEFAULTS: &[(&str, &str)] = &[
+    ("layout.html", include_str!("templates/layout.html")),
+    (
+        "confirmation.subject",
+        include_str!("templates/confirmation.subject"),
+    ),
+    (
+        "confirmation.html",
+        include_str!("templates/confirmation.html"),
+    ),
+    (
+        "mailing_confirmation.subject",
+        include_str!("templates/mailing_confirmation.subject"),
+    ),
+    (
+        "mailing_confirmation.html",
+        include_str!("templates/mailing_confirmation.html"),
+    ),
+    (
+        "password_reset.subject",
+        include_str!("templates/password_reset.subject"),
+    ),
+    (
+        "password_reset.html",
+        include_str!("templates/password_reset.html"),
+    ),
+    (
+        "magic_link.subject",
+        include_str!("templates/magic_link.subject"),
+    ),
+    ("magic_link.html", include_str!("templates/magic_link.html")),
+    ("lockout.subject", include_str!("templates/lockout.subject")),
+    ("lockout.html", include_str!("templates/lockout.html")),
+    (
+        "email_change.subject",
+        include_str!("templates/email_change.subject"),
+    ),
+    (
+        "email_change.html",
+        include_str!("templates/email_change.html"),
+    ),
+    (
+        "email_change_notice.subject",
+        include_str!("templates/email_change_notice.subject"),
+    ),
+    (
+        "email_change_notice.html",
+        include_str!("templates/email_change_notice.html"),
+    ),
+    (
+        "account_deletion.subject",
+        include_str!("templates/account_deletion.subject"),
+    ),
+    (
+        "account_deletion.html",
+        include_str!("templates/account_deletion.html"),
+    ),
+    ("receipt.subject", include_str!("templates/receipt.subject")),
+    ("receipt.html", include_str!("templates/receipt.html")),
+    (
+        "campaign.subject",
+        include_str!("templates/campaign.subject"),
+    ),
+    ("campaign.html", include_str!("templates/campaign.html")),
+];
+
+/// Typed context of a single email template.
+pub trait EmailTemplate: Serialize {
+    /// Name shared by the subject, html and plain text templates.
+    const NAME: &'static str;
+}
+
+/// Email content ready to be put into a message.
+#[derive(Clone, Debug)]
+pub struct Rendered {
+    pub subject: String,
+    pub plain: String,
+    pub html: String,
+}
+
+#[derive(Serialize)]
+struct App<'a> {
+    name: &'a str,
+    domain: &'a str,
+    url: String,
+}
+
+/// Renders the template using provided context.
+pub fn render<T: EmailTemplate>(template: &T, config: &Config) -> Result<Rendered> {
+    render_named(T::NAME, template, config)
+}
+
+/// Renders the template by name. Useful for application-defined templates
+/// that don't have a typed context.
+pub fn render_named(name: &str, context: &impl Serialize, config: &Config) -> Result<Rendered> {
+    let tera = templates(config)?;
+
+    let mut context = Context::from_serialize(context).map_err(template_error)?;
+    context.insert(
+        "app",
+        &App {
+            name: &config.name,
+            domain: &config.domain,
+            url: format!("https://{}", config.domain),
+        },
+    );
+
+    let subject = tera
+        .render(&format!("{name}.subject"), &context)
+        .map_err(template_error)?
+        .trim()
+        .to_string();
+    let html = tera
+        .render(&format!("{name}.html"), &context)
+        .map_err(template_error)?;
+    let plain_name = format!("{name}.txt");
+    let plain = if tera.get_template_names().any(|n| n == plain_name) {
+        tera.render(&plain_name, &context).map_err(template_error)?
+    } else {
+        html_to_text(&html)
+    };
+
+    Ok(Rendered {
+        subject,
+        plain,
+        html,
+    })
+}
+
+/// Templates are collected once per override directory and kept for the
+/// lifetime of the application.
+fn cache() -> &'static RwLock<HashMap<Option<String>, Arc<Tera>>> {
+    static CACHE: OnceLock<RwLock<HashMap<Option<String>, Arc<Tera>>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// Loads the templates ahead of time, so that broken overrides are
+/// reported on startup instead of when the first email is sent.
+pub fn load(config: &Config) -> Result<()> {
+    templates(config).map(|_| ())
+}
+
+fn templates(config: &Config) -> Result<Arc<Tera>> {
+    if let Some(tera) = cache()
+        .read()
+        .ok()
+        .and_then(|cache| cache.get(&config.email.templates).cloned())
+    {
+        return Ok(tera);
+    }
+
+    let tera = Arc::new(collect(config)?);
+    if let Ok(mut cache) = cache().write() {
+        cache.insert(config.email.templates.clone(), tera.clone());
+    }
+    Ok(tera)
+}
+
+/// Collects default templates along with application overrides.
+fn collect(config: &Config) -> Result<Tera> {
+    let mut tera = Tera::default();
+    tera.add_raw_templates(DEFAULTS.iter().copied())
+        .map_err(template_error)?;
+
+    if let Some(dir) = &config.email.templates {
+        let mut overrides = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !path.is_file() {
+                continue;
+            }
+            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                overrides.push((path.clone(), Some(name.to_string())));
+            }
+        }
+        tera.add_template_files(overrides.iter().map(|(p, n)| (p.as_path(), n.as_deref())))
+            .map_err(template_error)?;
+    }
+
+    Ok(tera)
+}
+
+fn template_error(e: tera::Error) -> crate::Error {
+    // tera errors carry the actual cause in the source chain
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(&e);
+    while let Some(e) = source {
+        message = format!("{message}: {e}");
+        source = e.source();
+    }
+    ErrorKind::Other(format!("email template: {message}")).into()
+}
+
+/// Produces a plain text version of the html body.
+///
+/// Paragraphs and other block elements are turned into line breaks and
+/// links are followed by their targets in parentheses, the rest of the
+/// markup is dropped. Source whitespace is collapsed the way browsers do.
+pub fn html_to_text(html: &str) -> String {
+    // only the body is of interest, skip the head with title and styles
+    let html = match (html.find("<body"), html.rfind("</body>")) {
+        (Some(start), Some(end)) if start < end => &html[start..end],
+        _ => html,
+    };
+
+    let mut text = String::new();
+    let mut rest = html;
+    let mut href: Option<String> = None;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start].replace(['\n', '\r'], " "));
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace())
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        let closing = tag.starts_with('/');
+        match name.as_str() {
+            "a" if !closing => href = attribute(tag, "href"),
+            "a" => {
+                if let Some(href) = href.take() {
+                    // links displaying their own target don't need repeating
+                    if !decode_entities(&text).ends_with(&href) {
+                        text.push_str(&format!(" ({href})"));
+                    }
+                }
+            }
+            "br" => text.push('\n'),
+            "p" | "div" | "h1" | "h2" | "h3" | "table" if closing => text.push_str("\n\n"),
+            "tr" | "li" if closing => text.push('\n'),
+            "td" | "th" if closing => text.push(' '),
+            _ => (),
+        }
+    }
+    text.push_str(&rest.replace(['\n', '\r'], " "));
+
+    let text = decode_entities(&text);
+
+    // collapse whitespace, keeping at most one empty line between paragraphs
+    let mut out = String::new();
+    let mut breaks = 0;
+    for line in text.split('\n') {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if line.is_empty() {
+            breaks += 1;
+            continue;
+        }
+        if !out.is_empty() {
+            out.push_str(if breaks > 1 { "\n\n" } else { "\n" });
+        }
+        out.push_str(&line);
+        breaks = 1;
+    }
+    out
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.find(&format!("{name}=\""))? + name.len() + 2;
+    let end = tag[start..].find('"')?;
+    Some(decode_entities(&tag[start..start + end]))
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&nbsp;", " ")
+        .replace("&middot;", "·")
+        .replace("&times;", "×")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&#39;", "'")
+        .replace("&#x2F;", "/")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,286 @@
+//! Open and click tracking for campaigns.
+//!
+//! Tracking is enabled per mailing list with `config::MailingList::tracking`.
+//! A subscriber is only tracked if all the lists they receive the campaign
+//! through have tracking enabled.
+//!
+//! Opens are recorded when the tracking pixel appended to the message gets
+//! loaded. Clicks are recorded by rewriting links in the campaign content to
+//! go through a redirect endpoint. Both links are signed, so that events
+//! can't be forged and the redirect can't be used to send visitors to
+//! arbitrary sites.
+
+use std::collections::{BTreeMap, HashSet};
+
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::db::{Collectable, Identifiable};
+use crate::error::{ErrorKind, Result};
+use crate::{Config, Database};
+
+use super::campaign::Campaign;
+use super::list::Subscriber;
+
+/// Transparent 1x1 gif served as the tracking pixel.
+pub const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum EventKind {
+    Open,
+    Click,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrackingEvent {
+    pub id: Uuid,
+    pub campaign: Uuid,
+    pub subscriber: Uuid,
+    pub kind: EventKind,
+    /// Link target for clicks.
+    pub url: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Collectable for TrackingEvent {
+    fn get_collection_name() -> &'static str {
+        "email_tracking_events"
+    }
+}
+
+impl Identifiable for TrackingEvent {
+    fn get_id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// Checks if the subscriber should be tracked when receiving the campaign.
+pub fn is_tracked(campaign: &Campaign, subscriber: &Subscriber, config: &Config) -> bool {
+    config
+        .mailing
+        .is_tracked(campaign.lists.intersection(&subscriber.lists))
+}
+
+/// Key used for signing tracking links. Generated on first use and stored in
+/// the db so that links stay valid between restarts.
+fn key(db: &Database) -> Result<Vec<u8>> {
+    const TREE: &str = "email_tracking_keys";
+    match db.get_at::<Vec<u8>>(TREE, Uuid::nil()) {
+        Ok(key) => Ok(key),
+        Err(_) => {
+            let mut key = vec![0; 32];
+            rand::thread_rng().fill_bytes(&mut key);
+            db.set_raw_at(TREE, &key, Uuid::nil())?;
+            Ok(key)
+        }
+    }
+}
+
+fn mac(payload: &str, db: &Database) -> Result<Hmac<Sha256>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key(db)?)
+        .map_err(|e| ErrorKind::Other(format!("invalid tracking key: {e}")))?;
+    mac.update(payload.as_bytes());
+    Ok(mac)
+}
+
+fn payload(kind: EventKind, campaign: Uuid, subscriber: Uuid, url: Option<&str>) -> String {
+    format!("{kind}:{campaign}:{subscriber}:{}", url.unwrap_or_default())
+}
+
+fn sign(payload: &str, db: &Database) -> Result<String> {
+    let signature = mac(payload, db)?.finalize().into_bytes();
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(signature))
+}
+
+/// Verifies the signature of a tracking link.
+pub fn verify(
+    kind: EventKind,
+    campaign: Uuid,
+    subscriber: Uuid,
+    url: Option<&str>,
+    signature: &str,
+    db: &Database,
+) -> bool {
+    let Ok(signature) = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(signature) else {
+        return false;
+    };
+    mac(&payload(kind, campaign, subscriber, url), db)
+        .is_ok_and(|mac| mac.verify_slice(&signature).is_ok())
+}
+
+pub fn open_link(
+    campaign: Uuid,
+    subscriber: Uuid,
+    config: &Config,
+    db: &Database,
+) -> Result<String> {
+    let signature = sign(&payload(EventKind::Open, campaign, subscriber, None), db)?;
+    Ok(super::link(
+        config,
+        &format!("/mailing/open/{campaign}/{subscriber}?sig={signature}"),
+    ))
+}
+
+pub fn click_link(
+    campaign: Uuid,
+    subscriber: Uuid,
+    url: &str,
+    config: &Config,
+    db: &Database,
+) -> Result<String> {
+    let signature = sign(
+        &payload(EventKind::Click, campaign, subscriber, Some(url)),
+        db,
+    )?;
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("url", url)
+        .append_pair("sig", &signature)
+        .finish();
+    Ok(super::link(
+        config,
+        &format!("/mailing/click/{campaign}/{subscriber}?{query}"),
+    ))
+}
+
+/// Rewrites http links in the html content to go through the click tracking
+/// endpoint and appends the open tracking pixel. Links listed in `skip`,
+/// e.g. the unsubscribe link, are left untouched.
+pub fn track(
+    html: &str,
+    campaign: Uuid,
+    subscriber: Uuid,
+    skip: &[&str],
+    config: &Config,
+    db: &Database,
+) -> Result<String> {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find("href=") {
+        let (before, after) = rest.split_at(start + "href=".len());
+        out.push_str(before);
+
+        let Some(quote) = after.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            rest = after;
+            continue;
+        };
+        let Some(len) = after[1..].find(quote) else {
+            rest = after;
+            continue;
+        };
+        let href = &after[1..1 + len];
+        // attribute values are html-escaped
+        let url = href.replace("&amp;", "&");
+        if (url.starts_with("https://") || url.starts_with("http://"))
+            && !skip.contains(&url.as_str())
+        {
+            let link = click_link(campaign, subscriber, &url, config, db)?;
+            out.push(quote);
+            out.push_str(&link.replace('&', "&amp;"));
+            out.push(quote);
+        } else {
+            out.push_str(&after[..len + 2]);
+        }
+        rest = &after[len + 2..];
+    }
+    out.push_str(rest);
+
+    out.push_str(&format!(
+        r#"<img src="{}" width="1" height="1" alt="" border="0">"#,
+        open_link(campaign, subscriber, config, db)?.replace('&', "&amp;")
+    ));
+    Ok(out)
+}
+
+/// Stores the event, unless tracking was disabled for the subscriber's lists
+/// since the campaign was sent.
+pub fn record(
+    kind: EventKind,
+    campaign: Uuid,
+    subscriber: Uuid,
+    url: Option<String>,
+    config: &Config,
+    db: &Database,
+) -> Result<()> {
+    let campaign = db.get::<Campaign>(campaign)?;
+    let subscriber = db.get::<Subscriber>(subscriber)?;
+    if !is_tracked(&campaign, &subscriber, config) {
+        return Ok(());
+    }
+
+    db.set(&TrackingEvent {
+        id: Uuid::new_v4(),
+        campaign: campaign.id,
+        subscriber: subscriber.id,
+        kind,
+        url,
+        created_at: Utc::now(),
+    })
+}
+
+/// Removes all events recorded for the subscriber.
+pub fn forget(subscriber: Uuid, db: &Database) -> Result<()> {
+    for event in db.get_collection::<TrackingEvent>()? {
+        if event.subscriber == subscriber {
+            db.remove(&event)?;
+        }
+    }
+    Ok(())
+}
+
+/// Aggregate tracking results of a single campaign.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Stats {
+    pub campaign: Uuid,
+    pub subject: String,
+    pub sent: usize,
+    pub opens: usize,
+    pub unique_opens: usize,
+    pub clicks: usize,
+    pub unique_clicks: usize,
+    /// Number of clicks per link.
+    pub links: BTreeMap<String, usize>,
+}
+
+pub fn stats(campaign: &Campaign, db: &Database) -> Result<Stats> {
+    let mut stats = Stats {
+        campaign: campaign.id,
+        subject: campaign.subject.clone(),
+        sent: campaign.sent.len(),
+        ..Default::default()
+    };
+
+    let mut opened = HashSet::new();
+    let mut clicked = HashSet::new();
+    for event in db.get_collection::<TrackingEvent>()? {
+        if event.campaign != campaign.id {
+            continue;
+        }
+        match event.kind {
+            EventKind::Open => {
+                stats.opens += 1;
+                opened.insert(event.subscriber);
+            }
+            EventKind::Click => {
+                stats.clicks += 1;
+                clicked.insert(event.subscriber);
+                *stats
+                    .links
+                    .entry(event.url.unwrap_or_default())
+                    .or_default() += 1;
+            }
+        }
+    }
+    stats.unique_opens = opened.len();
+    stats.unique_clicks = clicked.len();
+
+    Ok(stats)
+}
@@ -0,0 +1,191 @@
+//! Email delivery backends.
+//!
+//! The transport is selected with `config::Email::transport`. Besides
+//! regular SMTP, messages can be written to disk, piped to a local
+//! `sendmail` binary or kept in memory, so that email flows can be used in
+//! development and tests without a mail server.
+
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use lettre::address::Envelope;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSendmailTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use uuid::Uuid;
+
+use crate::config::{self, MailTransport};
+use crate::{Error, ErrorKind, Result};
+
+/// Delivers email messages.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    /// Delivers already formatted message, as stored in the outbound queue.
+    async fn send_raw(&self, envelope: &Envelope, raw: &[u8]) -> Result<()>;
+
+    async fn send(&self, message: Message) -> Result<()> {
+        self.send_raw(message.envelope(), &message.formatted())
+            .await
+    }
+}
+
+/// Creates the mailer selected in config.
+pub fn mailer(config: &config::Email) -> Result<Box<dyn Mailer>> {
+    let mailer: Box<dyn Mailer> = match &config.transport {
+        MailTransport::Smtp => {
+            let creds = Credentials::new(config.smtp_user.clone(), config.smtp_password.clone());
+            Box::new(SmtpMailer(
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_server)
+                    .map_err(|e| Error::new(ErrorKind::Other(e.to_string())))?
+                    .port(config.smtp_port)
+                    .credentials(creds)
+                    .build(),
+            ))
+        }
+        MailTransport::SmtpPlain => Box::new(SmtpMailer(
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.smtp_server)
+                .port(config.smtp_port)
+                .build(),
+        )),
+        MailTransport::File { path, maildir } => Box::new(FileMailer {
+            path: PathBuf::from(path),
+            maildir: *maildir,
+        }),
+        MailTransport::Sendmail { command } => Box::new(SendmailMailer(match command {
+            Some(command) => AsyncSendmailTransport::new_with_command(command),
+            None => AsyncSendmailTransport::new(),
+        })),
+        MailTransport::Memory => Box::new(memory::MemoryMailer),
+    };
+    Ok(mailer)
+}
+
+pub struct SmtpMailer(pub AsyncSmtpTransport<Tokio1Executor>);
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send_raw(&self, envelope: &Envelope, raw: &[u8]) -> Result<()> {
+        let response = self.0.send_raw(envelope, raw).await?;
+        if response.is_positive() {
+            Ok(())
+        } else {
+            Err(ErrorKind::EmailBadResponse(response.code().to_string()).into())
+        }
+    }
+}
+
+pub struct SendmailMailer(pub AsyncSendmailTransport<Tokio1Executor>);
+
+#[async_trait]
+impl Mailer for SendmailMailer {
+    async fn send_raw(&self, envelope: &Envelope, raw: &[u8]) -> Result<()> {
+        self.0
+            .send_raw(envelope, raw)
+            .await
+            .map_err(|e| ErrorKind::Other(format!("sendmail: {e}")))?;
+        Ok(())
+    }
+}
+
+/// Writes each message into a separate `.eml` file.
+///
+/// With `maildir` set, messages are first written into `tmp` and then moved
+/// into `new`, as expected by maildir readers.
+pub struct FileMailer {
+    pub path: PathBuf,
+    pub maildir: bool,
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send_raw(&self, _: &Envelope, raw: &[u8]) -> Result<()> {
+        let name = format!("{}.eml", Uuid::new_v4());
+        let bytes = raw;
+
+        if self.maildir {
+            for dir in ["tmp", "new", "cur"] {
+                std::fs::create_dir_all(self.path.join(dir))?;
+            }
+            let tmp = self.path.join("tmp").join(&name);
+            std::fs::write(&tmp, bytes)?;
+            std::fs::rename(tmp, self.path.join("new").join(name))?;
+        } else {
+            std::fs::create_dir_all(&self.path)?;
+            std::fs::write(self.path.join(name), bytes)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// In-memory transport capturing sent messages.
+///
+/// Captured messages are shared across the whole process, integration tests
+/// can inspect them with `sent` and reset them with `clear`. Messages sent
+/// through the outbound queue only show up once delivered, either by the
+/// queue worker or with `queue::flush`.
+pub mod memory {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use lettre::address::Envelope;
+
+    use super::Mailer;
+    use crate::Result;
+
+    static SENT: Mutex<Vec<SentEmail>> = Mutex::new(Vec::new());
+
+    /// Message captured by the in-memory transport.
+    #[derive(Clone, Debug)]
+    pub struct SentEmail {
+        pub to: Vec<String>,
+        pub subject: String,
+        /// Whole message as it would be sent over the wire.
+        pub raw: String,
+    }
+
+    impl SentEmail {
+        /// Checks whether the message contains the text anywhere, e.g.
+        /// a confirmation link.
+        pub fn contains(&self, text: &str) -> bool {
+            self.raw.contains(text)
+        }
+    }
+
+    pub struct MemoryMailer;
+
+    #[async_trait]
+    impl Mailer for MemoryMailer {
+        async fn send_raw(&self, envelope: &Envelope, raw: &[u8]) -> Result<()> {
+            let raw = String::from_utf8_lossy(raw).to_string();
+            let email = SentEmail {
+                to: envelope.to().iter().map(|a| a.to_string()).collect(),
+                subject: raw
+                    .lines()
+                    .take_while(|line| !line.is_empty())
+                    .find_map(|line| line.strip_prefix("Subject: "))
+                    .unwrap_or_default()
+                    .to_string(),
+                raw,
+            };
+            SENT.lock().unwrap_or_else(|e| e.into_inner()).push(email);
+            Ok(())
+        }
+    }
+
+    /// Returns all messages captured so far.
+    pub fn sent() -> Vec<SentEmail> {
+        SENT.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Returns messages sent to the address.
+    pub fn sent_to(address: &str) -> Vec<SentEmail> {
+        sent()
+            .into_iter()
+            .filter(|e| e.to.iter().any(|to| to == address))
+            .collect()
+    }
+
+    pub fn clear() {
+        SENT.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
@@ -92,6 +92,8 @@ pub enum ErrorKind {
     EmailFailedSend(String),
     #[error("failed sending email through smtp: {0}")]
     EmailBadResponse(String),
+    #[error("email address is on the suppression list: {0}")]
+    EmailSuppressed(String),
     #[error("other error: {0}")]
     Other(String),
     #[error("msg: {0}")]
@@ -111,6 +113,9 @@ pub enum ErrorKind {
     PasswordNotSet,
     #[error("account disabled")]
     AccountDisabled,
+    /// Contains the number of seconds until the lockout expires.
+    #[error("too many failed login attempts, retry after {0} seconds")]
+    TooManyLoginAttempts(i64),
     /// Happens on unauthenticated user trying to access dash routes.
     /// Gets turned into a response redirecting to home page.
     #[error("failed getting token cookie")]
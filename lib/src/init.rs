@@ -45,6 +45,7 @@ pub fn users(config: &Config, db: &Database) -> Result<()> {
         {
             // TODO: implement merging strategy
             existing_user.is_admin = user.is_admin;
+            existing_user.roles = user.roles;
 
             db.set(&existing_user)?;
         } else {
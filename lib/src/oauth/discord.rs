@@ -1,14 +1,14 @@
 use http::header::USER_AGENT;
-use oauth2::reqwest::async_http_client;
-use oauth2::{AuthorizationCode, TokenResponse};
+use oauth2::{PkceCodeVerifier, TokenResponse};
 
 use crate::Result;
 use crate::{Config, Database};
 
-use super::UserInfo;
+use super::{Provider, UserInfo};
 
 pub const AUTH_URL: &str = "https://discordapp.com/api/oauth2/authorize";
 pub const TOKEN_URL: &str = "https://discordapp.com/api/oauth2/token";
+pub const SCOPES: &[&str] = &["identify", "email"];
 
 /// User information to be retrieved from the Discord API.
 ///
@@ -31,7 +31,9 @@ struct DiscordUserInfo {
 impl Into<UserInfo> for DiscordUserInfo {
     fn into(self) -> UserInfo {
         UserInfo {
+            subject: Some(self.id.clone()),
             email: self.email,
+            email_verified: self.verified,
             // With discord we never get reliable name information
             full_name: None,
             handle: Some(self.username),
@@ -49,22 +51,12 @@ impl Into<UserInfo> for DiscordUserInfo {
 /// Includes additional calls to target service to get user information.
 pub async fn get_user_info<'c>(
     auth_code: String,
+    pkce_verifier: PkceCodeVerifier,
     config: &Config,
     db: &Database,
 ) -> Result<UserInfo> {
     // Get an auth token
-    let client = crate::oauth::client(
-        &config.oauth.facebook,
-        config.domain.clone(),
-        "discord".to_string(),
-        AUTH_URL.to_string(),
-        TOKEN_URL.to_string(),
-    )?;
-    let token = client
-        .exchange_code(AuthorizationCode::new(auth_code.clone()))
-        .request_async(async_http_client)
-        .await
-        .unwrap();
+    let token = super::exchange_code(&Provider::Discord, auth_code, pkce_verifier, config).await?;
 
     // Fetch user data
     let client = reqwest::Client::new();
@@ -1,13 +1,13 @@
-use oauth2::reqwest::async_http_client;
-use oauth2::{AuthorizationCode, TokenResponse};
+use oauth2::{PkceCodeVerifier, TokenResponse};
 
 use crate::Result;
 use crate::{Config, Database};
 
-use super::UserInfo;
+use super::{Provider, UserInfo};
 
 pub const AUTH_URL: &str = "https://www.facebook.com/v3.1/dialog/oauth";
 pub const TOKEN_URL: &str = "https://graph.facebook.com/v3.1/oauth/access_token";
+pub const SCOPES: &[&str] = &["email", "public_profile"];
 
 #[derive(Clone, Debug, Default, Deserialize)]
 #[serde(default)]
@@ -27,6 +27,7 @@ struct FacebookPictureData {
 #[derive(Clone, Debug, Default, Deserialize)]
 #[serde(default)]
 struct FacebookUserInfo {
+    id: String,
     email: String,
     name: Option<String>,
     picture: Option<FacebookPicture>,
@@ -35,7 +36,10 @@ struct FacebookUserInfo {
 impl Into<UserInfo> for FacebookUserInfo {
     fn into(self) -> UserInfo {
         UserInfo {
+            subject: Some(self.id),
             email: self.email,
+            // facebook only returns confirmed addresses
+            email_verified: true,
             full_name: self.name,
             // location: self.location,
             avatar_url: self.picture.map(|p| p.data.url),
@@ -49,22 +53,12 @@ impl Into<UserInfo> for FacebookUserInfo {
 /// Includes additional calls to target service to get user information.
 pub async fn get_user_info<'c>(
     auth_code: String,
+    pkce_verifier: PkceCodeVerifier,
     config: &Config,
     db: &Database,
 ) -> Result<UserInfo> {
     // Get an auth token
-    let client = crate::oauth::client(
-        &config.oauth.facebook,
-        config.domain.clone(),
-        "facebook".to_string(),
-        AUTH_URL.to_string(),
-        TOKEN_URL.to_string(),
-    )?;
-    let token = client
-        .exchange_code(AuthorizationCode::new(auth_code.clone()))
-        .request_async(async_http_client)
-        .await
-        .unwrap();
+    let token = super::exchange_code(&Provider::Facebook, auth_code, pkce_verifier, config).await?;
 
     // Q: do we need to set MIME?
 
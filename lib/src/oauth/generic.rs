@@ -0,0 +1,192 @@
+//! Generic OAuth2/OIDC provider, configured entirely through
+//! `config::Oauth::providers`.
+//!
+//! Works with any OIDC-compliant identity provider, e.g. Keycloak,
+//! Authentik or Microsoft Entra. Providers that aren't OIDC-compliant can
+//! still be used as long as they expose an endpoint returning user
+//! information as json, in which case endpoints and claims mapping need to
+//! be provided explicitly.
+
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+use http::header::ACCEPT;
+use oauth2::{PkceCodeVerifier, TokenResponse};
+use serde_json::Value;
+
+use crate::config::OauthProvider;
+use crate::{Config, ErrorKind, Result};
+
+use super::{Endpoints, Provider, UserInfo};
+
+const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+
+/// Relevant subset of the OIDC discovery document.
+#[derive(Clone, Debug, Deserialize)]
+struct Discovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+}
+
+/// Discovery documents are fetched once per discovery url and kept for the
+/// lifetime of the application.
+fn discovery_cache() -> &'static RwLock<HashMap<String, Discovery>> {
+    static CACHE: OnceLock<RwLock<HashMap<String, Discovery>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+async fn discover(url: &str) -> Result<Discovery> {
+    let url = if url.contains("/.well-known/") {
+        url.to_string()
+    } else {
+        format!("{}{DISCOVERY_PATH}", url.trim_end_matches('/'))
+    };
+
+    if let Some(discovery) = discovery_cache()
+        .read()
+        .ok()
+        .and_then(|cache| cache.get(&url).cloned())
+    {
+        return Ok(discovery);
+    }
+
+    let discovery = reqwest::Client::new()
+        .get(&url)
+        .header(ACCEPT, mime::APPLICATION_JSON.essence_str())
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Discovery>()
+        .await?;
+
+    if let Ok(mut cache) = discovery_cache().write() {
+        cache.insert(url, discovery.clone());
+    }
+
+    Ok(discovery)
+}
+
+/// Resolves provider endpoints. Explicitly configured endpoints take
+/// precedence over discovered ones.
+pub async fn endpoints(provider: &OauthProvider) -> Result<Endpoints> {
+    let discovery = match &provider.discovery_url {
+        Some(url) => Some(discover(url).await?),
+        None => None,
+    };
+
+    let missing = |endpoint: &str| {
+        ErrorKind::Other(format!(
+            "oauth provider {} is missing {endpoint} endpoint, provide it explicitly or set discovery_url",
+            provider.name
+        ))
+    };
+
+    Ok(Endpoints {
+        auth_url: provider
+            .auth_url
+            .clone()
+            .or(discovery.as_ref().map(|d| d.authorization_endpoint.clone()))
+            .ok_or(missing("authorization"))?,
+        token_url: provider
+            .token_url
+            .clone()
+            .or(discovery.as_ref().map(|d| d.token_endpoint.clone()))
+            .ok_or(missing("token"))?,
+        userinfo_url: provider
+            .userinfo_url
+            .clone()
+            .or(discovery.and_then(|d| d.userinfo_endpoint)),
+    })
+}
+
+/// Constructs common user info using auth code sent by the service provider.
+///
+/// Includes an additional call to the provider userinfo endpoint.
+pub async fn get_user_info(
+    name: &str,
+    auth_code: String,
+    pkce_verifier: PkceCodeVerifier,
+    config: &Config,
+) -> Result<UserInfo> {
+    let provider = config
+        .oauth
+        .provider(name)
+        .ok_or(ErrorKind::AuthFailed(format!(
+            "unknown oauth provider: {name}"
+        )))?;
+    let custom = Provider::Custom(name.to_string());
+
+    // Get an auth token
+    let token = super::exchange_code(&custom, auth_code, pkce_verifier, config).await?;
+
+    // Fetch user data
+    let userinfo_url = custom
+        .endpoints(config)
+        .await?
+        .userinfo_url
+        .ok_or(ErrorKind::Other(format!(
+            "oauth provider {name} is missing userinfo endpoint"
+        )))?;
+    let claims = reqwest::Client::new()
+        .get(&userinfo_url)
+        .bearer_auth(token.access_token().secret())
+        .header(ACCEPT, mime::APPLICATION_JSON.essence_str())
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Value>()
+        .await?;
+
+    map_claims(provider, &claims)
+}
+
+/// Maps userinfo claims to user info based on provider configuration.
+pub fn map_claims(provider: &OauthProvider, claims: &Value) -> Result<UserInfo> {
+    let mapping = &provider.claims;
+
+    let email = claim(claims, &mapping.email).ok_or(ErrorKind::AuthFailed(format!(
+        "oauth provider {} did not provide user email",
+        provider.name
+    )))?;
+
+    // a missing claim is as good as an unverified email, unless the provider
+    // is explicitly trusted to only hand out verified addresses
+    let email_verified =
+        provider.trust_email || claim(claims, &mapping.email_verified).is_some_and(|v| v == "true");
+    if !email_verified {
+        return Err(ErrorKind::AuthFailed(format!(
+            "oauth provider {} did not verify email: {email}",
+            provider.name
+        ))
+        .into());
+    }
+
+    Ok(UserInfo {
+        subject: claim(claims, &mapping.subject),
+        email,
+        email_verified,
+        full_name: claim(claims, &mapping.name),
+        handle: claim(claims, &mapping.handle),
+        location: claim(claims, &mapping.location),
+        avatar_url: claim(claims, &mapping.avatar),
+    })
+}
+
+/// Reads a claim as string. Nested claims can be addressed using dots,
+/// e.g. `address.locality`.
+fn claim(claims: &Value, name: &str) -> Option<String> {
+    if name.is_empty() {
+        return None;
+    }
+
+    let value = name
+        .split('.')
+        .try_fold(claims, |value, key| value.get(key))?;
+    match value {
+        Value::String(s) if !s.is_empty() => Some(s.to_owned()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
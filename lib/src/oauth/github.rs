@@ -5,12 +5,7 @@ use std::sync::Arc;
 use cookie::Cookie;
 use http::header::{ACCEPT, USER_AGENT};
 use mime::Mime;
-use oauth2::basic::BasicClient;
-use oauth2::reqwest::async_http_client;
-use oauth2::{
-    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, Scope,
-    TokenResponse, TokenUrl,
-};
+use oauth2::{PkceCodeVerifier, TokenResponse};
 use tracing::debug;
 
 use serde::Deserialize;
@@ -24,15 +19,17 @@ use crate::error::Result;
 use crate::user::User;
 use crate::Config;
 
-use super::UserInfo;
+use super::{Provider, UserInfo};
 
 pub const AUTH_URL: &str = "https://github.com/login/oauth/authorize";
 pub const TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+pub const SCOPES: &[&str] = &["user:read"];
 
 /// User information to be retrieved from the GitHub API.
 #[derive(Clone, Debug, Default, Deserialize)]
 #[serde(default)]
 pub struct GitHubUserInfo {
+    id: u64,
     login: String,
     email: Option<String>,
     name: Option<String>,
@@ -43,8 +40,11 @@ pub struct GitHubUserInfo {
 impl Into<UserInfo> for GitHubUserInfo {
     fn into(self) -> UserInfo {
         UserInfo {
+            subject: Some(self.id.to_string()),
             // TODO: don't unwrap like this
             email: self.email.unwrap(),
+            // only verified addresses can be made public on github
+            email_verified: true,
             handle: Some(self.login),
             full_name: self.name,
             location: self.location,
@@ -59,22 +59,12 @@ impl Into<UserInfo> for GitHubUserInfo {
 /// Includes additional calls to target service to get user information.
 pub async fn get_user_info<'c>(
     auth_code: String,
+    pkce_verifier: PkceCodeVerifier,
     config: &Config,
     db: &Database,
 ) -> Result<UserInfo> {
     // Get an auth token
-    let client = crate::oauth::client(
-        &config.oauth.github,
-        config.domain.clone(),
-        "github".to_string(),
-        AUTH_URL.to_string(),
-        TOKEN_URL.to_string(),
-    )?;
-    let token = client
-        .exchange_code(AuthorizationCode::new(auth_code.clone()))
-        .request_async(async_http_client)
-        .await
-        .map_err(|e| crate::ErrorKind::AuthFailed(e.to_string()))?;
+    let token = super::exchange_code(&Provider::Github, auth_code, pkce_verifier, config).await?;
 
     let mime: Mime = "application/vnd.github.v3+json"
         .parse()
@@ -1,13 +1,16 @@
-use oauth2::reqwest::async_http_client;
-use oauth2::{AuthorizationCode, TokenResponse};
+use oauth2::{PkceCodeVerifier, TokenResponse};
 
 use crate::Result;
 use crate::{Config, Database};
 
-use super::UserInfo;
+use super::{Provider, UserInfo};
 
 pub const AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
 pub const TOKEN_URL: &str = "https://www.googleapis.com/oauth2/v4/token";
+pub const SCOPES: &[&str] = &[
+    "https://www.googleapis.com/auth/userinfo.email",
+    "https://www.googleapis.com/auth/userinfo.profile",
+];
 
 /// User information to be retrieved from the Google API.
 ///
@@ -17,6 +20,8 @@ pub const TOKEN_URL: &str = "https://www.googleapis.com/oauth2/v4/token";
 #[derive(Clone, Debug, Default, Deserialize)]
 #[serde(default, rename_all = "camelCase")]
 struct GoogleUserInfo {
+    /// Stable person identifier in the form of `people/{id}`.
+    resource_name: String,
     names: Vec<GoogleName>,
     email_addresses: Vec<GoogleEmail>,
     photos: Vec<GooglePhoto>,
@@ -25,7 +30,9 @@ struct GoogleUserInfo {
 impl Into<UserInfo> for GoogleUserInfo {
     fn into(self) -> UserInfo {
         UserInfo {
+            subject: Some(self.resource_name),
             email: self.email_addresses.first().unwrap().value.clone(),
+            email_verified: self.email_addresses.first().unwrap().metadata.verified,
             full_name: Some(format!(
                 "{} {}",
                 self.names.first().unwrap().given_name,
@@ -74,22 +81,12 @@ struct GooglePhoto {
 /// Includes additional calls to target service to get user information.
 pub async fn get_user_info<'c>(
     auth_code: String,
+    pkce_verifier: PkceCodeVerifier,
     config: &Config,
     db: &Database,
 ) -> Result<UserInfo> {
     // Get an auth token
-    let client = crate::oauth::client(
-        &config.oauth.google,
-        config.domain.clone(),
-        "google".to_string(),
-        AUTH_URL.to_string(),
-        TOKEN_URL.to_string(),
-    )?;
-    let token = client
-        .exchange_code(AuthorizationCode::new(auth_code.clone()))
-        .request_async(async_http_client)
-        .await
-        .unwrap();
+    let token = super::exchange_code(&Provider::Google, auth_code, pkce_verifier, config).await?;
 
     // Q: do we need to set MIME?
 
@@ -1,22 +1,29 @@
 pub mod discord;
 pub mod facebook;
+pub mod generic;
 pub mod github;
 pub mod google;
 
+use std::collections::BTreeMap;
 use std::env;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use std::sync::Arc;
 
-use axum::http::Uri;
-use axum::routing::{get, post};
-use axum::Router;
 use cookie::Cookie;
-use oauth2::basic::BasicClient;
+use oauth2::basic::{BasicClient, BasicTokenResponse};
+use oauth2::reqwest::async_http_client;
 use oauth2::url::Url;
-use oauth2::{AuthUrl, ClientId, ClientSecret, RedirectUrl, TokenUrl};
+use oauth2::{
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, Scope, TokenUrl,
+};
 
 use crate::auth::login::log_in_user_id;
-use crate::{config, user, User};
-use crate::{Config, ErrorKind, Result};
+use crate::config::SequenceTrigger;
+use crate::email::sequence;
+use crate::{config, user, util, User};
+use crate::{Config, Error, ErrorKind, Result};
 use crate::{Database, UserId};
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -25,63 +32,380 @@ pub struct Links {
     pub google: Option<Link>,
     pub discord: Option<Link>,
     pub facebook: Option<Link>,
+    /// Links to generic providers, keyed by provider name.
+    #[serde(default)]
+    pub custom: BTreeMap<String, Link>,
+}
+
+impl Links {
+    pub fn get(&self, provider: &Provider) -> Option<&Link> {
+        match provider {
+            Provider::Github => self.github.as_ref(),
+            Provider::Google => self.google.as_ref(),
+            Provider::Discord => self.discord.as_ref(),
+            Provider::Facebook => self.facebook.as_ref(),
+            Provider::Custom(name) => self.custom.get(name),
+        }
+    }
+
+    pub fn set(&mut self, provider: &Provider, link: Link) {
+        match provider {
+            Provider::Github => self.github = Some(link),
+            Provider::Google => self.google = Some(link),
+            Provider::Discord => self.discord = Some(link),
+            Provider::Facebook => self.facebook = Some(link),
+            Provider::Custom(name) => {
+                self.custom.insert(name.to_owned(), link);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, provider: &Provider) -> Option<Link> {
+        match provider {
+            Provider::Github => self.github.take(),
+            Provider::Google => self.google.take(),
+            Provider::Discord => self.discord.take(),
+            Provider::Facebook => self.facebook.take(),
+            Provider::Custom(name) => self.custom.remove(name),
+        }
+    }
+
+    /// Returns the number of linked accounts.
+    pub fn len(&self) -> usize {
+        [&self.github, &self.google, &self.discord, &self.facebook]
+            .iter()
+            .filter(|link| link.is_some())
+            .count()
+            + self.custom.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Link {
+    /// Stable identifier of the user with the provider. Unlike email or
+    /// handle it doesn't change over time. Empty for links created before
+    /// subject ids were stored.
+    #[serde(default)]
+    pub subject: String,
     pub email: String,
     pub handle: String,
 }
 
+impl Link {
+    pub fn new(user_info: &UserInfo) -> Self {
+        Self {
+            subject: user_info.subject.clone().unwrap_or_default(),
+            email: user_info.email.clone(),
+            handle: user_info.handle.clone().unwrap_or(user_info.email.clone()),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum Provider {
     Github,
     Google,
     Discord,
     Facebook,
+    /// Generic provider defined in `config::Oauth::providers`.
+    Custom(String),
+}
+
+/// Built-in provider names take precedence, any other name is assumed to
+/// refer to a generic provider.
+impl FromStr for Provider {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "" => return Err(ErrorKind::BadInput("empty oauth provider name".to_string()).into()),
+            "github" => Provider::Github,
+            "google" => Provider::Google,
+            "discord" => Provider::Discord,
+            "facebook" => Provider::Facebook,
+            name => Provider::Custom(name.to_string()),
+        })
+    }
+}
+
+impl Display for Provider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Provider::Github => write!(f, "github"),
+            Provider::Google => write!(f, "google"),
+            Provider::Discord => write!(f, "discord"),
+            Provider::Facebook => write!(f, "facebook"),
+            Provider::Custom(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Provider service endpoints.
+#[derive(Clone, Debug)]
+pub struct Endpoints {
+    pub auth_url: String,
+    pub token_url: String,
+    pub userinfo_url: Option<String>,
+}
+
+impl Provider {
+    /// Returns client id and secret, as long as the provider is enabled.
+    pub fn credentials<'c>(&self, config: &'c Config) -> Result<(&'c str, &'c str)> {
+        let not_enabled = || {
+            Error::from(ErrorKind::AuthFailed(format!(
+                "oauth provider not enabled: {self}"
+            )))
+        };
+        if !config.oauth.enabled {
+            return Err(not_enabled());
+        }
+
+        let entry = match self {
+            Provider::Github => &config.oauth.github,
+            Provider::Google => &config.oauth.google,
+            Provider::Discord => &config.oauth.discord,
+            Provider::Facebook => &config.oauth.facebook,
+            Provider::Custom(name) => {
+                let provider =
+                    config
+                        .oauth
+                        .provider(name)
+                        .ok_or(ErrorKind::AuthFailed(format!(
+                            "unknown oauth provider: {name}"
+                        )))?;
+                if !provider.enabled {
+                    return Err(not_enabled());
+                }
+                return Ok((&provider.client_id, &provider.client_secret));
+            }
+        };
+        if !entry.enabled {
+            return Err(not_enabled());
+        }
+        Ok((&entry.client_id, &entry.client_secret))
+    }
+
+    pub async fn endpoints(&self, config: &Config) -> Result<Endpoints> {
+        let (auth_url, token_url) = match self {
+            Provider::Github => (github::AUTH_URL, github::TOKEN_URL),
+            Provider::Google => (google::AUTH_URL, google::TOKEN_URL),
+            Provider::Discord => (discord::AUTH_URL, discord::TOKEN_URL),
+            Provider::Facebook => (facebook::AUTH_URL, facebook::TOKEN_URL),
+            Provider::Custom(name) => {
+                let provider =
+                    config
+                        .oauth
+                        .provider(name)
+                        .ok_or(ErrorKind::AuthFailed(format!(
+                            "unknown oauth provider: {name}"
+                        )))?;
+                return generic::endpoints(provider).await;
+            }
+        };
+        Ok(Endpoints {
+            auth_url: auth_url.to_string(),
+            token_url: token_url.to_string(),
+            userinfo_url: None,
+        })
+    }
+
+    /// Scopes requested from the provider during authorization.
+    pub fn scopes(&self, config: &Config) -> Vec<String> {
+        let scopes = match self {
+            Provider::Github => github::SCOPES,
+            Provider::Google => google::SCOPES,
+            Provider::Discord => discord::SCOPES,
+            Provider::Facebook => facebook::SCOPES,
+            Provider::Custom(name) => {
+                return config
+                    .oauth
+                    .provider(name)
+                    .map(|p| p.scopes.clone())
+                    .unwrap_or_default()
+            }
+        };
+        scopes.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// Checks if the provider can be relied on to only hand out addresses
+    /// actually owned by the user, so that identities can be matched to
+    /// existing accounts by email. Generic providers need to be explicitly
+    /// trusted with `config::OauthProvider::trust_email`.
+    pub fn trusts_email(&self, config: &Config) -> bool {
+        match self {
+            Provider::Custom(name) => config.oauth.provider(name).is_some_and(|p| p.trust_email),
+            _ => true,
+        }
+    }
+
+    pub fn redirect_url(&self, config: &Config) -> String {
+        if let Provider::Custom(name) = self {
+            if let Some(url) = config
+                .oauth
+                .provider(name)
+                .and_then(|p| p.redirect_url.clone())
+            {
+                return url;
+            }
+        }
+        format!("https://{}/auth/{self}", config.domain)
+    }
 }
 
-pub fn client(
-    config: &config::OauthEntry,
-    domain: String,
-    provider: String,
-    auth_url: String,
-    token_url: String,
-) -> Result<BasicClient> {
+/// Creates a client for the provider, based on the application config.
+pub async fn client(provider: &Provider, config: &Config) -> Result<BasicClient> {
+    let (client_id, client_secret) = provider.credentials(config)?;
+    let endpoints = provider.endpoints(config).await?;
     let client = BasicClient::new(
-        ClientId::new(config.client_id.to_owned()),
-        Some(ClientSecret::new(config.client_secret.clone())),
-        AuthUrl::new(auth_url)?,
-        Some(TokenUrl::new(token_url)?),
+        ClientId::new(client_id.to_owned()),
+        Some(ClientSecret::new(client_secret.to_owned())),
+        AuthUrl::new(endpoints.auth_url)?,
+        Some(TokenUrl::new(endpoints.token_url)?),
     )
-    .set_redirect_uri(RedirectUrl::new(format!(
-        "https://{domain}/auth/{provider}"
-    ))?);
+    .set_redirect_uri(RedirectUrl::new(provider.redirect_url(config))?);
     Ok(client)
 }
 
+/// Pending authorization state, kept by the client between initiating the
+/// flow and the provider calling back.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuthState {
+    pub provider: Provider,
+    /// Set if the flow was initiated by a logged in user to link another
+    /// identity to their account, as opposed to logging in.
+    pub link: Option<UserId>,
+    pub csrf_token: String,
+    pub pkce_verifier: String,
+}
+
+/// Builds the provider authorization url, along with the state needed to
+/// verify the callback.
+pub async fn authorize(
+    provider: &Provider,
+    link: Option<UserId>,
+    config: &Config,
+) -> Result<(Url, AuthState)> {
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+    let (url, csrf_token) = client(provider, config)
+        .await?
+        .authorize_url(CsrfToken::new_random)
+        .add_scopes(provider.scopes(config).into_iter().map(Scope::new))
+        .set_pkce_challenge(pkce_challenge)
+        .url();
+
+    Ok((
+        url,
+        AuthState {
+            provider: provider.clone(),
+            link,
+            csrf_token: csrf_token.secret().to_owned(),
+            pkce_verifier: pkce_verifier.secret().to_owned(),
+        },
+    ))
+}
+
+/// Verifies the callback against the stored state and retrieves user
+/// information from the provider.
+pub async fn callback(
+    provider: &Provider,
+    code: String,
+    state: &str,
+    auth_state: AuthState,
+    config: &Config,
+    db: &Database,
+) -> Result<UserInfo> {
+    if &auth_state.provider != provider {
+        return Err(ErrorKind::AuthFailed("oauth provider mismatch".to_string()).into());
+    }
+    if auth_state.csrf_token != state {
+        return Err(ErrorKind::AuthFailed("oauth state mismatch".to_string()).into());
+    }
+
+    let verifier = PkceCodeVerifier::new(auth_state.pkce_verifier);
+    match provider {
+        Provider::Github => github::get_user_info(code, verifier, config, db).await,
+        Provider::Google => google::get_user_info(code, verifier, config, db).await,
+        Provider::Discord => discord::get_user_info(code, verifier, config, db).await,
+        Provider::Facebook => facebook::get_user_info(code, verifier, config, db).await,
+        Provider::Custom(name) => generic::get_user_info(name, code, verifier, config).await,
+    }
+}
+
+/// Exchanges the authorization code for an access token.
+pub async fn exchange_code(
+    provider: &Provider,
+    code: String,
+    verifier: PkceCodeVerifier,
+    config: &Config,
+) -> Result<BasicTokenResponse> {
+    client(provider, config)
+        .await?
+        .exchange_code(AuthorizationCode::new(code))
+        .set_pkce_verifier(verifier)
+        .request_async(async_http_client)
+        .await
+        .map_err(|e| ErrorKind::AuthFailed(e.to_string()).into())
+}
+
 /// Set of data points that can be extracted from oauth providers and
 /// integrated into our model.
 #[derive(Clone, Default)]
 pub struct UserInfo {
+    /// Stable identifier of the user with the provider.
+    pub subject: Option<String>,
     pub email: String,
+    /// Whether the provider vouches for the user owning the email.
+    pub email_verified: bool,
     pub full_name: Option<String>,
     pub handle: Option<String>,
     pub location: Option<String>,
     pub avatar_url: Option<String>,
 }
 
+/// Finds the user with the provider identity linked to their account.
+pub fn find_linked_user(provider: &Provider, subject: &str, db: &Database) -> Result<Option<User>> {
+    if subject.is_empty() {
+        return Ok(None);
+    }
+    Ok(db.get_collection::<User>()?.into_iter().find(|user| {
+        user.linked_accounts
+            .get(provider)
+            .is_some_and(|link| link.subject == subject)
+    }))
+}
+
 /// Determines how to proceed after successful oauth procedure.
+///
+/// Users are matched by the provider subject id of their linked identities
+/// first. Matching by email is only a fallback for identities that haven't
+/// been linked yet, and only if the provider verified the email and is
+/// trusted to do so. Otherwise the user has to log in and link the identity
+/// at `/account/link/:provider` themselves.
 pub async fn login_or_register<'c>(
+    provider: &Provider,
     user_info: UserInfo,
     db: &Database,
     config: &Config,
 ) -> Result<(UserId, Cookie<'c>)> {
+    let link = Link::new(&user_info);
+
+    // the identity is already linked to an account
+    if let Some(user) = find_linked_user(provider, &link.subject, db)? {
+        if user.is_disabled {
+            return Err(ErrorKind::AccountDisabled.into());
+        }
+        return Ok((user.id, log_in_user_id(&user.id, false, config, db)?));
+    }
+
     let mut matched_user = None;
 
     // determine if it's a new user logging in, or if we've already seen them
     for user in db.get_collection::<User>()? {
-        if user.email == user_info.email {
+        if util::same_email(&user.email, &user_info.email) {
             // found user with matching email
             // TODO: if the found user has a confirmed email and/or has set
             // a password, perform an additional check
@@ -92,43 +416,148 @@ pub async fn login_or_register<'c>(
 
     // user appears in the db (matching email)
     if let Some(mut user) = matched_user {
+        if !user_info.email_verified || !provider.trusts_email(config) {
+            return Err(ErrorKind::AuthFailed(format!(
+                "an account with this email already exists, log in and link \
+                your {provider} account at /account/link/{provider}"
+            ))
+            .into());
+        }
+
         // user email was not confirmed, we will overwrite that user
         // with a new one based on the oauth provider info
         if !user.email_confirmed {
-            let user = new_user_from_oauth(&db, user_info).await?;
+            let mut user = new_user_from_oauth(&db, user_info).await?;
+            user.linked_accounts.set(provider, link);
             db.set(&user)?;
+            registered(&user, config, db);
 
-            return Ok((user.id, log_in_user_id(&user.id, db)?));
+            return Ok((user.id, log_in_user_id(&user.id, false, config, db)?));
         } else {
             // user is confirmed the owner of the email, it must be the
-            // same person, log in as the existing user
+            // same person, log in as the existing user. That is unless they
+            // already have a different identity with this provider linked.
+            match user.linked_accounts.get(provider) {
+                Some(existing) if !existing.subject.is_empty() => {
+                    return Err(ErrorKind::AuthFailed(format!(
+                        "user already has a different {provider} identity linked"
+                    ))
+                    .into());
+                }
+                _ => user.linked_accounts.set(provider, link),
+            }
 
             // TODO: add any additional information provided by oauth provider
             // to the user account
             if let Some(url) = user_info.avatar_url {
                 user.set_avatar_from_url(db, &url).await?;
-                db.set(&user)?;
             }
+            db.set(&user)?;
 
             // let the user in
             println!("logging in as the existing user: {:?}", user.id);
-            return Ok((user.id, log_in_user_id(&user.id, db)?));
+            return Ok((user.id, log_in_user_id(&user.id, false, config, db)?));
         }
     } else {
         // user email doesn't appear in the db, treat this login as a new user
 
         // return immediately if config dissalows registration in general, or
-        // through oauth specifically
-        if !config.registration.enabled || !config.registration.oauth {
+        // through oauth specifically, invites can only be redeemed through
+        // email signup
+        if !config.registration.enabled
+            || !config.registration.oauth
+            || config.registration.invite_only
+        {
             return Err(ErrorKind::RegistrationClosed(
                 "can't create new user based on valid oauth process".to_string(),
             )
             .into());
         }
 
-        let user = new_user_from_oauth(&db, user_info).await?;
+        let mut user = new_user_from_oauth(&db, user_info).await?;
+        user.linked_accounts.set(provider, link);
         db.set(&user)?;
-        return Ok((user.id, log_in_user_id(&user.id, db)?));
+        registered(&user, config, db);
+        return Ok((user.id, log_in_user_id(&user.id, false, config, db)?));
+    }
+}
+
+/// Links the provider identity to an existing user account.
+pub fn link(
+    user: &mut User,
+    provider: &Provider,
+    user_info: &UserInfo,
+    db: &Database,
+) -> Result<()> {
+    let link = Link::new(user_info);
+    if link.subject.is_empty() {
+        return Err(ErrorKind::AuthFailed(format!(
+            "{provider} provider did not provide subject id"
+        ))
+        .into());
+    }
+
+    if let Some(other) = find_linked_user(provider, &link.subject, db)? {
+        if other.id != user.id {
+            return Err(ErrorKind::BadInput(format!(
+                "this {provider} account is already linked to another user"
+            ))
+            .into());
+        }
+    }
+
+    user.linked_accounts.set(provider, link);
+    db.set(user)?;
+
+    Ok(())
+}
+
+/// Removes the provider identity from the user account.
+///
+/// Fails if that would leave the user without any way of logging in.
+pub fn unlink(user: &mut User, provider: &Provider, config: &Config, db: &Database) -> Result<()> {
+    if user.linked_accounts.get(provider).is_none() {
+        return Err(ErrorKind::BadInput(format!("no {provider} account linked")).into());
+    }
+
+    if login_methods(user, config) <= 1 {
+        return Err(ErrorKind::BadInput(
+            "can't remove the last remaining login method".to_string(),
+        )
+        .into());
+    }
+
+    user.linked_accounts.remove(provider);
+    db.set(user)?;
+
+    Ok(())
+}
+
+/// Counts the ways the user is currently able to log in.
+pub fn login_methods(user: &User, config: &Config) -> usize {
+    let mut methods = user.linked_accounts.len();
+    if config.auth.mode.password() && user.password_hash.is_some() {
+        methods += 1;
+    }
+    if config.auth.mode.magic_link() && user.email_confirmed {
+        methods += 1;
+    }
+    methods
+}
+
+/// Starts email sequences for a user registered through oauth. The email
+/// is considered confirmed if the oauth provider verified it.
+///
+/// The user is already stored at this point, failures are only logged.
+fn registered(user: &User, config: &Config, db: &Database) {
+    let mut events = vec![SequenceTrigger::Registration];
+    if user.email_confirmed {
+        events.push(SequenceTrigger::EmailConfirmed);
+    }
+    for event in events {
+        if let Err(e) = sequence::trigger(event, user, config, db) {
+            log::error!("failed starting sequences for user {}: {e}", user.id);
+        }
     }
 }
 
@@ -136,7 +565,7 @@ pub async fn login_or_register<'c>(
 pub async fn new_user_from_oauth(db: &Database, user_info: UserInfo) -> Result<User> {
     let mut user = User::new(db)?;
     user.email = user_info.email.clone();
-    user.email_confirmed = true;
+    user.email_confirmed = user_info.email_verified;
     user.is_disabled = false;
     user.name = user_info.full_name.unwrap_or("".to_string());
     user.handle = user_info.handle.unwrap_or(user_info.email);
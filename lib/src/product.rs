@@ -47,6 +47,15 @@ pub enum ProductInner {
 }
 
 impl Product {
+    /// Human readable product name, e.g. for receipts.
+    pub fn name(&self) -> String {
+        match &self.inner {
+            ProductInner::Subscription { plan, .. } => format!("{} plan", plan.name),
+            ProductInner::Credits { .. } => "Credits".to_string(),
+            ProductInner::Custom { name, .. } => name.clone(),
+        }
+    }
+
     pub fn amount(&self) -> usize {
         match &self.inner {
             ProductInner::Credits { amount, .. } => *amount,
@@ -0,0 +1,275 @@
+//! Handling of data-subject requests, i.e. exporting and deleting all the
+//! data tied to a user.
+
+use std::io::{Cursor, Write};
+
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use crate::auth::legacy::Format;
+use crate::auth::magic::MagicLinkKey;
+use crate::auth::refresh::RefreshToken;
+use crate::auth::{throttle, ConfirmationKey, ConfirmationPurpose, TokenMeta};
+use crate::db::Collectable;
+use crate::email::list::Subscriber;
+use crate::email::sequence::Enrollment;
+use crate::email::tracking;
+use crate::error::{ErrorKind, Result};
+use crate::image::Image;
+use crate::order::Order;
+use crate::payment::Payment;
+use crate::{util, Comment, Config, Database, User, UserId};
+
+/// All the data tied to a single user.
+#[derive(Clone, Debug, Serialize)]
+pub struct UserData {
+    pub user: User,
+    pub orders: Vec<Order>,
+    pub payments: Vec<Payment>,
+    pub comments: Vec<Comment>,
+    pub subscriptions: Vec<Subscriber>,
+    pub tokens: Vec<TokenMeta>,
+    #[serde(skip)]
+    pub images: Vec<Image>,
+}
+
+/// Gathers all the data tied to the user.
+pub fn collect(user_id: UserId, db: &Database) -> Result<UserData> {
+    let user = db.get::<User>(user_id)?;
+
+    let orders = db
+        .get_collection::<Order>()?
+        .into_iter()
+        .filter(|o| o.user == user_id)
+        .collect::<Vec<_>>();
+    let payments = db
+        .get_collection::<Payment>()?
+        .into_iter()
+        .filter(|p| orders.iter().any(|o| o.id == p.order))
+        .collect();
+
+    // comments are stored in separate trees per parent
+    let mut comments = vec![];
+    for tree in db.trees_for::<Comment>()? {
+        comments.extend(
+            db.get_collection_at::<Comment>(tree)?
+                .into_iter()
+                .filter(|c| c.owner == user_id),
+        );
+    }
+
+    // subscriptions are only matched by address if the user proved they own
+    // it, otherwise anyone could claim someone else's subscriber record
+    let subscriptions = db
+        .get_collection::<Subscriber>()?
+        .into_iter()
+        .filter(|s| {
+            s.user == Some(user_id)
+                || (user.email_confirmed && util::same_email(&s.address, &user.email))
+        })
+        .collect();
+    let tokens = db
+        .get_collection::<TokenMeta>()?
+        .into_iter()
+        .filter(|t| t.user_id == user_id)
+        .collect();
+
+    let images = db.get::<Image>(user.avatar).into_iter().collect();
+
+    Ok(UserData {
+        user,
+        orders,
+        payments,
+        comments,
+        subscriptions,
+        tokens,
+        images,
+    })
+}
+
+/// Bundles all the data tied to the user into a zip archive. Records are
+/// stored as json files, images as separate png files.
+pub fn export(user_id: UserId, db: &Database) -> Result<Vec<u8>> {
+    let data = collect(user_id, db)?;
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default();
+
+    let mut add = |name: &str, bytes: &[u8]| -> Result<()> {
+        zip.start_file(name, options)
+            .map_err(|e| ErrorKind::Other(e.to_string()))?;
+        zip.write_all(bytes)?;
+        Ok(())
+    };
+
+    add("data.json", &serde_json::to_vec_pretty(&data)?)?;
+    for image in &data.images {
+        add(&format!("images/{}.png", image.id), &image.bytes)?;
+    }
+
+    let cursor = zip.finish().map_err(|e| ErrorKind::Other(e.to_string()))?;
+
+    Ok(cursor.into_inner())
+}
+
+/// User record brought over from another system, see `import`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImportedUser {
+    pub email: String,
+    /// Password hash in any of the formats supported by `auth::legacy`.
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub handle: Option<String>,
+    #[serde(default)]
+    pub email_confirmed: bool,
+}
+
+/// Creates a user from the imported record. Password hashes are stored as
+/// they are and get upgraded to argon2 on the first successful login.
+pub fn import(record: ImportedUser, db: &Database) -> Result<User> {
+    if util::find_user_by_email(db, &record.email).is_ok() {
+        return Err(ErrorKind::UserWithEmailAlreadyExists(record.email).into());
+    }
+    if let Some(handle) = record.handle.as_ref().filter(|h| !h.is_empty()) {
+        if util::find_user_by_handle(db, handle).is_ok() {
+            return Err(
+                ErrorKind::BadInput(format!("user with handle {handle} already exists")).into(),
+            );
+        }
+    }
+
+    let password_hash = record.password_hash.filter(|h| !h.is_empty());
+    if let Some(hash) = &password_hash {
+        if Format::detect(hash).is_none() {
+            return Err(ErrorKind::BadInput(format!(
+                "unrecognized password hash format for {}",
+                record.email
+            ))
+            .into());
+        }
+    }
+
+    let mut user = User::new(db)?;
+    user.email = record.email;
+    user.email_confirmed = record.email_confirmed;
+    user.password_hash = password_hash;
+    if let Some(name) = record.name {
+        user.name = name;
+    }
+    if let Some(handle) = record.handle {
+        user.handle = handle;
+    }
+    db.set(&user)?;
+
+    Ok(user)
+}
+
+/// Sends the user a link confirming the deletion request.
+pub fn request_deletion(user: &User, config: &Config, db: &Database) -> Result<()> {
+    let key = ConfirmationKey::with_purpose(user.id, ConfirmationPurpose::AccountDeletion);
+    db.set(&key)?;
+
+    crate::email::account_deletion(user.email.clone(), key.key.to_string(), config, db)
+}
+
+/// Schedules the confirmed deletion according to the configured grace
+/// period. With no grace period the account is deleted right away.
+pub fn schedule_deletion(user_id: UserId, config: &Config, db: &Database) -> Result<()> {
+    if config.account.deletion_grace_period == 0 {
+        return delete(user_id, config, db);
+    }
+
+    let mut user = db.get::<User>(user_id)?;
+    user.deletion_scheduled =
+        Some(Utc::now() + Duration::seconds(config.account.deletion_grace_period as i64));
+    db.set(&user)?;
+
+    Ok(())
+}
+
+pub fn cancel_deletion(user: &mut User, db: &Database) -> Result<()> {
+    if user.deletion_scheduled.take().is_none() {
+        return Err(ErrorKind::BadInput("account deletion not scheduled".to_string()).into());
+    }
+    db.set(user)?;
+    Ok(())
+}
+
+/// Deletes accounts with expired grace period.
+pub fn process_scheduled_deletions(config: &Config, db: &Database) -> Result<()> {
+    for user in db.get_collection::<User>()? {
+        if user.deletion_scheduled.is_some_and(|at| at <= Utc::now()) {
+            log::info!("deleting user {} as scheduled", user.id);
+            delete(user.id, config, db)?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes or anonymises all the data tied to the user.
+///
+/// Orders and payments are kept for accounting purposes, but are no longer
+/// attributed to the user.
+pub fn delete(user_id: UserId, config: &Config, db: &Database) -> Result<()> {
+    let data = collect(user_id, db)?;
+
+    for mut order in data.orders {
+        order.user = Uuid::nil();
+        db.set(&order)?;
+    }
+
+    for tree in db.trees_for::<Comment>()? {
+        for mut comment in db.get_collection_at::<Comment>(&tree)? {
+            if comment.owner != user_id {
+                continue;
+            }
+            if config.account.delete_comments {
+                db.remove_at(&tree, &comment)?;
+            } else {
+                comment.owner = Uuid::nil();
+                db.set_at(&tree, &comment)?;
+            }
+        }
+    }
+
+    for subscriber in data.subscriptions {
+        tracking::forget(subscriber.id, db)?;
+        db.remove(&subscriber)?;
+    }
+    for token in data.tokens {
+        db.remove(&token)?;
+    }
+    for image in data.images {
+        db.remove(&image)?;
+    }
+    for token in db.get_collection::<RefreshToken>()? {
+        if token.user_id == user_id {
+            db.remove(&token)?;
+        }
+    }
+    for key in db.get_collection::<ConfirmationKey>()? {
+        if key.user == user_id {
+            db.remove(&key)?;
+        }
+    }
+    for enrollment in db.get_collection::<Enrollment>()? {
+        if enrollment.user == user_id {
+            db.remove(&enrollment)?;
+        }
+    }
+    for key in db.get_collection::<MagicLinkKey>()? {
+        if data.user.email_confirmed && util::same_email(&key.email, &data.user.email) {
+            db.remove(&key)?;
+        }
+    }
+    throttle::clear(&throttle::account_key(&user_id), db)?;
+
+    db.remove(&data.user)?;
+
+    Ok(())
+}
@@ -1,8 +1,11 @@
+pub mod data;
+pub mod role;
 pub mod subscription;
 
 use fnv::FnvHashSet;
 pub use subscription::Plan;
 
+use std::collections::BTreeSet;
 use std::fmt::{Display, Formatter};
 use std::io::{BufWriter, Cursor};
 use std::str::FromStr;
@@ -58,10 +61,19 @@ pub struct User {
 
     pub registration_date: DateTime<Utc>,
 
+    /// Built-in `admin` role, kept as a separate flag for compatibility.
+    /// See `User::has_role`.
     pub is_admin: bool,
     pub is_disabled: bool,
     pub is_verified: bool,
 
+    /// Roles assigned to the user, as defined in `Config::roles`.
+    pub roles: BTreeSet<String>,
+
+    /// Time at which the account is going to be deleted, if the user
+    /// requested deletion.
+    pub deletion_scheduled: Option<DateTime<Utc>>,
+
     pub email: String,
     pub email_confirmed: bool,
 
@@ -100,6 +112,9 @@ impl Default for User {
             is_disabled: false,
             is_verified: false,
 
+            roles: BTreeSet::new(),
+            deletion_scheduled: None,
+
             email: "foo@bar.com".to_string(),
             email_confirmed: false,
 
@@ -324,6 +339,8 @@ pub enum UserActivityCategory {
     Payment,
     LoginSuccessful,
     LoginUnsuccesful,
+    LoginLockout,
+    Impersonation,
 }
 
 #[derive(
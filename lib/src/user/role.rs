@@ -0,0 +1,84 @@
+//! Roles and permissions.
+//!
+//! Roles are defined in `Config::roles` as named sets of permissions and
+//! assigned to users. Permissions are plain strings defined by the
+//! application, e.g. `comments:moderate`.
+
+use crate::error::{ErrorKind, Result};
+use crate::{Config, User};
+
+/// Built-in role granting all permissions. Backed by the `User::is_admin`
+/// flag.
+pub const ADMIN: &str = "admin";
+
+/// Permission allowing to post comments without rate limiting.
+pub const COMMENTS_UNLIMITED: &str = "comments:unlimited";
+
+/// Checks whether the granted permission pattern covers the required
+/// permission. Patterns ending with `*` match by prefix.
+pub fn matches(pattern: &str, permission: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => permission.starts_with(prefix),
+        None => pattern == permission,
+    }
+}
+
+impl User {
+    pub fn has_role(&self, role: &str) -> bool {
+        if role == ADMIN {
+            self.is_admin
+        } else {
+            self.roles.contains(role)
+        }
+    }
+
+    /// Lists all the roles assigned to the user, including the built-in
+    /// `admin` role.
+    pub fn roles(&self) -> Vec<&str> {
+        let mut roles = Vec::with_capacity(self.roles.len() + 1);
+        if self.is_admin {
+            roles.push(ADMIN);
+        }
+        roles.extend(self.roles.iter().map(String::as_str));
+        roles
+    }
+
+    pub fn has_permission(&self, permission: &str, config: &Config) -> bool {
+        if self.is_admin {
+            return true;
+        }
+
+        self.roles
+            .iter()
+            .filter_map(|role| config.roles.get(role))
+            .flat_map(|role| role.permissions.iter())
+            .any(|pattern| matches(pattern, permission))
+    }
+
+    /// Assigns the role to the user. Only roles defined in config, as well
+    /// as the built-in `admin` role, can be granted.
+    pub fn grant_role(&mut self, role: &str, config: &Config) -> Result<()> {
+        if role == ADMIN {
+            self.is_admin = true;
+        } else if config.roles.contains_key(role) {
+            self.roles.insert(role.to_string());
+        } else {
+            return Err(ErrorKind::BadInput(format!("unknown role: {role}")).into());
+        }
+        Ok(())
+    }
+
+    /// Removes the role from the user. Roles no longer present in config
+    /// can still be revoked.
+    pub fn revoke_role(&mut self, role: &str) -> Result<()> {
+        let had_role = if role == ADMIN {
+            std::mem::replace(&mut self.is_admin, false)
+        } else {
+            self.roles.remove(role)
+        };
+        if !had_role {
+            return Err(ErrorKind::BadInput(format!("user doesn't have role: {role}")).into());
+        }
+        Ok(())
+    }
+}
@@ -58,9 +58,21 @@ pub fn token_expired(db: &Database, token: &TokenMeta) -> bool {
     }
 }
 
+/// Compares secrets in constant time, so that the comparison doesn't leak
+/// how much of the secret was guessed right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Compares email addresses the way they're matched throughout the
+/// application, i.e. ignoring case.
+pub fn same_email(a: &str, b: &str) -> bool {
+    a.trim().eq_ignore_ascii_case(b.trim())
+}
+
 pub fn find_user_by_email(db: &Database, email: &String) -> Result<User> {
     for user in db.get_collection::<User>()? {
-        if &user.email == email {
+        if same_email(&user.email, email) {
             return Ok(user);
         }
     }
@@ -0,0 +1,59 @@
+//! Email signup driven through the router, with messages captured by the
+//! in-memory transport.
+
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use axum::Extension;
+use tower::ServiceExt;
+
+use micron::config::MailTransport;
+use micron::email::{queue, transport::memory};
+use micron::{Config, Database};
+
+#[tokio::test]
+async fn signup_sends_confirmation_email() {
+    let dir = std::env::temp_dir().join(format!("micron-test-signup-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+
+    let mut config = Config {
+        domain: "example.com".to_string(),
+        ..Default::default()
+    };
+    config.email.address = "app@example.com".to_string();
+    config.email.transport = MailTransport::Memory;
+    config.registration.enabled = true;
+    config.registration.email = true;
+
+    let db = Database::open(dir.join("db")).unwrap();
+    let key = cookie::Key::generate();
+    let router = micron::axum::router(micron::axum::Router::new(), &config)
+        .layer(Extension(Arc::new(config.clone())))
+        .layer(Extension(Arc::new(db.clone())))
+        .layer(Extension(key.clone()))
+        .with_state(key);
+
+    let response = router
+        .oneshot(
+            Request::post("/signup")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from(
+                    "email=new%40example.com&password=correct-horse-battery-staple",
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // nothing is delivered until the queue is processed
+    assert!(memory::sent_to("new@example.com").is_empty());
+    assert_eq!(queue::flush(&config, &db).await.unwrap(), 1);
+
+    let sent = memory::sent_to("new@example.com");
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].contains("https://example.com/confirm/"));
+
+    std::fs::remove_dir_all(&dir).ok();
+}